@@ -1,4 +1,14 @@
-const PACKAGES: &[&str] = &["color", "direct_32", "perturbed_32"];
+const PACKAGES: &[&str] = &[
+    "color",
+    "direct_32",
+    "perturbed_32",
+    "direct_64",
+    "perturbed_64",
+    "perturbed_32x2",
+    "density",
+    "lyapunov",
+    "newton",
+];
 
 fn main() {
     println!("cargo::rerun-if-changed=build.rs");
@@ -1,11 +1,10 @@
 use std::path::PathBuf;
-use std::sync::atomic::AtomicBool;
-use std::sync::{Arc, mpsc};
+use std::sync::mpsc;
 use std::thread;
 use std::time::{Duration, Instant};
 
 use clap::Parser;
-use corgi::types::{Debouncer, Image, ImageGenCommand, StatusMessage};
+use corgi::types::{CancelFlags, Debouncer, Image, ImageGenCommand, StatusMessage};
 use wgpu::Extent3d;
 
 use crate::config::Context;
@@ -34,6 +33,17 @@ pub struct CorgiCliOptions {
     /// written into the finished file.
     #[arg(short, long, value_name = "FILE")]
     pub output_file: Option<PathBuf>,
+    /// Render deep zooms with the emulated double precision perturbed shader instead
+    /// of the f32 one, to compare their accuracy and speed. Only used with an output file.
+    #[arg(long)]
+    pub double_single: bool,
+    /// Render the output file on the CPU instead of the GPU. The CPU is also used
+    /// if no GPU is found. Only the escape time mode is supported.
+    #[arg(long)]
+    pub cpu: bool,
+    /// The number of threads of the CPU renderer, or 0 for one per CPU
+    #[arg(long, value_name = "COUNT", default_value_t = 0)]
+    pub threads: usize,
 }
 
 /// The App State management struct
@@ -44,11 +54,14 @@ pub struct CorgiApp {
     last_save_time: Instant,
     command_channel: mpsc::Sender<ImageGenCommand>,
     status_channel: mpsc::Receiver<StatusMessage>,
+    cancel_flags: CancelFlags,
     last_rendered: Image,
     previous_frame: Image,
     last_send_time: Instant,
     last_calc_time: Duration,
     debouncer: Debouncer,
+    /// The glitched pixels left after the glitch correction of the render in progress
+    remaining_glitches: usize,
 }
 
 impl CorgiApp {
@@ -63,7 +76,7 @@ impl CorgiApp {
             .expect("Eframe must be launched with the wgpu backend");
         let (ui_send, worker_recv) = mpsc::channel::<ImageGenCommand>();
         let (worker_send, ui_recv) = mpsc::channel::<StatusMessage>();
-        let cancelled = Arc::new(AtomicBool::new(false));
+        let cancel_flags = CancelFlags::default();
         let mut initial_image = Image::default();
         let output_image = Image::default();
         let ctx = cc.egui_ctx.clone();
@@ -85,7 +98,7 @@ impl CorgiApp {
             output_image.clone(),
             worker_recv,
             worker_send,
-            cancelled,
+            cancel_flags.clone(),
             ctx,
             &context,
         );
@@ -101,7 +114,12 @@ impl CorgiApp {
                 output_image.viewport.height as u32,
             ),
         )?;
-        let ui_state = CorgiUI::new(&context, initial_image, ui_send.clone());
+        let ui_state = CorgiUI::new(
+            &context,
+            initial_image,
+            ui_send.clone(),
+            cancel_flags.clone(),
+        );
 
         wgpu.renderer.write().callback_resources.insert(resources);
         thread::spawn(move || {
@@ -111,11 +129,13 @@ impl CorgiApp {
         Ok(Box::new(CorgiApp {
             command_channel: ui_send,
             status_channel: ui_recv,
+            cancel_flags,
             debouncer: Debouncer::new(std::time::Duration::from_millis(300)),
             last_rendered: ui_state.image().clone(),
             previous_frame: ui_state.image().clone(),
             last_send_time: Instant::now(),
             last_calc_time: Duration::from_millis(16),
+            remaining_glitches: 0,
             ui_state,
             context,
             last_save_time: Instant::now(),
@@ -132,7 +152,9 @@ impl eframe::App for CorgiApp {
                     self.ui_state.status.progress = Some(progress);
                 }
                 StatusMessage::NewPreviewViewport(new_calc_time, viewport) => {
-                    self.ui_state.status.message = "Finished rendering".into();
+                    self.ui_state.status.message =
+                        finished_message("Finished rendering", self.remaining_glitches);
+                    self.remaining_glitches = 0;
                     self.ui_state.status.progress = None;
                     self.ui_state.rendered_explore_viewport = viewport;
                     self.ui_state.swap = true;
@@ -143,8 +165,14 @@ impl eframe::App for CorgiApp {
                         Instant::now() - self.last_send_time
                     );
                 }
+                StatusMessage::GlitchesCorrected(corrected, remaining) => {
+                    tracing::info!("Corrected {corrected} glitched pixels, {remaining} remaining");
+                    self.remaining_glitches = remaining;
+                }
                 StatusMessage::NewOutputViewport(calc_time, viewport) => {
-                    self.ui_state.status.message = "Finished rendering output".into();
+                    self.ui_state.status.message =
+                        finished_message("Finished rendering output", self.remaining_glitches);
+                    self.remaining_glitches = 0;
                     self.ui_state.status.progress = None;
                     self.ui_state.rendered_output_viewport = viewport.clone();
                     self.ui_state.output_preview_viewport = viewport;
@@ -152,6 +180,23 @@ impl eframe::App for CorgiApp {
                     self.ui_state.swap = true;
                     tracing::debug!("Finished in {calc_time:?}");
                 }
+                StatusMessage::NucleusFound(nucleus) => {
+                    self.ui_state.status.progress = None;
+                    self.ui_state.apply_nucleus(nucleus);
+                }
+                StatusMessage::SkewEstimated(skew) => {
+                    self.ui_state.status.progress = None;
+                    self.ui_state.apply_skew(skew);
+                }
+                StatusMessage::IterationStats(stats) => {
+                    tracing::debug!("Iteration statistics: {stats:?}");
+                    self.ui_state.update_max_iter(stats);
+                }
+                StatusMessage::Cancelled => {
+                    self.remaining_glitches = 0;
+                    self.ui_state.status.message = "Render cancelled".into();
+                    self.ui_state.status.progress = None;
+                }
             }
         }
         self.ui_state.generate_ui(ctx, &mut self.context);
@@ -184,6 +229,8 @@ impl eframe::App for CorgiApp {
                     }
                 };
                 if do_send {
+                    // the newer settings replace the preview being rendered
+                    self.cancel_flags.replace_preview();
                     if self
                         .command_channel
                         .send(ImageGenCommand::NewPreviewSettings(image.clone()))
@@ -217,3 +264,12 @@ impl eframe::App for CorgiApp {
         self.context.save();
     }
 }
+
+/// The status message of a finished render, mentioning the pixels that are still glitched
+fn finished_message(message: &str, remaining_glitches: usize) -> String {
+    if remaining_glitches == 0 {
+        message.into()
+    } else {
+        format!("{message}, {remaining_glitches} glitched pixels remain")
+    }
+}
@@ -2,14 +2,21 @@ use std::fs;
 use std::io::Write;
 use std::path::PathBuf;
 
+use corgi::image_gen::OrbitCache;
+use corgi::types::AlgorithmPolicy;
 use directories::{ProjectDirs, UserDirs};
 use eframe::egui::style::WidgetVisuals;
 use eframe::egui::{Color32, CornerRadius, FontId, Stroke, Style, TextStyle, vec2};
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Config {
     pub max_shader_batch_iters: u64,
+    pub max_glitch_references: u32,
+    pub algorithm_policy: AlgorithmPolicy,
+    /// The size of the reference orbit cache in MiB, or 0 to disable it
+    pub orbit_cache_size: u64,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -45,10 +52,27 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             max_shader_batch_iters: 5000,
+            max_glitch_references: 8,
+            algorithm_policy: AlgorithmPolicy::default(),
+            orbit_cache_size: 1024,
         }
     }
 }
 
+impl Config {
+    /// The cache of reference orbits in the cache directory, unless it is disabled
+    pub fn orbit_cache(&self) -> Option<OrbitCache> {
+        if self.orbit_cache_size == 0 {
+            return None;
+        }
+        let proj_dirs = ProjectDirs::from("com", "kiranwells", "corgi")?;
+        Some(OrbitCache::new(
+            proj_dirs.cache_dir().join("orbits"),
+            self.orbit_cache_size << 20,
+        ))
+    }
+}
+
 impl Default for Cache {
     fn default() -> Self {
         let home_dir = UserDirs::new()
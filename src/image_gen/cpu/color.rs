@@ -0,0 +1,468 @@
+/*!
+# CPU Color Step
+
+The coloring of each pixel on the CPU, which mirrors the color shader `color.wesl`.
+ */
+
+use std::f32::consts::TAU;
+
+use super::CpuBuffers;
+use crate::types::{ColorParams, Image, LayerKind, LightingKind, RenderParams};
+
+// the kinds are uploaded as the values of their enums, like for the color shader
+const LAYER_NONE: u8 = LayerKind::None as u8;
+const LAYER_STEP: u8 = LayerKind::Step as u8;
+const LAYER_SMOOTH_STEP: u8 = LayerKind::SmoothStep as u8;
+const LAYER_DISTANCE: u8 = LayerKind::Distance as u8;
+const LAYER_ORBIT_TRAP: u8 = LayerKind::OrbitTrap as u8;
+const LAYER_STRIPE: u8 = LayerKind::Stripe as u8;
+const LAYER_PERIOD: u8 = LayerKind::Period as u8;
+const LAYER_MULTIPLIER: u8 = LayerKind::Multiplier as u8;
+const LAYER_LYAPUNOV: u8 = LayerKind::Lyapunov as u8;
+const LAYER_ROOT_INDEX: u8 = LayerKind::RootIndex as u8;
+const LAYER_CONVERGENCE: u8 = LayerKind::Convergence as u8;
+
+const GRADIENT_FLAT: u32 = 0;
+const GRADIENT_PROCEDURAL: u32 = 1;
+const GRADIENT_MANUAL: u32 = 2;
+const GRADIENT_HUE: u32 = 3;
+
+const LIGHTING_FLAT: u32 = LightingKind::Flat as u32;
+const LIGHTING_GRADIENT: u32 = LightingKind::Gradient as u32;
+const LIGHTING_REPEATING_GRADIENT: u32 = LightingKind::RepeatingGradient as u32;
+const LIGHTING_SHADED: u32 = LightingKind::Shaded as u32;
+
+type Vec3 = [f32; 3];
+
+/// The parameters of the color step, as uploaded to the color shader
+pub(super) struct ColorInputs {
+    external: ColorParams,
+    internal: ColorParams,
+    render: RenderParams,
+    /// The gradient stops of the external coloring, followed by the internal ones
+    gradient: Vec<[f32; 4]>,
+}
+
+impl From<&Image> for ColorInputs {
+    fn from(image: &Image) -> Self {
+        let (_, mut gradient) = image.external_coloring.gradient.decompose();
+        let (_, internal_gradient) = image.internal_coloring.gradient.decompose();
+        gradient.extend(internal_gradient);
+        Self {
+            external: ColorParams::from(&image.external_coloring),
+            internal: ColorParams::from(&image.internal_coloring),
+            render: image.into(),
+            gradient: gradient
+                .chunks_exact(4)
+                .map(|stop| [stop[0], stop[1], stop[2], stop[3]])
+                .collect(),
+        }
+    }
+}
+
+impl ColorInputs {
+    fn stop(&self, i: u32) -> [f32; 4] {
+        self.gradient.get(i as usize).copied().unwrap_or_default()
+    }
+}
+
+fn fract(x: f32) -> f32 {
+    x - x.floor()
+}
+
+fn mix(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    std::array::from_fn(|i| a[i] * (1.0 - t) + b[i] * t)
+}
+
+fn smoothstep(edge_0: f32, edge_1: f32, x: f32) -> f32 {
+    let t = ((x - edge_0) / (edge_1 - edge_0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn scale(a: Vec3, scale: f32) -> Vec3 {
+    a.map(|x| x * scale)
+}
+
+fn add(a: Vec3, b: Vec3) -> Vec3 {
+    std::array::from_fn(|i| a[i] + b[i])
+}
+
+fn dot(a: Vec3, b: Vec3) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn length(a: Vec3) -> f32 {
+    dot(a, a).sqrt()
+}
+
+fn normalize(a: Vec3) -> Vec3 {
+    scale(a, 1.0 / length(a))
+}
+
+fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn hsv2rgb([h, s, v]: Vec3) -> Vec3 {
+    let i = (h * 6.0).floor();
+    let f = h * 6.0 - i;
+    let p = v * (1.0 - s);
+    let q = v * (1.0 - f * s);
+    let t = v * (1.0 - (1.0 - f) * s);
+    match (i % 6.0) as i32 {
+        0 => [v, t, p],
+        1 => [q, v, p],
+        2 => [p, v, t],
+        3 => [p, q, v],
+        4 => [t, p, v],
+        5 => [v, p, q],
+        _ => [0.0; 3],
+    }
+}
+
+fn rgb2hsv([r, g, b]: Vec3) -> Vec3 {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let c = max - min;
+    if c == 0.0 {
+        return [0.0, 0.0, max];
+    }
+    let mut h = if max == r {
+        (g - b) / c
+    } else if max == g {
+        (b - r) / c + 2.0
+    } else {
+        (r - g) / c + 4.0
+    };
+    h /= 6.0;
+    if h < 0.0 {
+        h += 1.0;
+    }
+    [h, c / max, max]
+}
+
+/// The index of the root a Newton fractal point converged to, from 0 to 1
+fn root_value(attractor: Vec3) -> f32 {
+    attractor[0] / attractor[1].max(1.0)
+}
+
+/// The magnitude (0) or argument (1) of the multiplier of an attracting cycle
+fn multiplier_value(attractor: Vec3, part: u32) -> f32 {
+    if part == 0 {
+        attractor[0]
+    } else {
+        attractor[1] / TAU + 0.5
+    }
+}
+
+/// The values of a pixel that the layers are computed from
+struct PixelValues {
+    step: i32,
+    internal: bool,
+    orbits: [f32; 4],
+    stripes: [f32; 4],
+    exponent: f32,
+    smoothed_step: f32,
+    distance_estimate: f32,
+    attractor: Vec3,
+}
+
+impl PixelValues {
+    fn new(buffers: &CpuBuffers, index: usize, power: f32) -> Self {
+        // pixels past the edge of the image read the last pixel, as the robust
+        // buffer access of the shader reads some value in the buffer
+        let index = index.min(buffers.step.len() - 1);
+        let step = buffers.step[index];
+        let z = buffers.z[index];
+        let dz = buffers.dz[index];
+        let r = (z[0] * z[0] + z[1] * z[1]).sqrt();
+        let dr = (dz[0] * dz[0] + dz[1] * dz[1]).sqrt();
+        let mut values = Self {
+            step,
+            internal: step < 0,
+            orbits: buffers.orbits[index],
+            stripes: buffers.stripes[index],
+            // the Lyapunov renderer stores the exponent of each point in z.z
+            exponent: z[2],
+            smoothed_step: step as f32 + (1.0 - r.ln().ln() / power.ln()),
+            distance_estimate: r.ln() * r / dr,
+            attractor: [0.0; 3],
+        };
+        if values.internal {
+            values.step = -step;
+            values.smoothed_step = dz[0];
+            values.distance_estimate = dz[1];
+            values.attractor = z;
+        }
+        values
+    }
+}
+
+fn calculate_lighting_layers(
+    inputs: &ColorInputs,
+    buffers: &CpuBuffers,
+    x: u32,
+    y: u32,
+    color_params: &ColorParams,
+) -> f32 {
+    let index = (x + y * inputs.render.width) as usize;
+    let values = PixelValues::new(buffers, index, inputs.render.power);
+    let mut brightness = 0.0;
+    for i in 0..8 {
+        let layer_strength = color_params.light_strengths[i];
+        let layer_param = color_params.light_params[i];
+        match color_params.light_layer_types[i] {
+            LAYER_NONE => continue,
+            LAYER_STEP => {
+                brightness += (values.step as f32 - layer_param) * 0.05 * layer_strength;
+            }
+            LAYER_SMOOTH_STEP => {
+                if values.internal {
+                    brightness += (values.smoothed_step - layer_param) * 0.3 * layer_strength;
+                } else {
+                    brightness += (values.smoothed_step.ln() - layer_param) * 0.4 * layer_strength;
+                }
+            }
+            LAYER_DISTANCE
+                if values.distance_estimate > 0.0 && values.distance_estimate.is_finite() =>
+            {
+                brightness += (-values.distance_estimate.ln() + 5.0 + layer_param * 2.5)
+                    * layer_strength
+                    * 0.1;
+            }
+            LAYER_ORBIT_TRAP => {
+                let mut orbit = values.orbits[(layer_param as usize).min(3)];
+                if values.internal {
+                    let offset = 0.25 * fract(layer_param);
+                    brightness +=
+                        (orbit - offset) / (0.25 - offset) * (layer_strength - 1.0).exp2();
+                } else {
+                    orbit = 10.0 - orbit;
+                    let offset = 10.0 * fract(layer_param);
+                    brightness +=
+                        (orbit - offset) / (10.0 - offset) * (layer_strength - 1.0).exp2();
+                }
+            }
+            LAYER_STRIPE => {
+                let stripe = values.stripes[(layer_param as usize).min(3)];
+                brightness += (stripe - fract(layer_param)) * layer_strength.powi(3);
+            }
+            LAYER_PERIOD => {
+                brightness += (values.attractor[2] - layer_param) * 0.1 * layer_strength;
+            }
+            LAYER_MULTIPLIER => {
+                brightness += (multiplier_value(values.attractor, layer_param as u32)
+                    - fract(layer_param))
+                    * layer_strength;
+            }
+            LAYER_LYAPUNOV => {
+                brightness += (values.exponent - layer_param) * layer_strength;
+            }
+            LAYER_ROOT_INDEX => {
+                brightness += (root_value(values.attractor) - layer_param) * layer_strength;
+            }
+            LAYER_CONVERGENCE => {
+                brightness += (values.smoothed_step - layer_param) * 0.1 * layer_strength;
+            }
+            _ => {}
+        }
+    }
+    brightness
+}
+
+/// Colors a pixel, returning its RGBA value as stored in the texture of the color shader
+pub(super) fn color_pixel(inputs: &ColorInputs, buffers: &CpuBuffers, x: u32, y: u32) -> [u8; 4] {
+    let width = inputs.render.width;
+    let index = (x + y * width) as usize;
+    let values = PixelValues::new(buffers, index, inputs.render.power);
+    let (color_params, gradient_offset) = if values.internal {
+        (&inputs.internal, inputs.external.gradient_size)
+    } else {
+        (&inputs.external, 0)
+    };
+
+    // first, calculate the color layers
+    let mut color_value = 0.0;
+    for i in 0..8 {
+        let layer_strength = color_params.color_strengths[i];
+        let layer_param = color_params.color_params[i];
+        match color_params.color_layer_types[i] {
+            LAYER_NONE => continue,
+            LAYER_STEP => {
+                color_value += values.step as f32 * 0.05 * layer_strength;
+            }
+            LAYER_SMOOTH_STEP => {
+                if values.internal {
+                    color_value += (values.smoothed_step - layer_param) * 0.3 * layer_strength;
+                } else {
+                    let log = values.smoothed_step.ln();
+                    color_value += (log * log - layer_param) * 0.1 * layer_strength;
+                }
+            }
+            LAYER_DISTANCE
+                if values.distance_estimate > 0.0 && values.distance_estimate.is_finite() =>
+            {
+                color_value += (values.distance_estimate.ln() + layer_param) * 0.1 * layer_strength;
+            }
+            LAYER_ORBIT_TRAP => {
+                let mut orbit = values.orbits[(layer_param as usize).min(3)];
+                if orbit > 2.0 {
+                    orbit = (orbit / 2.0).ln() + 2.0;
+                }
+                color_value += orbit * 0.1 * layer_strength.exp2() + layer_param;
+            }
+            LAYER_STRIPE => {
+                let stripe = values.stripes[(layer_param as usize).min(3)];
+                color_value += (stripe - fract(layer_param)) * layer_strength.powi(3);
+            }
+            LAYER_PERIOD => {
+                color_value += values.attractor[2] * 0.1 * layer_strength;
+            }
+            LAYER_MULTIPLIER => {
+                color_value += (multiplier_value(values.attractor, layer_param as u32)
+                    - fract(layer_param))
+                    * layer_strength;
+            }
+            LAYER_LYAPUNOV => {
+                color_value += (values.exponent + layer_param) * layer_strength;
+            }
+            LAYER_ROOT_INDEX => {
+                color_value += (root_value(values.attractor) + layer_param) * layer_strength;
+            }
+            LAYER_CONVERGENCE => {
+                color_value += values.smoothed_step * 0.1 * layer_strength + layer_param;
+            }
+            _ => {}
+        }
+    }
+
+    // then, turn the color value into a color
+    let stop = |i: u32| inputs.stop(i + gradient_offset);
+    let rgb = |stop: [f32; 4]| [stop[0], stop[1], stop[2]];
+    let position = color_value * color_params.color_frequency + color_params.color_offset;
+    let mut color = match color_params.gradient_kind {
+        GRADIENT_FLAT => rgb(stop(0)),
+        GRADIENT_PROCEDURAL => {
+            let [a, b, c, d] = [0, 1, 2, 3].map(|i| rgb(stop(i)));
+            std::array::from_fn(|i| {
+                a[i] + b[i]
+                    * (TAU
+                        * (c[i] * color_value * color_params.color_frequency
+                            + color_params.color_offset
+                            + d[i]))
+                        .cos()
+            })
+        }
+        GRADIENT_MANUAL => {
+            let frac = fract(position);
+            let size = color_params.gradient_size;
+            let mut color = [0.0; 3];
+            for i in 0..size {
+                let current = stop(i);
+                if frac < current[3] {
+                    let (previous, start) = if i == 0 {
+                        let previous = stop(size - 1);
+                        (previous, previous[3] - 1.0)
+                    } else {
+                        let previous = stop(i - 1);
+                        (previous, previous[3])
+                    };
+                    let t = smoothstep(start, current[3], frac);
+                    color = mix(rgb(previous), rgb(current), t);
+                    break;
+                }
+                if i == size - 1 {
+                    let next = stop(0);
+                    let t = smoothstep(current[3], next[3] + 1.0, frac);
+                    color = mix(rgb(current), rgb(next), t);
+                }
+            }
+            color
+        }
+        GRADIENT_HUE => hsv2rgb([fract(position), stop(0)[0], stop(0)[1]]),
+        _ => [0.0; 3],
+    };
+
+    // next, calculate the lighting
+    let lighting = |x, y| calculate_lighting_layers(inputs, buffers, x, y, color_params);
+    let brightness = match color_params.lighting_kind {
+        LIGHTING_FLAT => [1.0; 3],
+        LIGHTING_GRADIENT => [lighting(x, y); 3],
+        LIGHTING_REPEATING_GRADIENT => [lighting(x, y).cos() * 0.5 + 0.5; 3],
+        LIGHTING_SHADED => {
+            let point = lighting(x, y);
+            let plus_x = lighting(x + 1, y);
+            let plus_y = lighting(x, y + 1);
+            let aspect = width as f32 / inputs.render.height as f32;
+            let aspect_scale = if aspect < 1.0 {
+                [aspect, 1.0]
+            } else {
+                [1.0, 1.0 / aspect]
+            };
+            let normal = normalize(cross(
+                [100.0 / width as f32 * aspect_scale[0], 0.0, plus_x - point],
+                [
+                    0.0,
+                    100.0 / inputs.render.height as f32 * aspect_scale[1],
+                    plus_y - point,
+                ],
+            ));
+            let brightness = color_params.lights.iter().fold([0.0; 3], |sum, light| {
+                add(
+                    sum,
+                    scale(
+                        light.color,
+                        dot(normal, light.direction).max(0.0) * light.strength,
+                    ),
+                )
+            });
+            if (x as f32) < width as f32 * inputs.render.debug_shutter {
+                color = scale(add(normalize(normal), [1.0; 3]), 0.5);
+                [1.0; 3]
+            } else {
+                scale(brightness, 1.0 / 3.0)
+            }
+        }
+        _ => [0.0; 3],
+    };
+    let [hue, saturation, value] = rgb2hsv(color);
+    let b = length(brightness) * color_params.brightness / 3f32.sqrt();
+    color = hsv2rgb([hue, saturation * color_params.saturation, value * b]);
+    color = std::array::from_fn(|i| color[i] * brightness[i]);
+    // above 1.0 brightness, start fading to the light color
+    color = mix(
+        color,
+        brightness.map(|x| x.clamp(0.0, 1.0)),
+        (b - 1.0).clamp(0.0, 1.0),
+    );
+
+    // finally, check for overlays
+    let overlays = &color_params.overlays;
+    let outline = overlays.iteration_outline_color;
+    if fract(outline[3]) > 0.0 {
+        let step_at = |index: u32| {
+            let index = (index as usize).min(buffers.step.len() - 1);
+            buffers.step[index]
+        };
+        let step_x = step_at((x + 1) + y * width);
+        let step_y = step_at(x + (y + 1) * width);
+        let steps = (outline[3].floor() as i32).max(1);
+        if (values.step / steps - step_x / steps).abs() == 1
+            || (values.step / steps - step_y / steps).abs() == 1
+        {
+            color = mix(color, rgb(outline), fract(outline[3]));
+        }
+    }
+    let outline = overlays.set_outline_color;
+    if fract(outline[3]) > 0.0 && values.distance_estimate >= 0.0 {
+        let frac = 1.0 - (values.distance_estimate / 0.00005 / outline[3].floor()).clamp(0.0, 1.0);
+        color = mix(color, rgb(outline), fract(outline[3]) * frac);
+    }
+
+    let [r, g, b] = color.map(|x| (x.clamp(0.0, 1.0) * 255.0).round() as u8);
+    [r, g, b, 255]
+}
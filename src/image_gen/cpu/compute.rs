@@ -0,0 +1,688 @@
+/*!
+# CPU Compute Step
+
+The iteration of each pixel on the CPU, which mirrors the f64 compute shaders
+`direct_64.wesl` and `perturbed_64.wesl`. The coloring values are kept in f32,
+as in the shaders, so that both write the same data.
+ */
+
+use std::ops::{Add, Mul, Sub};
+
+use super::super::probe::{length_squared, normalize, orbit_values, stripe_values};
+use crate::types::{
+    ATTRACTOR_ENABLED, DERIVATIVE_ENABLED, FractalFormula, INTERIOR_DISTANCE_ENABLED, JULIA,
+    ORBIT_ENABLED, STRIPES_ENABLED, TOTAL_ANGLE_ENABLED, complex_mul_f64,
+};
+
+/// The escape radius of the shaders, which is compared with `|z|^2`
+pub(super) const ESCAPE_RADIUS: f32 = 10000.0;
+
+/// The largest ratio of `|z_n|^2` to `|reference_n|^2` before a pixel is considered glitched
+const GLITCH_TOLERANCE: f64 = 1e-6;
+
+/// The longest attracting cycle searched for by the interior coloring
+const MAX_INTERIOR_PERIOD: u32 = 1024;
+
+/// A complex number in double precision
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(super) struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    fn norm_squared(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    fn to_f32(self) -> [f32; 2] {
+        [self.re as f32, self.im as f32]
+    }
+}
+
+impl From<(f64, f64)> for Complex {
+    fn from((re, im): (f64, f64)) -> Self {
+        Self { re, im }
+    }
+}
+
+impl From<Complex> for (f64, f64) {
+    fn from(z: Complex) -> Self {
+        (z.re, z.im)
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        complex_mul_f64(self.into(), other.into()).into()
+    }
+}
+
+impl Mul<f64> for Complex {
+    type Output = Complex;
+
+    fn mul(self, scale: f64) -> Complex {
+        Complex::new(self.re * scale, self.im * scale)
+    }
+}
+
+/// The data the compute step writes for each pixel, in the layout of the GPU buffers
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct PixelData {
+    pub step: i32,
+    pub orbits: [f32; 4],
+    pub stripes: [f32; 4],
+    pub z: [f32; 3],
+    pub dz: [f32; 3],
+    /// Whether the pixel lost too much precision to its reference orbit
+    pub glitched: bool,
+}
+
+/// The parameters of the compute step, like the `Params` of the compute shaders
+pub(super) struct PixelParams {
+    pub width: u32,
+    pub height: u32,
+    pub max_iter: u32,
+    pub power: u32,
+    pub flags: u32,
+    /// The formula of each step of the hybrid sequence
+    pub formulas: Vec<&'static dyn FractalFormula>,
+    pub zoom: f32,
+    /// The size of the view, `2^-zoom`
+    pub scale: f64,
+    pub aspect: (f64, f64),
+    /// The rotation and skew of the viewport
+    pub transform: [[f64; 2]; 2],
+    pub center: Complex,
+    pub julia: Complex,
+}
+
+impl PixelParams {
+    fn formula_at(&self, n: u32) -> &'static dyn FractalFormula {
+        self.formulas[n as usize % self.formulas.len()]
+    }
+
+    fn julia(&self) -> bool {
+        self.flags & JULIA != 0
+    }
+
+    /// The offset of a pixel from a point in the view, relative to the size of the view
+    fn offset(&self, x: u32, y: u32, (from_x, from_y): (f64, f64)) -> Complex {
+        let [[a, b], [c, d]] = self.transform;
+        let x = (x as f64 / self.width as f64 - 0.5 - from_x) * 2.0 * self.aspect.0 * self.scale;
+        let y = (y as f64 / self.height as f64 - 0.5 - from_y) * 2.0 * self.aspect.1 * self.scale;
+        Complex::new(a * x + b * y, c * x + d * y)
+    }
+
+    fn derivative(&self, formula: &dyn FractalFormula, z: [f32; 2]) -> Complex {
+        formula
+            .derivative_f64((z[0] as f64, z[1] as f64), self.power)
+            .into()
+    }
+}
+
+/// The reference orbits of the perturbed compute step
+pub(super) struct Reference {
+    /// The orbit of the reference, followed by the critical orbit of Julia sets
+    pub orbit: Vec<Complex>,
+    /// The length of the orbit of the reference
+    pub probe_len: usize,
+    /// The length of the critical orbit
+    pub critical_len: usize,
+    /// The location of the reference point
+    pub location: Complex,
+    /// The offset of the reference from the center of the view, relative to its size
+    pub offset: (f64, f64),
+}
+
+impl Reference {
+    /// The point of the orbit at an iteration, which is zero past its end, as in the
+    /// zero-initialized probe buffer
+    fn point(&self, iteration: usize) -> Complex {
+        self.orbit.get(iteration).copied().unwrap_or_default()
+    }
+
+    /// The iteration that points are rebased to, as in `rebase_start` of the perturbed shaders
+    fn rebase_start(&self, params: &PixelParams, iteration: u32) -> usize {
+        let start = iteration as usize % params.formulas.len();
+        if params.julia() {
+            start + self.probe_len
+        } else {
+            start
+        }
+    }
+
+    /// Whether the orbit containing the iteration has escaped
+    fn orbit_ended(&self, iteration: usize) -> bool {
+        iteration == self.probe_len || iteration == self.probe_len + self.critical_len
+    }
+
+    /// Moves the delta back to the start of the reference orbit when the point gets
+    /// closer to it, or when the reference escapes
+    fn rebase(
+        &self,
+        params: &PixelParams,
+        x_n: &mut Complex,
+        delta_n: &mut Complex,
+        ref_iteration: &mut usize,
+        iteration: u32,
+    ) {
+        let start = self.rebase_start(params, iteration);
+        let x_0 = self.point(start);
+        let y_n = (*x_n - x_0) + *delta_n;
+        if y_n.norm_squared() < delta_n.norm_squared() || self.orbit_ended(*ref_iteration) {
+            *delta_n = y_n;
+            *ref_iteration = start;
+            *x_n = x_0;
+        }
+    }
+}
+
+/// A complex number with an extended exponent, stored as `[re, im, exponent]`
+/// like the `fe_` functions of the shaders
+type FloatExpComplex = [f32; 3];
+
+const FE_ONE: FloatExpComplex = [1.0, 0.0, 0.0];
+
+fn fe_normalize([x, y, exponent]: FloatExpComplex) -> FloatExpComplex {
+    let magnitude = x.abs().max(y.abs());
+    if magnitude == 0.0 {
+        return [0.0; 3];
+    }
+    let shift = magnitude.log2().floor() + 1.0;
+    let scale = (-shift).exp2();
+    [x * scale, y * scale, exponent + shift]
+}
+
+fn fe_add(a: FloatExpComplex, b: FloatExpComplex) -> FloatExpComplex {
+    if a[0] == 0.0 && a[1] == 0.0 {
+        return b;
+    }
+    if b[0] == 0.0 && b[1] == 0.0 {
+        return a;
+    }
+    let exponent = a[2].max(b[2]);
+    let a_scale = (a[2] - exponent).exp2();
+    let b_scale = (b[2] - exponent).exp2();
+    fe_normalize([
+        a[0] * a_scale + b[0] * b_scale,
+        a[1] * a_scale + b[1] * b_scale,
+        exponent,
+    ])
+}
+
+fn fe_mul(a: FloatExpComplex, b: FloatExpComplex) -> FloatExpComplex {
+    fe_normalize([
+        a[0] * b[0] - a[1] * b[1],
+        a[0] * b[1] + a[1] * b[0],
+        a[2] + b[2],
+    ])
+}
+
+fn fe_div(a: FloatExpComplex, b: FloatExpComplex) -> FloatExpComplex {
+    let length = length_squared([b[0], b[1]]);
+    fe_normalize([
+        (a[0] * b[0] + a[1] * b[1]) / length,
+        (a[1] * b[0] - a[0] * b[1]) / length,
+        a[2] - b[2],
+    ])
+}
+
+fn fe_scale(a: FloatExpComplex, scale: f32) -> FloatExpComplex {
+    fe_normalize([a[0] * scale, a[1] * scale, a[2]])
+}
+
+/// Converts to the extended exponent form, keeping values that are too small for an f32
+fn fe_from_f64(z: Complex) -> FloatExpComplex {
+    let magnitude = z.re.abs().max(z.im.abs());
+    if magnitude == 0.0 {
+        return [0.0; 3];
+    }
+    let shift = magnitude.log2().floor() + 1.0;
+    let scale = (-shift).exp2();
+    fe_normalize([(z.re * scale) as f32, (z.im * scale) as f32, shift as f32])
+}
+
+/// The derivatives of n iterations of `z^power + c`, with respect to the
+/// starting point z and to c
+#[derive(Clone, Copy, Debug)]
+struct CycleDerivatives {
+    dz: FloatExpComplex,
+    dc: FloatExpComplex,
+    dzdz: FloatExpComplex,
+    dcdz: FloatExpComplex,
+}
+
+impl CycleDerivatives {
+    fn new() -> Self {
+        Self {
+            dz: FE_ONE,
+            dc: [0.0; 3],
+            dzdz: [0.0; 3],
+            dcdz: [0.0; 3],
+        }
+    }
+
+    /// Extends the derivatives by one iteration from the point z
+    fn step(&mut self, z: FloatExpComplex, power: u32) {
+        // z^(power - 2)
+        let mut z_power = FE_ONE;
+        for _ in 2..power {
+            z_power = fe_mul(z_power, z);
+        }
+        let p = power as f32;
+        let f_prime = fe_scale(fe_mul(z_power, z), p);
+        let f_prime_prime = fe_scale(z_power, p * (p - 1.0));
+        let d = *self;
+        self.dcdz = fe_add(
+            fe_mul(f_prime_prime, fe_mul(d.dc, d.dz)),
+            fe_mul(f_prime, d.dcdz),
+        );
+        self.dzdz = fe_add(
+            fe_mul(f_prime_prime, fe_mul(d.dz, d.dz)),
+            fe_mul(f_prime, d.dzdz),
+        );
+        self.dc = fe_add(fe_mul(f_prime, d.dc), FE_ONE);
+        self.dz = fe_mul(f_prime, d.dz);
+    }
+
+    /// The interior distance estimate from the derivatives over one period of the
+    /// attracting cycle, relative to the size of the view
+    fn interior_distance_estimate(&self, zoom: f32) -> f32 {
+        let dz_length_squared =
+            length_squared([self.dz[0], self.dz[1]]) * (2.0 * self.dz[2]).exp2();
+        let one_minus_dz = fe_add(FE_ONE, [-self.dz[0], -self.dz[1], self.dz[2]]);
+        let denominator = fe_add(self.dcdz, fe_div(fe_mul(self.dzdz, self.dc), one_minus_dz));
+        (1.0 - dz_length_squared) / length_squared([denominator[0], denominator[1]]).sqrt()
+            * (zoom - denominator[2]).exp2()
+    }
+}
+
+/// The attracting cycle of an interior point: its period and the derivatives over one period
+type AttractingCycle = Option<(u32, CycleDerivatives)>;
+
+/// The magnitude and argument of the multiplier of an attracting cycle, and its period
+fn attractor_values(cycle: AttractingCycle) -> [f32; 3] {
+    match cycle {
+        None => [0.0; 3],
+        Some((period, derivatives)) => {
+            let [x, y, exponent] = derivatives.dz;
+            [
+                length_squared([x, y]).sqrt() * exponent.exp2(),
+                y.atan2(x),
+                period as f32,
+            ]
+        }
+    }
+}
+
+/// The smooth fraction of a step between two radii
+fn step_frac(start_radius_squared: f32, end_radius_squared: f32, power: f32) -> f32 {
+    1.0 + (end_radius_squared.ln() / start_radius_squared.ln()).log2() / power.log2()
+}
+
+fn sub_f32(a: [f32; 2], b: [f32; 2]) -> [f32; 2] {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+fn dot_f32(a: [f32; 2], b: [f32; 2]) -> f32 {
+    a[0] * b[0] + a[1] * b[1]
+}
+
+fn add_values(sum: &mut [f32; 4], values: [f32; 4]) {
+    sum.iter_mut()
+        .zip(values)
+        .for_each(|(sum, value)| *sum += value);
+}
+
+fn min_values(min: &mut [f32; 4], values: [f32; 4]) {
+    min.iter_mut()
+        .zip(values)
+        .for_each(|(min, value)| *min = min.min(value));
+}
+
+/// The stripe average of an escaped point, interpolated between the last two steps
+fn escaped_stripes(stripes: [f32; 4], prev_stripes: [f32; 4], step: u32, frac: f32) -> [f32; 4] {
+    let step = step as f32;
+    std::array::from_fn(|i| {
+        stripes[i] / step * frac + prev_stripes[i] / (step - 1.0) * (1.0 - frac)
+    })
+}
+
+/// Finds the attracting cycle of a point whose orbit has settled into it, iterating on
+/// from z at the given iteration. The first phase finds how close the orbit returns to z,
+/// and the second takes the derivatives up to the first return that close.
+fn attracting_cycle(
+    params: &PixelParams,
+    z_start: Complex,
+    c: Complex,
+    iteration: u32,
+) -> AttractingCycle {
+    let mut closest = f64::MAX;
+    for phase in 0..2 {
+        let mut z = z_start;
+        let mut derivatives = CycleDerivatives::new();
+        for k in 0..MAX_INTERIOR_PERIOD {
+            if phase == 1 {
+                derivatives.step(fe_from_f64(z), params.power);
+            }
+            let formula = params.formula_at(iteration + k);
+            z = Complex::from(formula.step_f64(z.into(), params.power)) + c;
+            let distance = (z - z_start).norm_squared();
+            if phase == 0 {
+                closest = closest.min(distance);
+            } else if distance <= 16.0 * closest {
+                return Some((k + 1, derivatives));
+            }
+        }
+    }
+    None
+}
+
+/// Iterates a pixel directly, as in `direct_64.wesl`
+pub(super) fn direct_pixel(params: &PixelParams, x: u32, y: u32) -> PixelData {
+    let offset = params.center + params.offset(x, y, (0.0, 0.0));
+    let (c, mut z_n) = if params.julia() {
+        (params.julia, offset)
+    } else {
+        (offset, Complex::default())
+    };
+    let flags = params.flags;
+    let mut z_n_prime = Complex::new(1.0, 0.0);
+    let mut orbits = [ESCAPE_RADIUS; 4];
+    let mut stripes = [0.0; 4];
+    let mut prev_stripes = [0.0; 4];
+
+    // reference values for detecting orbit cycles
+    let mut z_old = Complex::new(ESCAPE_RADIUS as f64, ESCAPE_RADIUS as f64);
+    // internal coloring values
+    let mut closest = length_squared(c.to_f32()).sqrt();
+    let mut min_iter = 1;
+    let mut line = [0.0; 2];
+    let mut angles = 0.0;
+    let mut total_angle = 0.0;
+
+    let mut step = 0;
+    while step < params.max_iter {
+        let z_f32 = z_n.to_f32();
+        let radius_squared = z_n.norm_squared() as f32;
+        if flags & STRIPES_ENABLED != 0 {
+            prev_stripes = stripes;
+            add_values(&mut stripes, stripe_values(z_f32));
+        }
+        if flags & ORBIT_ENABLED != 0 && step > 1 {
+            min_values(&mut orbits, orbit_values(z_f32));
+        }
+
+        // test if the point is outside the escape radius, or repeating a cycle
+        if radius_squared > ESCAPE_RADIUS || z_n == z_old {
+            break;
+        }
+        if step >= 100 && (step - 100) % 1024 == 0 {
+            z_old = z_n;
+        }
+
+        let previous = z_n;
+        let formula = params.formula_at(step);
+        if flags & DERIVATIVE_ENABLED != 0 {
+            z_n_prime = params.derivative(formula, z_f32) * z_n_prime + Complex::new(1.0, 0.0);
+        }
+        z_n = Complex::from(formula.step_f64(z_n.into(), params.power)) + c;
+
+        // track the total angle for a cycle
+        if flags & TOTAL_ANGLE_ENABLED != 0 {
+            if step != 0 {
+                angles += dot_f32(normalize((z_n - previous).to_f32()), line).acos();
+                line = normalize((previous - z_n).to_f32());
+                let distance = length_squared((z_n - c).to_f32()).sqrt();
+                if distance < closest {
+                    total_angle = angles;
+                    min_iter = step + 1;
+                    closest = distance;
+                }
+            } else {
+                line = normalize((previous - z_n).to_f32());
+            }
+        }
+        step += 1;
+    }
+
+    let radius_squared = z_n.norm_squared() as f32;
+    if radius_squared < 4.0 {
+        let mut distance_estimate = -1.0;
+        let mut attractor = [0.0; 3];
+        if flags & (INTERIOR_DISTANCE_ENABLED | ATTRACTOR_ENABLED) != 0 {
+            let cycle = attracting_cycle(params, z_n, c, step);
+            if let Some((_, derivatives)) = cycle
+                && flags & INTERIOR_DISTANCE_ENABLED != 0
+            {
+                distance_estimate = derivatives.interior_distance_estimate(params.zoom);
+            }
+            attractor = attractor_values(cycle);
+        }
+        PixelData {
+            step: -(min_iter as i32),
+            orbits,
+            stripes: stripes.map(|stripe| stripe / step as f32),
+            z: attractor,
+            dz: [total_angle, distance_estimate, 0.0],
+            glitched: false,
+        }
+    } else {
+        let frac = step_frac(radius_squared, ESCAPE_RADIUS, params.power as f32);
+        let [dz_x, dz_y] = (z_n_prime * params.scale).to_f32();
+        let [z_x, z_y] = z_n.to_f32();
+        PixelData {
+            step: step as i32,
+            orbits,
+            stripes: escaped_stripes(stripes, prev_stripes, step, frac),
+            z: [z_x, z_y, 0.0],
+            dz: [dz_x, dz_y, params.zoom],
+            glitched: false,
+        }
+    }
+}
+
+/// Finds the attracting cycle of a point like [`attracting_cycle`], iterating the
+/// perturbed orbit on from the given state
+fn perturbed_attracting_cycle(
+    params: &PixelParams,
+    reference: &Reference,
+    start_delta: Complex,
+    start_ref: usize,
+    delta_0: Complex,
+    iteration: u32,
+) -> AttractingCycle {
+    let start = reference.point(start_ref);
+    let mut closest = f64::MAX;
+    for phase in 0..2 {
+        let mut delta_n = start_delta;
+        let mut ref_iteration = start_ref;
+        let mut derivatives = CycleDerivatives::new();
+        for k in 0..MAX_INTERIOR_PERIOD {
+            let mut x_n = reference.point(ref_iteration);
+            if phase == 1 {
+                derivatives.step(fe_from_f64(x_n + delta_n), params.power);
+            }
+            reference.rebase(
+                params,
+                &mut x_n,
+                &mut delta_n,
+                &mut ref_iteration,
+                iteration + k,
+            );
+            ref_iteration += 1;
+            let formula = params.formula_at(iteration + k);
+            delta_n = Complex::from(formula.delta_f64(x_n.into(), delta_n.into(), params.power))
+                + delta_0;
+            // the reference and delta parts are subtracted separately to avoid rounding errors
+            let distance =
+                ((reference.point(ref_iteration) - start) + (delta_n - start_delta)).norm_squared();
+            if phase == 0 {
+                closest = closest.min(distance);
+            } else if distance <= 16.0 * closest {
+                return Some((k + 1, derivatives));
+            }
+        }
+    }
+    None
+}
+
+/// Iterates a pixel relative to a reference orbit, as in `perturbed_64.wesl`
+pub(super) fn perturbed_pixel(
+    params: &PixelParams,
+    reference: &Reference,
+    x: u32,
+    y: u32,
+) -> PixelData {
+    let offset = params.offset(x, y, reference.offset);
+    let x_0 = reference.location;
+    let (delta_0, mut delta_n) = if params.julia() {
+        (Complex::default(), offset)
+    } else {
+        (offset, Complex::default())
+    };
+    let y_0 = (x_0 + delta_0).to_f32();
+    let flags = params.flags;
+    // z' scaled by the size of the view
+    let mut z_n_prime = Complex::default();
+    let mut orbits = [ESCAPE_RADIUS; 4];
+    let mut stripes = [0.0; 4];
+    let mut prev_stripes = [0.0; 4];
+
+    // reference values for detecting orbit cycles
+    let mut x_old = Complex::new(ESCAPE_RADIUS as f64 * 2.0, ESCAPE_RADIUS as f64 * 2.0);
+    let mut delta_old = Complex::new(-ESCAPE_RADIUS as f64, -ESCAPE_RADIUS as f64);
+    let mut ref_iteration = 0;
+
+    // internal coloring values
+    let mut closest = length_squared(y_0);
+    let mut min_iter = 1;
+    let mut line = normalize([-y_0[0], -y_0[1]]);
+    let mut angles = 0.0;
+    let mut total_angle = 0.0;
+    let mut previous = [0.0; 2];
+
+    let mut glitched = false;
+    let mut step = 0;
+    while step < params.max_iter {
+        let mut x_n = reference.point(ref_iteration);
+        let y_n = x_n + delta_n;
+        let y_f32 = y_n.to_f32();
+
+        // track the total angle for a cycle
+        if flags & TOTAL_ANGLE_ENABLED != 0 && step > 1 {
+            angles += dot_f32(normalize(sub_f32(y_f32, previous)), line).acos();
+            line = normalize(sub_f32(previous, y_f32));
+            let distance = ((x_n - x_0) + (delta_n - delta_0)).norm_squared() as f32;
+            if distance < closest {
+                total_angle = angles;
+                min_iter = step;
+                closest = distance;
+            }
+        }
+        previous = y_f32;
+
+        let radius_squared = y_n.norm_squared();
+        if flags & STRIPES_ENABLED != 0 {
+            prev_stripes = stripes;
+            add_values(&mut stripes, stripe_values(y_f32));
+        }
+        if flags & ORBIT_ENABLED != 0 && step > 1 {
+            min_values(&mut orbits, orbit_values(y_f32));
+        }
+
+        // Pauldelbrot's criterion: the point is much closer to zero than the reference
+        if radius_squared < GLITCH_TOLERANCE * x_n.norm_squared() {
+            glitched = true;
+            break;
+        }
+        // test if the point is outside the escape radius, or repeating a cycle
+        if radius_squared > ESCAPE_RADIUS as f64
+            || (step > 100 && x_n - x_old == delta_old - delta_n)
+        {
+            break;
+        }
+        reference.rebase(params, &mut x_n, &mut delta_n, &mut ref_iteration, step);
+        ref_iteration += 1;
+
+        if step >= 100 && (step - 100) % 1024 == 0 {
+            x_old = x_n;
+            delta_old = delta_n;
+        }
+
+        let formula = params.formula_at(step);
+        if flags & DERIVATIVE_ENABLED != 0 {
+            z_n_prime =
+                params.derivative(formula, y_f32) * z_n_prime + Complex::new(params.scale, 0.0);
+        }
+        delta_n =
+            Complex::from(formula.delta_f64(x_n.into(), delta_n.into(), params.power)) + delta_0;
+        step += 1;
+    }
+
+    let y_n = reference.point(ref_iteration) + delta_n;
+    let radius_squared = y_n.norm_squared() as f32;
+    if radius_squared < 4.0 {
+        let mut distance_estimate = -1.0;
+        let mut attractor = [0.0; 3];
+        if flags & (INTERIOR_DISTANCE_ENABLED | ATTRACTOR_ENABLED) != 0 && !glitched {
+            let cycle = perturbed_attracting_cycle(
+                params,
+                reference,
+                delta_n,
+                ref_iteration,
+                delta_0,
+                step,
+            );
+            if let Some((_, derivatives)) = cycle
+                && flags & INTERIOR_DISTANCE_ENABLED != 0
+            {
+                distance_estimate = derivatives.interior_distance_estimate(params.zoom);
+            }
+            attractor = attractor_values(cycle);
+        }
+        PixelData {
+            step: -(min_iter as i32),
+            orbits,
+            stripes: stripes.map(|stripe| stripe / step as f32),
+            z: attractor,
+            dz: [total_angle, distance_estimate, 0.0],
+            glitched,
+        }
+    } else {
+        let frac = step_frac(radius_squared, ESCAPE_RADIUS, params.power as f32);
+        let [y_x, y_y] = y_n.to_f32();
+        let [dz_x, dz_y] = z_n_prime.to_f32();
+        PixelData {
+            step: step as i32,
+            orbits,
+            stripes: escaped_stripes(stripes, prev_stripes, step, frac),
+            z: [y_x, y_y, params.zoom],
+            dz: [dz_x, dz_y, params.zoom],
+            glitched,
+        }
+    }
+}
@@ -0,0 +1,494 @@
+/*!
+# CPU Rendering
+
+A renderer for machines without a usable GPU. It mirrors the f64 compute shaders and the
+color shader, and fills the same step, orbit trap, stripe, z and dz data as the GPU
+buffers, so it also serves as a deterministic reference for the shaders.
+
+Pixels are computed on a thread pool. Only the escape time mode is supported, at zooms
+up to [`AlgorithmPolicy::max_zoom_f64`], where the pixel offsets get too small for an f64.
+ */
+
+mod color;
+mod compute;
+
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use color::{ColorInputs, color_pixel};
+use color_eyre::eyre::{Result, eyre};
+use compute::{Complex, PixelData, PixelParams, Reference, direct_pixel, perturbed_pixel};
+use eframe::wgpu::Extent3d;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use super::probe::{FloatExp, probe, probe_critical};
+use super::{
+    glitch_reference, is_auto_reference, is_cancelled, nucleus_reference, orbit_f64,
+    save_image_data,
+};
+use crate::types::{
+    AlgorithmPolicy, ComplexPoint, Image, IterationStats, RenderMode, StatusMessage,
+};
+
+/// The number of pixels computed between progress updates and checks for cancellation
+const PIXEL_BATCH_SIZE: usize = 1 << 14;
+
+/// The data the CPU renderer writes for each pixel, matching the GPU buffers of the same names
+#[derive(Debug, Default)]
+pub struct CpuBuffers {
+    pub step: Vec<i32>,
+    pub orbits: Vec<[f32; 4]>,
+    pub stripes: Vec<[f32; 4]>,
+    pub z: Vec<[f32; 3]>,
+    pub dz: Vec<[f32; 3]>,
+    /// The glitch correction pass in which each pixel was last computed,
+    /// or the following pass if the pixel was glitched
+    pub glitches: Vec<u32>,
+}
+
+impl CpuBuffers {
+    fn resize(&mut self, size: usize) {
+        *self = Self {
+            step: vec![0; size],
+            orbits: vec![[0.0; 4]; size],
+            stripes: vec![[0.0; 4]; size],
+            z: vec![[0.0; 3]; size],
+            dz: vec![[0.0; 3]; size],
+            glitches: vec![0; size],
+        };
+    }
+
+    fn write(&mut self, index: usize, pixel: PixelData, glitch_pass: u32) {
+        self.step[index] = pixel.step;
+        self.orbits[index] = pixel.orbits;
+        self.stripes[index] = pixel.stripes;
+        self.z[index] = pixel.z;
+        self.dz[index] = pixel.dz;
+        self.glitches[index] = glitch_pass + pixel.glitched as u32;
+    }
+}
+
+/// # CpuData
+/// The state of the CPU renderer: its thread pool, the per-pixel data, and the final image
+pub struct CpuData {
+    pool: ThreadPool,
+    /// The zoom levels at which the direct and perturbed algorithms are used
+    pub algorithm_policy: AlgorithmPolicy,
+    /// The maximum number of extra references used to correct glitches
+    pub max_glitch_references: u32,
+    pub size: Extent3d,
+    pub buffers: CpuBuffers,
+    /// The colored image, in the RGBA layout of the GPU texture
+    pub pixels: Vec<u8>,
+}
+
+impl CpuData {
+    /// Creates the renderer with a pool of `threads` threads, or one per CPU if it is 0
+    pub fn new(
+        threads: usize,
+        algorithm_policy: AlgorithmPolicy,
+        max_glitch_references: u32,
+    ) -> Result<Self> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("corgi cpu renderer {i}"))
+            .build()?;
+        Ok(Self {
+            pool,
+            algorithm_policy,
+            max_glitch_references,
+            size: Extent3d::default(),
+            buffers: CpuBuffers::default(),
+            pixels: Vec::new(),
+        })
+    }
+
+    /// The number of threads in the pool
+    pub fn threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// Checks that the image can be rendered on the CPU, which only supports the escape
+    /// time mode at zooms below [`AlgorithmPolicy::max_zoom_f64`]
+    pub fn check_supported(&self, image: &Image) -> Result<()> {
+        if image.render_mode != RenderMode::Escape {
+            return Err(eyre!(
+                "The CPU renderer only supports the escape time render mode, so this image needs a GPU"
+            ));
+        }
+        if image.viewport.zoom >= self.algorithm_policy.max_zoom_f64 {
+            return Err(eyre!(
+                "The CPU renderer only supports zooms below {}, but the image is at zoom {:.1}, so it needs a GPU",
+                self.algorithm_policy.max_zoom_f64,
+                image.viewport.zoom
+            ));
+        }
+        Ok(())
+    }
+
+    /// Computes the given pixels on the thread pool, writing them to the buffers.
+    /// Stops between batches of pixels once the render is cancelled.
+    fn compute_pixels(
+        &mut self,
+        pixels: &[usize],
+        glitch_pass: u32,
+        compute: impl Fn(u32, u32) -> PixelData + Sync,
+        cancelled: &AtomicBool,
+        status_callback: &mut impl FnMut(StatusMessage),
+    ) {
+        let width = self.size.width as usize;
+        let mut done = 0;
+        for batch in pixels.chunks(PIXEL_BATCH_SIZE) {
+            if cancelled.load(Ordering::Relaxed) {
+                return;
+            }
+            let results: Vec<PixelData> = self.pool.install(|| {
+                batch
+                    .par_iter()
+                    .map(|&i| compute((i % width) as u32, (i / width) as u32))
+                    .collect()
+            });
+            for (&i, pixel) in batch.iter().zip(results) {
+                self.buffers.write(i, pixel, glitch_pass);
+            }
+            done += batch.len();
+            status_callback(StatusMessage::Progress(
+                format!("Computing pixel {done} of {}", pixels.len()),
+                done as f64 / pixels.len() as f64,
+            ));
+        }
+    }
+
+    /// Colors every pixel from the buffers
+    fn color_pixels(&mut self, image: &Image) {
+        let inputs = ColorInputs::from(image);
+        let width = self.size.width as usize;
+        let buffers = &self.buffers;
+        let pixels = &mut self.pixels;
+        self.pool.install(|| {
+            pixels
+                .par_chunks_exact_mut(4)
+                .enumerate()
+                .for_each(|(i, pixel)| {
+                    pixel.copy_from_slice(&color_pixel(
+                        &inputs,
+                        buffers,
+                        (i % width) as u32,
+                        (i / width) as u32,
+                    ));
+                });
+        });
+    }
+}
+
+/// The reference point of the perturbed algorithm: the nearest minibrot nucleus in the
+/// automatic mode, as for the GPU renderer, or the probe location
+fn select_reference(image: &Image, cancelled: &AtomicBool) -> ComplexPoint {
+    if is_auto_reference(image) {
+        nucleus_reference(image, cancelled)
+    } else {
+        image.probe_location.clone()
+    }
+}
+
+/// Probes the reference orbits of a reference point
+fn probe_reference(
+    image: &Image,
+    location: &ComplexPoint,
+    critical: &[[FloatExp; 2]],
+    cancelled: &AtomicBool,
+) -> Reference {
+    let viewport = &image.viewport;
+    let orbit: Vec<[FloatExp; 2]> = probe(
+        location,
+        image.max_iter,
+        viewport.zoom,
+        &image.fractal_kind,
+        &image.formulas(),
+        cancelled,
+    );
+    let (x, y) = viewport.coords_to_px_offset(&location.x, &location.y);
+    Reference {
+        orbit: orbit_f64(&[orbit.as_slice(), critical].concat())
+            .into_iter()
+            .map(|[re, im]| Complex::new(re, im))
+            .collect(),
+        probe_len: orbit.len(),
+        critical_len: critical.len(),
+        location: Complex::new(location.x.to_f64(), location.y.to_f64()),
+        offset: (x / viewport.width as f64, y / viewport.height as f64),
+    }
+}
+
+/// Renders the image on the CPU, like [`render_image`](super::render_image) without
+/// the caching between renders. Returns the escape iterations of the image, or `None`
+/// if the render was cancelled, reporting [`StatusMessage::Cancelled`].
+///
+/// Returns an error for the images that [`CpuData::check_supported`] rejects.
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::sync::atomic::AtomicBool;
+///
+/// use corgi::image_gen::{CpuData, render_image_cpu};
+/// use corgi::types::{AlgorithmPolicy, Image};
+///
+/// let mut image = Image::default();
+/// image.viewport.width = 32;
+/// image.viewport.height = 24;
+/// image.max_iter = 200;
+/// let mut cpu_data = CpuData::new(2, AlgorithmPolicy::default(), 4).unwrap();
+/// let cancelled = Arc::new(AtomicBool::new(false));
+/// let stats = render_image_cpu(&mut cpu_data, &image, cancelled, |_| {}).unwrap();
+///
+/// // the view of the whole set contains both escaping and internal points
+/// let stats = stats.unwrap();
+/// assert_eq!(stats.pixels, 32 * 24);
+/// assert!(stats.unescaped > 0 && stats.unescaped < stats.pixels);
+/// assert_eq!(cpu_data.pixels.len(), 32 * 24 * 4);
+///
+/// // a cancelled render stops before computing the pixels
+/// let cancelled = Arc::new(AtomicBool::new(true));
+/// assert!(render_image_cpu(&mut cpu_data, &image, cancelled, |_| {}).unwrap().is_none());
+/// ```
+///
+/// At zooms where the direct algorithm is still accurate, the perturbed one computes the
+/// same steps:
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::sync::atomic::AtomicBool;
+///
+/// use corgi::image_gen::{CpuData, render_image_cpu};
+/// use corgi::types::{AlgorithmPolicy, ComplexPoint, Image};
+/// use rug::Float;
+///
+/// let mut image = Image::default();
+/// image.viewport.width = 64;
+/// image.viewport.height = 48;
+/// image.viewport.zoom = 14.0;
+/// image.viewport.center = ComplexPoint {
+///     x: Float::with_val(64, -1.25066),
+///     y: Float::with_val(64, 0.02012),
+/// };
+/// image.max_iter = 500;
+/// let render = |algorithm_policy| {
+///     let mut cpu_data = CpuData::new(2, algorithm_policy, 4).unwrap();
+///     let cancelled = Arc::new(AtomicBool::new(false));
+///     render_image_cpu(&mut cpu_data, &image, cancelled, |_| {}).unwrap();
+///     cpu_data.buffers.step
+/// };
+/// let direct = render(AlgorithmPolicy::default());
+/// let perturbed = render(AlgorithmPolicy {
+///     perturbation_zoom_f64: 0.0,
+///     ..Default::default()
+/// });
+///
+/// assert!(direct.iter().any(|&step| step > 0) && direct.iter().any(|&step| step <= 0));
+/// let differing = direct.iter().zip(&perturbed).filter(|(a, b)| a != b).count();
+/// assert!(differing * 100 <= direct.len());
+/// ```
+///
+/// The steps and colors match those of the f64 compute shaders and the color shader.
+/// This is skipped on machines without a GPU that supports f64.
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::sync::atomic::AtomicBool;
+///
+/// use corgi::image_gen::{
+///     Constants, CpuData, GPUData, ReferenceOrbit, SharedState, get_device_and_queue,
+///     render_image, render_image_cpu,
+/// };
+/// use corgi::types::{Algorithm, AlgorithmPolicy, Image};
+/// use pollster::FutureExt;
+///
+/// let Ok((device, queue)) = get_device_and_queue().block_on() else {
+///     return;
+/// };
+/// let mut image = Image::default();
+/// image.viewport.width = 64;
+/// image.viewport.height = 48;
+/// image.max_iter = 500;
+/// // the direct f64 shader is used at every zoom of the test
+/// let algorithm_policy = AlgorithmPolicy {
+///     perturbation_zoom: f64::NEG_INFINITY,
+///     ..Default::default()
+/// };
+/// let mut gpu_data = GPUData::init(
+///     &image.viewport,
+///     image.max_iter as usize,
+///     SharedState::new(device, queue),
+///     "Test",
+///     Constants {
+///         iter_batch_size: 1000,
+///         max_glitch_references: 0,
+///         algorithm_policy: algorithm_policy.clone(),
+///         orbit_cache: None,
+///     },
+/// );
+/// if gpu_data.algorithm(&image) != Algorithm::Directf64 {
+///     return;
+/// }
+/// let cancelled = Arc::new(AtomicBool::new(false));
+/// let mut probe = ReferenceOrbit::default();
+/// render_image(&mut gpu_data, &mut probe, &image, None, cancelled.clone(), |_| {});
+/// let mut cpu_data = CpuData::new(2, algorithm_policy, 0).unwrap();
+/// render_image_cpu(&mut cpu_data, &image, cancelled, |_| {}).unwrap();
+///
+/// let pixels = cpu_data.buffers.step.len();
+/// let steps = gpu_data.get_step_data().unwrap();
+/// let differing = cpu_data.buffers.step.iter().zip(&steps).filter(|(a, b)| a != b).count();
+/// assert!(differing * 100 <= pixels);
+///
+/// // the colors are computed in f32, which may round differently on the GPU
+/// let colors = gpu_data.get_texture_data().unwrap();
+/// let differing = cpu_data
+///     .pixels
+///     .chunks_exact(4)
+///     .zip(colors.chunks_exact(4))
+///     .filter(|(a, b)| a.iter().zip(*b).any(|(a, b)| a.abs_diff(*b) > 2))
+///     .count();
+/// assert!(differing * 100 <= pixels);
+/// ```
+pub fn render_image_cpu(
+    cpu_data: &mut CpuData,
+    image: &Image,
+    cancelled: Arc<AtomicBool>,
+    mut status_callback: impl FnMut(StatusMessage),
+) -> Result<Option<IterationStats>> {
+    cpu_data.check_supported(image)?;
+    let viewport = &image.viewport;
+    let perturbed = viewport.zoom >= cpu_data.algorithm_policy.perturbation_zoom_f64;
+
+    let size: Extent3d = viewport.into();
+    let pixel_count = (size.width * size.height) as usize;
+    cpu_data.size = size;
+    cpu_data.buffers.resize(pixel_count);
+    cpu_data.pixels = vec![0; pixel_count * 4];
+
+    let aspect_scale = viewport.aspect_scale();
+    let julia = image.fractal_kind.julia_point();
+    let params = PixelParams {
+        width: size.width,
+        height: size.height,
+        max_iter: image.max_iter as u32,
+        power: image.fractal_kind.power(),
+        flags: image.get_flags(),
+        formulas: image
+            .formulas()
+            .into_iter()
+            .map(|formula| formula.formula())
+            .collect(),
+        zoom: viewport.zoom as f32,
+        scale: (-viewport.zoom).exp2(),
+        aspect: (aspect_scale.x as f64, aspect_scale.y as f64),
+        transform: viewport.linear_transform(),
+        center: Complex::new(viewport.center.x.to_f64(), viewport.center.y.to_f64()),
+        julia: julia
+            .map(|point| Complex::new(point.x.to_f64(), point.y.to_f64()))
+            .unwrap_or_default(),
+    };
+    let all_pixels: Vec<usize> = (0..pixel_count).collect();
+
+    if !perturbed {
+        cpu_data.compute_pixels(
+            &all_pixels,
+            0,
+            |x, y| direct_pixel(&params, x, y),
+            &cancelled,
+            &mut status_callback,
+        );
+        if is_cancelled(&cancelled, &mut status_callback) {
+            return Ok(None);
+        }
+    } else {
+        status_callback(StatusMessage::Progress("Probing point".into(), 0.0));
+        let critical: Vec<[FloatExp; 2]> = probe_critical(
+            image.max_iter,
+            viewport.zoom,
+            &image.fractal_kind,
+            &image.formulas(),
+            &cancelled,
+        );
+        let reference = probe_reference(
+            image,
+            &select_reference(image, &cancelled),
+            &critical,
+            &cancelled,
+        );
+        cpu_data.compute_pixels(
+            &all_pixels,
+            0,
+            |x, y| perturbed_pixel(&params, &reference, x, y),
+            &cancelled,
+            &mut status_callback,
+        );
+        if is_cancelled(&cancelled, &mut status_callback) {
+            return Ok(None);
+        }
+
+        // re-compute the glitched pixels with extra references inside the glitched areas
+        let mut initial_glitches = 0;
+        let mut pass = 0;
+        let glitches = loop {
+            let glitched: Vec<usize> = (0..pixel_count)
+                .filter(|&i| cpu_data.buffers.glitches[i] == pass + 1)
+                .collect();
+            let glitches = glitched.len();
+            if pass == 0 {
+                initial_glitches = glitches;
+            }
+            if glitches == 0 || pass >= cpu_data.max_glitch_references {
+                break glitches;
+            }
+            pass += 1;
+            status_callback(StatusMessage::Progress(
+                format!("Correcting {glitches} glitched pixels"),
+                0.0,
+            ));
+            let location = glitch_reference(&glitched, size, viewport);
+            let reference = probe_reference(image, &location, &critical, &cancelled);
+            cpu_data.compute_pixels(
+                &glitched,
+                pass,
+                |x, y| perturbed_pixel(&params, &reference, x, y),
+                &cancelled,
+                &mut status_callback,
+            );
+            if is_cancelled(&cancelled, &mut status_callback) {
+                return Ok(None);
+            }
+        };
+        if initial_glitches > 0 {
+            status_callback(StatusMessage::GlitchesCorrected(
+                initial_glitches - glitches,
+                glitches,
+            ));
+        }
+    }
+
+    status_callback(StatusMessage::Progress("Rendering Colors".into(), 0.0));
+    cpu_data.color_pixels(image);
+    Ok(Some(IterationStats::from_steps(
+        &cpu_data.buffers.step,
+        image.max_iter,
+    )))
+}
+
+/// Saves the image rendered on the CPU to a file, like [`save_to_file`](super::save_to_file)
+pub fn save_to_file_cpu(
+    cpu_data: &CpuData,
+    image_settings: &Image,
+    path: &Path,
+    status_callback: impl FnMut(StatusMessage),
+) {
+    save_image_data(
+        cpu_data.pixels.clone(),
+        image_settings,
+        path,
+        status_callback,
+    );
+}
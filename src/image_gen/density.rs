@@ -0,0 +1,354 @@
+/*!
+# Orbit Density
+
+Renders the [`RenderMode::Density`](crate::types::RenderMode::Density) mode, which samples
+random points and counts how many times the orbits of the escaping ones pass through each
+pixel. Each color channel only counts the orbits which escape within its band of iterations,
+so the channels show the shapes of orbits of different lengths, as in the Nebulabrot.
+
+The GPU shader and the CPU reference in [`sample_density`] sample the same points, so the
+reference can be used to check the output of the shader.
+ */
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use eframe::wgpu::{self, Extent3d};
+use rug::Float;
+
+use super::gpu_setup::{DensityPipelines, GPUData, SharedState};
+use super::hybrid_params;
+use crate::types::{DensityOptions, DensityParams, Image, StatusMessage};
+
+/// The squared radius outside of which orbits escape
+const ESCAPE_RADIUS_SQUARED: f32 = 4.0;
+/// Points are sampled from the square from -SAMPLE_RADIUS to SAMPLE_RADIUS,
+/// which contains every point that does not escape immediately
+const SAMPLE_RADIUS: f32 = 2.0;
+/// The number of samples in each workgroup of the density shader
+const WORKGROUP_SIZE: u32 = 64;
+/// The largest number of workgroups in one dimension of a dispatch
+const MAX_WORKGROUPS: u32 = 65535;
+
+/// The parameters of the density shader for a batch of samples
+fn density_params(
+    image: &Image,
+    options: &DensityOptions,
+    sample_offset: u32,
+    sample_count: u32,
+) -> DensityParams {
+    let texture_size: Extent3d = (&image.viewport).into();
+    let (hybrid, hybrid_len) = hybrid_params(image);
+    let julia_point = match image.fractal_kind.julia_point() {
+        None => (0.0, 0.0),
+        Some(pt) => (pt.x.to_f32(), pt.y.to_f32()),
+    };
+    let [red, green, blue] = options.bands;
+    DensityParams {
+        width: texture_size.width,
+        height: texture_size.height,
+        sample_offset,
+        sample_count,
+        seed: options.seed,
+        max_iter: options.max_iter(),
+        power: image.fractal_kind.power(),
+        hybrid,
+        band_min: [red[0], green[0], blue[0], 0],
+        band_max: [red[1], green[1], blue[1], 0],
+        hybrid_len,
+        x: image.viewport.center.x.to_f32(),
+        y: image.viewport.center.y.to_f32(),
+        zoom: image.viewport.zoom as f32,
+        julia_x: julia_point.0,
+        julia_y: julia_point.1,
+        exposure: options.exposure,
+        gamma: options.gamma,
+        inverse_transform: image.viewport.inverse_transform_f32(),
+    }
+}
+
+/// Runs the density shader on the GPU, sampling every orbit of the image into the density
+/// buffer. Like the compute step, this is split into batches to avoid GPU timeouts.
+pub(super) fn run_density_step(
+    image: &Image,
+    options: &DensityOptions,
+    gpu_data: &GPUData,
+    cancelled: Arc<AtomicBool>,
+    status_callback: &mut impl FnMut(StatusMessage),
+) {
+    let GPUData {
+        shared: SharedState { device, queue, .. },
+        bind_groups,
+        buffers,
+        constants,
+        density_pipelines: DensityPipelines { density, max, .. },
+        ..
+    } = gpu_data;
+    let texture_size: Extent3d = (&image.viewport).into();
+
+    let mut encoder =
+        device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    encoder.clear_buffer(&buffers.density, 0, None);
+    queue.submit(Some(encoder.finish()));
+
+    // each sample takes up to twice the iterations of a pixel in the compute step,
+    // since escaping orbits are iterated again to count their points
+    let batch_size = (image.viewport.buffer_size() as u64 * constants.iter_batch_size
+        / (2 * options.max_iter().max(1) as u64))
+        .clamp(
+            WORKGROUP_SIZE as u64,
+            (WORKGROUP_SIZE * MAX_WORKGROUPS) as u64,
+        ) as u32;
+    let mut sample_offset = 0;
+    while sample_offset < options.samples {
+        if cancelled.load(Ordering::Relaxed) {
+            return;
+        }
+        let sample_count = batch_size.min(options.samples - sample_offset);
+        queue.write_buffer(
+            &buffers.density_parameters,
+            0,
+            bytemuck::cast_slice(&[density_params(image, options, sample_offset, sample_count)]),
+        );
+
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: None,
+                timestamp_writes: None,
+            });
+            cpass.set_bind_group(0, &bind_groups.density_buffers, &[]);
+            cpass.set_bind_group(1, &bind_groups.render_texture, &[]);
+            cpass.set_bind_group(2, &bind_groups.density_parameters, &[]);
+            cpass.set_pipeline(density);
+            cpass.dispatch_workgroups(sample_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+        let si = queue.submit(Some(encoder.finish()));
+        let _ = device.poll(wgpu::PollType::Wait {
+            submission_index: Some(si),
+            timeout: Some(Duration::from_secs(1)),
+        });
+
+        sample_offset += sample_count;
+        status_callback(StatusMessage::Progress(
+            format!("Sampling orbit {} of {}", sample_offset, options.samples),
+            sample_offset as f64 / options.samples as f64,
+        ));
+    }
+
+    // find the largest count of each channel for the tone mapping
+    let mut encoder =
+        device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    {
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None,
+        });
+        cpass.set_bind_group(0, &bind_groups.density_buffers, &[]);
+        cpass.set_bind_group(1, &bind_groups.render_texture, &[]);
+        cpass.set_bind_group(2, &bind_groups.density_parameters, &[]);
+        cpass.set_pipeline(max);
+        cpass.dispatch_workgroups(
+            texture_size.width.div_ceil(16),
+            texture_size.height.div_ceil(16),
+            1,
+        );
+    }
+    queue.submit(Some(encoder.finish()));
+}
+
+/// Maps the counts of the density buffer to the colors of the image
+pub(super) fn run_tone_map_step(image: &Image, options: &DensityOptions, gpu_data: &GPUData) {
+    let GPUData {
+        shared: SharedState { device, queue, .. },
+        bind_groups,
+        buffers,
+        density_pipelines: DensityPipelines { tone_map, .. },
+        ..
+    } = gpu_data;
+    let texture_size: Extent3d = (&image.viewport).into();
+    queue.write_buffer(
+        &buffers.density_parameters,
+        0,
+        bytemuck::cast_slice(&[density_params(image, options, 0, 0)]),
+    );
+
+    let mut encoder =
+        device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    {
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None,
+        });
+        cpass.set_bind_group(0, &bind_groups.density_buffers, &[]);
+        cpass.set_bind_group(1, &bind_groups.render_texture, &[]);
+        cpass.set_bind_group(2, &bind_groups.density_parameters, &[]);
+        cpass.set_pipeline(tone_map);
+        cpass.dispatch_workgroups(
+            texture_size.width.div_ceil(16),
+            texture_size.height.div_ceil(16),
+            1,
+        );
+    }
+    let si = queue.submit(Some(encoder.finish()));
+    let _ = device.poll(wgpu::PollType::Wait {
+        submission_index: Some(si),
+        timeout: Some(Duration::from_secs(1)),
+    });
+}
+
+/// The PCG hash, from "Hash Functions for GPU Rendering" by Jarzynski and Olano
+fn pcg_hash(v: u32) -> u32 {
+    let state = v.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+/// The sampled point with the given index, which is the same as the one of the density shader
+fn sample_point(index: u32, seed: u32) -> (f32, f32) {
+    let x = pcg_hash(index ^ pcg_hash(seed));
+    let y = pcg_hash(x);
+    let unit = |v: u32| (v >> 8) as f32 / 16777216.0;
+    (
+        (unit(x) * 2.0 - 1.0) * SAMPLE_RADIUS,
+        (unit(y) * 2.0 - 1.0) * SAMPLE_RADIUS,
+    )
+}
+
+/// Counts the orbit densities of an image on the CPU, as the density shader does. The points
+/// are iterated with the precision of an f32, though the results may still differ slightly
+/// from the shader for orbits that escape close to the radius.
+///
+/// Returns the red, green and blue counts of each pixel, in rows from the top of the
+/// texture coordinates (the lowest imaginary part).
+///
+/// ```
+/// use corgi::image_gen::{sample_density, tone_map};
+/// use corgi::types::{DensityOptions, Image, Viewport};
+///
+/// let image = Image {
+///     viewport: Viewport {
+///         width: 16,
+///         height: 16,
+///         ..Default::default()
+///     },
+///     ..Default::default()
+/// };
+/// let options = DensityOptions {
+///     samples: 2000,
+///     bands: [[0, 50], [0, 20], [0, 5]],
+///     ..Default::default()
+/// };
+/// let counts = sample_density(&image, &options);
+/// assert_eq!(counts.len(), 16 * 16);
+/// // the bands are nested, so each channel counts a subset of the orbits of the previous one
+/// assert!(counts.iter().all(|[r, g, b]| r >= g && g >= b));
+/// assert!(counts.iter().any(|[r, _, _]| *r > 0));
+///
+/// let colors = tone_map(&counts, &options);
+/// assert_eq!(colors.len(), 16 * 16 * 4);
+/// assert!(colors.contains(&255));
+/// ```
+pub fn sample_density(image: &Image, options: &DensityOptions) -> Vec<[u32; 3]> {
+    const PRECISION: u32 = 24;
+    let params = density_params(image, options, 0, options.samples);
+    let formulas = image.formulas();
+    let (width, height) = (params.width as f32, params.height as f32);
+    let aspect = width / height;
+    let aspect_scale = if aspect < 1.0 {
+        (aspect, 1.0)
+    } else {
+        (1.0, 1.0 / aspect)
+    };
+    let scale = 2.0 * (-params.zoom).exp2();
+    let [a, c, b, d] = params.inverse_transform;
+    let pixel_index = |x: f32, y: f32| {
+        let (x, y) = (x - params.x, y - params.y);
+        let (x, y) = (a * x + b * y, c * x + d * y);
+        let px = (x / (scale * aspect_scale.0) + 0.5) * width;
+        let py = (y / (scale * aspect_scale.1) + 0.5) * height;
+        (px >= 0.0 && py >= 0.0 && px < width && py < height)
+            .then(|| px as usize + py as usize * params.width as usize)
+    };
+
+    let mut counts = vec![[0; 3]; params.width as usize * params.height as usize];
+    for index in 0..options.samples {
+        let (x, y) = sample_point(index, options.seed);
+        let point = (Float::with_val(PRECISION, x), Float::with_val(PRECISION, y));
+        let zero = (Float::new(PRECISION), Float::new(PRECISION));
+        let (z_0, c) = match image.fractal_kind.julia_point() {
+            None => (zero, point),
+            Some(_) => (
+                point,
+                (
+                    Float::with_val(PRECISION, params.julia_x),
+                    Float::with_val(PRECISION, params.julia_y),
+                ),
+            ),
+        };
+        // points that do not escape are not counted
+        let mut z = z_0;
+        let mut orbit = Vec::new();
+        for n in 0..params.max_iter as usize {
+            let (re, im) = formulas[n % formulas.len()]
+                .formula()
+                .step(&z.0, &z.1, params.power);
+            z = (
+                Float::with_val(PRECISION, re + &c.0),
+                Float::with_val(PRECISION, im + &c.1),
+            );
+            let (re, im) = (z.0.to_f32(), z.1.to_f32());
+            orbit.push((re, im));
+            if re * re + im * im > ESCAPE_RADIUS_SQUARED {
+                break;
+            }
+        }
+        let escape = orbit.len() as u32;
+        if orbit
+            .last()
+            .is_none_or(|&(re, im)| re * re + im * im <= ESCAPE_RADIUS_SQUARED)
+        {
+            continue;
+        }
+        let counted: Vec<bool> = (0..3)
+            .map(|channel| escape >= params.band_min[channel] && escape < params.band_max[channel])
+            .collect();
+        for &(re, im) in &orbit {
+            let Some(pixel) = pixel_index(re, im) else {
+                continue;
+            };
+            for channel in 0..3 {
+                if counted[channel] {
+                    counts[pixel][channel] += 1;
+                }
+            }
+        }
+    }
+    counts
+}
+
+/// Maps the counts of [`sample_density`] to RGBA colors, as the tone mapping of the
+/// density shader does
+pub fn tone_map(counts: &[[u32; 3]], options: &DensityOptions) -> Vec<u8> {
+    let max = (0..3).map(|channel| {
+        counts
+            .iter()
+            .map(|count| count[channel])
+            .max()
+            .unwrap_or_default()
+            .max(1)
+    });
+    let max: Vec<u32> = max.collect();
+    counts
+        .iter()
+        .flat_map(|count| {
+            let channel = |i: usize| {
+                let value = (count[i] as f32 / max[i] as f32 * options.exposure).clamp(0.0, 1.0);
+                (value.powf(1.0 / options.gamma) * 255.0).round() as u8
+            };
+            [channel(0), channel(1), channel(2), 255]
+        })
+        .collect()
+}
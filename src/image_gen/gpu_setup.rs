@@ -18,7 +18,14 @@ use eframe::wgpu::{
 };
 use wgpu::{ExperimentalFeatures, ShaderModule};
 
-use crate::types::{ColorParams, ComputeParams, MAX_GRADIENT_STOPS, RenderParams, Viewport};
+use super::orbit_cache::OrbitCache;
+use super::probe_window::PROBE_WINDOW_WORDS;
+use crate::types::{
+    Algorithm, AlgorithmPolicy, BlaEntry, BlaHeader, ColorParams, ComplexPoint, ComputeParams,
+    DERIVATIVE, DIRECT_STEP, DIRECT_STEP_F64, DensityParams, Image, MAX_GRADIENT_STOPS,
+    NewtonParams, PERTURBED_STEP, PERTURBED_STEP_F64, RenderParams, SeriesParams, Viewport,
+    assemble_formulas, enable_f64,
+};
 
 /// Contains GPU state that can be shared between all image generation
 /// contexts.
@@ -32,8 +39,20 @@ pub struct SharedState {
     pub direct_f32_shader: ShaderModule,
     /// The shader module for the perturbation formula f32 shader
     pub perturbed_f32_shader: ShaderModule,
+    /// The shader module for the perturbation formula shader with emulated double precision
+    pub perturbed_f32x2_shader: ShaderModule,
+    /// The shader module for the directly calculated f64 shader, if the device supports f64
+    pub direct_f64_shader: Option<ShaderModule>,
+    /// The shader module for the perturbation formula f64 shader, if the device supports f64
+    pub perturbed_f64_shader: Option<ShaderModule>,
     /// The shader module for the color shader
     pub color_shader: ShaderModule,
+    /// The shader module for the orbit density shader
+    pub density_shader: ShaderModule,
+    /// The shader module for the Lyapunov exponent shader
+    pub lyapunov_shader: ShaderModule,
+    /// The shader module for the Newton and Nova fractal shader
+    pub newton_shader: ShaderModule,
 }
 
 /// A struct containing all of the GPU handles for the application
@@ -49,8 +68,23 @@ pub struct GPUData {
     pub direct_f32_pipeline: ComputePipeline,
     /// The compute pipeline for the perturbation formula f32 shader
     pub perturbed_f32_pipeline: ComputePipeline,
+    /// The compute pipeline for the perturbation formula shader with emulated double precision
+    pub perturbed_f32x2_pipeline: ComputePipeline,
+    /// The compute pipeline for the directly calculated f64 shader, if the device supports f64
+    pub direct_f64_pipeline: Option<ComputePipeline>,
+    /// The compute pipeline for the perturbation formula f64 shader, if the device supports f64
+    pub perturbed_f64_pipeline: Option<ComputePipeline>,
     /// The color pipeline for the color shader
     pub color_pipeline: ComputePipeline,
+    /// The pipelines of the density shader, which accumulate the orbit densities,
+    /// find the largest density, and map the densities to colors
+    pub density_pipelines: DensityPipelines,
+    /// The compute pipeline for the Lyapunov exponent shader, which writes
+    /// to the same buffers as the escape time shaders
+    pub lyapunov_pipeline: ComputePipeline,
+    /// The compute pipeline for the Newton and Nova fractal shader, which writes
+    /// to the same buffers as the escape time shaders
+    pub newton_pipeline: ComputePipeline,
     /// A struct containing all of the buffers used by the GPU
     pub buffers: Buffers,
     /// A struct containing all of the bind groups used by the GPU
@@ -59,6 +93,17 @@ pub struct GPUData {
     pub shared: SharedState,
     /// A group of constants used to parameterize calculations
     pub constants: Constants,
+    /// The automatically selected reference point and the zoom it was searched at
+    pub auto_reference: Option<(ComplexPoint, f64)>,
+    /// The algorithm used for the last computed image
+    pub algorithm: Option<Algorithm>,
+}
+
+/// The compute pipelines for each entry point of the density shader
+pub struct DensityPipelines {
+    pub density: ComputePipeline,
+    pub max: ComputePipeline,
+    pub tone_map: ComputePipeline,
 }
 
 /// A struct containing all of the buffers used by the GPU
@@ -66,18 +111,38 @@ pub struct GPUData {
 pub struct Buffers {
     // compute input
     pub probe: Buffer,
+    pub series: Buffer,
+    pub bla: Buffer,
     pub delta_n: Buffer,
     pub delta_prime: Buffer,
     // parameters
     pub compute_parameters: Buffer,
+    pub newton_parameters: Buffer,
     pub external_coloring: Buffer,
     pub internal_coloring: Buffer,
     pub render_parameters: Buffer,
     // intermediate data
     pub step: Buffer,
+    pub step_readback: Buffer,
     pub orbits: Buffer,
     pub stripes: Buffer,
     pub gradient: Buffer,
+    // glitch detection
+    pub glitches: Buffer,
+    pub glitches_readback: Buffer,
+    // iteration state that does not fit in the f32 buffers,
+    // used by the f64 and emulated double precision shaders
+    pub extended_state: Buffer,
+    // the iteration state of the points that did not escape, for resuming at a higher max iteration
+    pub resume_state: Buffer,
+    // the progress of each point through the windows of a streamed reference orbit,
+    // and the windows requested by the points
+    pub window_steps: Buffer,
+    pub window_requests: Buffer,
+    pub window_requests_readback: Buffer,
+    // orbit densities
+    pub density: Buffer,
+    pub density_parameters: Buffer,
 }
 
 /// A struct containing all of the bind groups used by the GPU
@@ -88,6 +153,8 @@ pub struct BindGroups {
     pub render_buffers: BindGroup,
     pub render_parameters: BindGroup,
     pub render_texture: BindGroup,
+    pub density_buffers: BindGroup,
+    pub density_parameters: BindGroup,
 }
 
 /// The different types of buffers that can be created.
@@ -96,8 +163,11 @@ enum BuffType {
     ShaderOnly,
     /// A buffer that can be written to by the host, but not read.
     HostWritable,
+    /// A buffer that is only used by the shader, but can be copied to a [`BuffType::HostReadable`] buffer.
+    HostCopyable,
+    /// A buffer that is cleared by the host, and can be copied to a [`BuffType::HostReadable`] buffer.
+    HostClearable,
     /// A buffer that can be read by the host; used for the target of a copy operation.
-    #[allow(dead_code)]
     HostReadable,
     /// A uniform buffer that can be written by the host.
     Uniform,
@@ -110,6 +180,35 @@ pub struct Constants {
     /// busy for too long; that causes stuttering and possibly
     /// shader execution failures.
     pub iter_batch_size: u64,
+    /// The maximum number of extra reference points used to
+    /// re-compute glitched pixels in the perturbed algorithm.
+    pub max_glitch_references: u32,
+    /// Decides which algorithm is used at each zoom
+    pub algorithm_policy: AlgorithmPolicy,
+    /// Where computed reference orbits are stored, to skip probing
+    /// locations that were rendered before
+    pub orbit_cache: Option<OrbitCache>,
+}
+
+/// The features and limits to request from an adapter for rendering. The f64
+/// shaders are only used if the adapter supports them, and the compute shaders
+/// bind more storage buffers than the default limits allow. The largest buffers
+/// the adapter supports are requested so that fewer reference orbits are streamed.
+pub fn device_descriptor(adapter: &wgpu::Adapter) -> wgpu::DeviceDescriptor<'static> {
+    let limits = adapter.limits();
+    wgpu::DeviceDescriptor {
+        label: None,
+        required_features: adapter.features() & wgpu::Features::SHADER_F64,
+        required_limits: wgpu::Limits {
+            max_storage_buffers_per_shader_stage: limits.max_storage_buffers_per_shader_stage,
+            max_storage_buffer_binding_size: limits.max_storage_buffer_binding_size,
+            max_buffer_size: limits.max_buffer_size,
+            ..Default::default()
+        },
+        memory_hints: wgpu::MemoryHints::Performance,
+        trace: wgpu::Trace::Off,
+        experimental_features: ExperimentalFeatures::disabled(),
+    }
 }
 
 /// Selects a device and queue suitable for non-UI rendering.
@@ -126,43 +225,102 @@ pub async fn get_device_and_queue() -> Result<(Device, Queue)> {
         })
         .await?;
 
-    Ok(adapter
-        .request_device(&wgpu::DeviceDescriptor {
-            label: None,
-            required_features: wgpu::Features::empty(),
-            required_limits: wgpu::Limits::default(),
-            memory_hints: wgpu::MemoryHints::Performance,
-            trace: wgpu::Trace::Off,
-            experimental_features: ExperimentalFeatures::disabled(),
-        })
-        .await?)
+    Ok(adapter.request_device(&device_descriptor(&adapter)).await?)
 }
 
 impl SharedState {
     /// Create a new shared state object for image generation. This should
     /// only be called once in a program and cloned to share between multiple
     /// [`GPUData`] objects.
+    ///
+    /// The compute shaders include every formula registered before this is called.
     pub fn new(device: Device, queue: Queue) -> Self {
         let direct_f32_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Direct f32 Shader".to_string().as_str()),
-            source: wgpu::ShaderSource::Wgsl(wesl::include_wesl!("direct_32").into()),
+            source: wgpu::ShaderSource::Wgsl(
+                assemble_formulas(wesl::include_wesl!("direct_32"), &[DIRECT_STEP, DERIVATIVE])
+                    .into(),
+            ),
         });
 
         let perturbed_f32_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Perturbed f32 Shader".to_string().as_str()),
-            source: wgpu::ShaderSource::Wgsl(wesl::include_wesl!("perturbed_32").into()),
+            source: wgpu::ShaderSource::Wgsl(
+                assemble_formulas(
+                    wesl::include_wesl!("perturbed_32"),
+                    &[PERTURBED_STEP, DERIVATIVE],
+                )
+                .into(),
+            ),
+        });
+
+        let perturbed_f32x2_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Perturbed f32x2 Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                assemble_formulas(wesl::include_wesl!("perturbed_32x2"), &[DERIVATIVE]).into(),
+            ),
+        });
+
+        let f64_supported = device.features().contains(wgpu::Features::SHADER_F64);
+        let direct_f64_shader = f64_supported.then(|| {
+            device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Direct f64 Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    enable_f64(&assemble_formulas(
+                        wesl::include_wesl!("direct_64"),
+                        &[DIRECT_STEP_F64, DERIVATIVE],
+                    ))
+                    .into(),
+                ),
+            })
+        });
+
+        let perturbed_f64_shader = f64_supported.then(|| {
+            device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Perturbed f64 Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    enable_f64(&assemble_formulas(
+                        wesl::include_wesl!("perturbed_64"),
+                        &[PERTURBED_STEP_F64, DERIVATIVE],
+                    ))
+                    .into(),
+                ),
+            })
         });
 
         let color_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Color Shader".to_string().as_str()),
             source: wgpu::ShaderSource::Wgsl(wesl::include_wesl!("color").into()),
         });
+
+        let density_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Density Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                assemble_formulas(wesl::include_wesl!("density"), &[DIRECT_STEP]).into(),
+            ),
+        });
+
+        let lyapunov_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Lyapunov Shader"),
+            source: wgpu::ShaderSource::Wgsl(wesl::include_wesl!("lyapunov").into()),
+        });
+
+        let newton_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Newton Shader"),
+            source: wgpu::ShaderSource::Wgsl(wesl::include_wesl!("newton").into()),
+        });
         SharedState {
             device,
             queue,
             direct_f32_shader,
             perturbed_f32_shader,
+            perturbed_f32x2_shader,
+            direct_f64_shader,
+            perturbed_f64_shader,
             color_shader,
+            density_shader,
+            lyapunov_shader,
+            newton_shader,
         }
     }
 }
@@ -182,7 +340,7 @@ impl GPUData {
         let final_texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
 
         let buffers = Buffers::init(device, viewport, max_iter);
-        let (bind_groups, compute_pipeline_layout, color_pipeline_layout) =
+        let (bind_groups, compute_pipeline_layout, color_pipeline_layout, density_pipeline_layout) =
             BindGroups::init(device, &buffers, &final_texture_view);
 
         let color_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
@@ -197,45 +355,90 @@ impl GPUData {
             cache: None,
         });
 
-        let direct_f32_pipeline =
-            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                label: Some(format!("{label} Direct f32 Pipeline").as_str()),
-                layout: Some(&compute_pipeline_layout),
-                module: &shared.direct_f32_shader,
-                entry_point: Some("main_mandel"),
-                compilation_options: wgpu::PipelineCompilationOptions {
-                    constants: &[],
-                    zero_initialize_workgroup_memory: false,
-                },
-                cache: None,
-            });
-
-        let perturbed_f32_pipeline =
-            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                label: Some(format!("{label} Perturbed f32 Pipeline").as_str()),
-                layout: Some(&compute_pipeline_layout),
-                module: &shared.perturbed_f32_shader,
-                entry_point: Some("main_mandel"),
-                compilation_options: wgpu::PipelineCompilationOptions {
-                    constants: &[],
-                    zero_initialize_workgroup_memory: false,
-                },
-                cache: None,
-            });
+        let create_pipeline = |name: &str, module: &ShaderModule| {
+            Self::create_compute_pipeline(
+                device,
+                &format!("{label} {name} Pipeline"),
+                &compute_pipeline_layout,
+                module,
+                "main_mandel",
+                0,
+            )
+        };
+        let direct_f32_pipeline = create_pipeline("Direct f32", &shared.direct_f32_shader);
+        let perturbed_f32_pipeline = create_pipeline("Perturbed f32", &shared.perturbed_f32_shader);
+        let perturbed_f32x2_pipeline =
+            create_pipeline("Perturbed f32x2", &shared.perturbed_f32x2_shader);
+        let direct_f64_pipeline = shared
+            .direct_f64_shader
+            .as_ref()
+            .map(|module| create_pipeline("Direct f64", module));
+        let perturbed_f64_pipeline = shared
+            .perturbed_f64_shader
+            .as_ref()
+            .map(|module| create_pipeline("Perturbed f64", module));
+        let lyapunov_pipeline = Self::create_compute_pipeline(
+            device,
+            &format!("{label} Lyapunov Pipeline"),
+            &compute_pipeline_layout,
+            &shared.lyapunov_shader,
+            "main_lyapunov",
+            0,
+        );
+        let newton_pipeline = Self::create_compute_pipeline(
+            device,
+            &format!("{label} Newton Pipeline"),
+            &compute_pipeline_layout,
+            &shared.newton_shader,
+            "main_newton",
+            0,
+        );
+        let density_pipelines = DensityPipelines::init(
+            device,
+            label,
+            &density_pipeline_layout,
+            &shared.density_shader,
+            0,
+        );
 
         Self {
             label: label.into(),
             shared,
             direct_f32_pipeline,
             perturbed_f32_pipeline,
+            perturbed_f32x2_pipeline,
+            direct_f64_pipeline,
+            perturbed_f64_pipeline,
             color_pipeline,
+            density_pipelines,
+            lyapunov_pipeline,
+            newton_pipeline,
             texture: Arc::new(RwLock::new(texture)),
             buffers,
             bind_groups,
             constants,
+            auto_reference: None,
+            algorithm: None,
         }
     }
 
+    /// Selects the algorithm for an image with the policy of this renderer. The f64
+    /// algorithms are only used if the device and all formulas of the image support them,
+    /// and the emulated double precision shader only supports the Mandelbrot formula.
+    pub fn algorithm(&self, image: &Image) -> Algorithm {
+        let f64_supported = self.direct_f64_pipeline.is_some()
+            && self.perturbed_f64_pipeline.is_some()
+            && image
+                .formulas()
+                .iter()
+                .all(|formula| formula.supports_f64());
+        self.constants.algorithm_policy.select(
+            image.viewport.zoom,
+            f64_supported,
+            image.is_analytic(),
+        )
+    }
+
     /// Resizes the image to the new viewport and recreates necessary handles.
     /// Any objects which created a texture view of the image will need to recreate it.
     pub fn resize(&mut self, new_view: &Viewport, max_iter: usize, flags: u32) {
@@ -244,44 +447,64 @@ impl GPUData {
         let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
 
         self.buffers.resize(new_view, max_iter, &self.shared.device);
+        self.create_pipelines(&texture_view, flags);
 
-        let (bind_groups, compute_pipeline_layout, render_pipeline_layout) =
-            BindGroups::init(&self.shared.device, &self.buffers, &texture_view);
+        *self.texture.write() = texture;
+    }
 
-        self.bind_groups = bind_groups;
+    /// Grows the reference orbit buffers to `max_iter` points without clearing the image,
+    /// so that the unescaped pixels of the last render can be resumed at the new max iteration.
+    pub fn resize_probe(&mut self, max_iter: usize, flags: u32) {
+        self.buffers.resize_probe(max_iter, &self.shared.device);
+        let texture_view = self
+            .texture
+            .read()
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        self.create_pipelines(&texture_view, flags);
+    }
 
-        self.direct_f32_pipeline =
-            self.shared
-                .device
-                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                    label: Some(format!("{} Compute Pipeline", self.label).as_str()),
-                    layout: Some(&compute_pipeline_layout),
-                    module: &self.shared.direct_f32_shader,
-                    entry_point: Some("main_mandel"),
-                    compilation_options: wgpu::PipelineCompilationOptions {
-                        constants: &[("flags", flags as f64)],
-                        zero_initialize_workgroup_memory: false,
-                    },
-                    cache: None,
-                });
+    /// Recreates the bind groups for the current buffers, and the pipelines with their layouts.
+    fn create_pipelines(&mut self, texture_view: &TextureView, flags: u32) {
+        let (bind_groups, compute_pipeline_layout, render_pipeline_layout, density_pipeline_layout) =
+            BindGroups::init(&self.shared.device, &self.buffers, texture_view);
 
-        self.perturbed_f32_pipeline =
-            self.shared
-                .device
-                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                    label: Some(format!("{} Compute Pipeline", self.label).as_str()),
-                    layout: Some(&compute_pipeline_layout),
-                    module: &self.shared.perturbed_f32_shader,
-                    entry_point: Some("main_mandel"),
-                    compilation_options: wgpu::PipelineCompilationOptions {
-                        // This cast is safe, since the maximum u32 is still representable
-                        // in an f64 without rounding. This will be re-converted to a u32
-                        // in the shader.
-                        constants: &[("flags", flags as f64)],
-                        zero_initialize_workgroup_memory: false,
-                    },
-                    cache: None,
-                });
+        self.bind_groups = bind_groups;
+
+        let create_pipeline = |module: &ShaderModule| {
+            Self::create_compute_pipeline(
+                &self.shared.device,
+                &format!("{} Compute Pipeline", self.label),
+                &compute_pipeline_layout,
+                module,
+                "main_mandel",
+                flags,
+            )
+        };
+        self.direct_f32_pipeline = create_pipeline(&self.shared.direct_f32_shader);
+        self.perturbed_f32_pipeline = create_pipeline(&self.shared.perturbed_f32_shader);
+        self.perturbed_f32x2_pipeline = create_pipeline(&self.shared.perturbed_f32x2_shader);
+        self.direct_f64_pipeline = self.shared.direct_f64_shader.as_ref().map(create_pipeline);
+        self.perturbed_f64_pipeline = self
+            .shared
+            .perturbed_f64_shader
+            .as_ref()
+            .map(create_pipeline);
+        self.lyapunov_pipeline = Self::create_compute_pipeline(
+            &self.shared.device,
+            &format!("{} Lyapunov Pipeline", self.label),
+            &compute_pipeline_layout,
+            &self.shared.lyapunov_shader,
+            "main_lyapunov",
+            flags,
+        );
+        self.newton_pipeline = Self::create_compute_pipeline(
+            &self.shared.device,
+            &format!("{} Newton Pipeline", self.label),
+            &compute_pipeline_layout,
+            &self.shared.newton_shader,
+            "main_newton",
+            flags,
+        );
 
         self.color_pipeline =
             self.shared
@@ -297,8 +520,38 @@ impl GPUData {
                     },
                     cache: None,
                 });
+        self.density_pipelines = DensityPipelines::init(
+            &self.shared.device,
+            &self.label,
+            &density_pipeline_layout,
+            &self.shared.density_shader,
+            flags,
+        );
+    }
 
-        *self.texture.write() = texture;
+    /// Creates a compute pipeline for one of the compute shaders with the given flags.
+    fn create_compute_pipeline(
+        device: &Device,
+        label: &str,
+        layout: &PipelineLayout,
+        module: &ShaderModule,
+        entry_point: &str,
+        flags: u32,
+    ) -> ComputePipeline {
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(label),
+            layout: Some(layout),
+            module,
+            entry_point: Some(entry_point),
+            compilation_options: wgpu::PipelineCompilationOptions {
+                // This cast is safe, since the maximum u32 is still representable
+                // in an f64 without rounding. This will be re-converted to a u32
+                // in the shader.
+                constants: &[("flags", flags as f64)],
+                zero_initialize_workgroup_memory: false,
+            },
+            cache: None,
+        })
     }
 
     /// Creates a texture for the image to be rendered to.
@@ -372,6 +625,88 @@ impl GPUData {
             }
         }
     }
+
+    /// Load the glitch state of each pixel from the GPU to the CPU. Each value is the
+    /// glitch correction pass in which the pixel was last computed, or the following
+    /// pass if the pixel was glitched.
+    pub fn get_glitch_data(&self) -> Option<Vec<u32>> {
+        self.read_buffer(&self.buffers.glitches, &self.buffers.glitches_readback)
+    }
+
+    /// Load the step of each pixel from the GPU to the CPU: the iteration at which the
+    /// pixel escaped, or zero or less if it did not escape.
+    pub fn get_step_data(&self) -> Option<Vec<i32>> {
+        self.read_buffer(&self.buffers.step, &self.buffers.step_readback)
+    }
+
+    /// Load the windows of a streamed reference orbit requested by the points from the GPU
+    /// to the CPU: a bit set of the windows needed in the current batch, followed by one of
+    /// the windows needed in the next batch.
+    pub fn get_window_requests(&self) -> Option<Vec<u32>> {
+        self.read_buffer(
+            &self.buffers.window_requests,
+            &self.buffers.window_requests_readback,
+        )
+    }
+
+    /// Copies a buffer to its readback buffer and loads the readback buffer to the CPU
+    fn read_buffer<T: bytemuck::Pod>(&self, buffer: &Buffer, readback: &Buffer) -> Option<Vec<T>> {
+        let mut encoder = self
+            .shared
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(buffer, 0, readback, 0, buffer.size());
+        self.shared.queue.submit([encoder.finish()]);
+        let slice = readback.slice(..);
+        let (send, recv) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |res| {
+            let _ = send.send(res);
+        });
+        let _ = self.shared.device.poll(wgpu::PollType::wait_indefinitely());
+        let data = match recv.recv() {
+            Ok(Ok(())) => Some(bytemuck::cast_slice::<u8, T>(&slice.get_mapped_range()).to_vec()),
+            Ok(Err(err)) => {
+                tracing::error!("Error: {err:?}");
+                None
+            }
+            Err(err) => {
+                tracing::error!("Error: {err:?}");
+                None
+            }
+        };
+        readback.unmap();
+        data
+    }
+}
+
+impl DensityPipelines {
+    /// Creates the pipelines of the density shader with the given flags.
+    fn init(
+        device: &Device,
+        label: &str,
+        layout: &PipelineLayout,
+        module: &ShaderModule,
+        flags: u32,
+    ) -> Self {
+        let create_pipeline = |name: &str, entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(format!("{label} {name} Pipeline").as_str()),
+                layout: Some(layout),
+                module,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &[("flags", flags as f64)],
+                    zero_initialize_workgroup_memory: false,
+                },
+                cache: None,
+            })
+        };
+        Self {
+            density: create_pipeline("Density", "main_density"),
+            max: create_pipeline("Density Max", "main_density_max"),
+            tone_map: create_pipeline("Tone Map", "main_tone_map"),
+        }
+    }
 }
 
 impl Buffers {
@@ -380,17 +715,50 @@ impl Buffers {
         use BuffType::*;
         let image_size = viewport.buffer_size();
         Self {
-            probe: Self::create_buffer::<f32>(device, max_iter * 2 * 2, HostWritable),
+            probe: Self::create_buffer::<f64>(
+                device,
+                Self::probe_size(device, max_iter),
+                HostWritable,
+            ),
+            series: Self::create_buffer::<SeriesParams>(device, 1, HostWritable),
+            bla: Self::create_buffer::<u8>(device, Self::bla_size(device, max_iter), HostWritable),
             delta_n: Self::create_buffer::<f32>(device, image_size * 4, ShaderOnly),
             delta_prime: Self::create_buffer::<f32>(device, image_size * 4, ShaderOnly),
             compute_parameters: Self::create_buffer::<ComputeParams>(device, 1, Uniform),
+            newton_parameters: Self::create_buffer::<NewtonParams>(device, 1, Uniform),
             external_coloring: Self::create_buffer::<ColorParams>(device, 1, Uniform),
             internal_coloring: Self::create_buffer::<ColorParams>(device, 1, Uniform),
             render_parameters: Self::create_buffer::<RenderParams>(device, 1, Uniform),
             gradient: Self::create_buffer::<f32>(device, MAX_GRADIENT_STOPS * 2 * 4, Uniform),
-            step: Self::create_buffer::<u32>(device, image_size, ShaderOnly),
+            step: Self::create_buffer::<u32>(device, image_size, HostCopyable),
+            step_readback: Self::create_buffer::<u32>(device, image_size, HostReadable),
             orbits: Self::create_buffer::<f32>(device, image_size * 4, ShaderOnly),
             stripes: Self::create_buffer::<f32>(device, image_size * 4, ShaderOnly),
+            glitches: Self::create_buffer::<u32>(device, image_size, HostCopyable),
+            glitches_readback: Self::create_buffer::<u32>(device, image_size, HostReadable),
+            extended_state: Self::create_buffer::<f64>(
+                device,
+                Self::extended_state_size(device, image_size),
+                ShaderOnly,
+            ),
+            resume_state: Self::create_buffer::<f32>(device, image_size * 12, ShaderOnly),
+            window_steps: Self::create_buffer::<u32>(device, image_size, HostWritable),
+            window_requests: Self::create_buffer::<u32>(
+                device,
+                PROBE_WINDOW_WORDS * 2,
+                HostClearable,
+            ),
+            window_requests_readback: Self::create_buffer::<u32>(
+                device,
+                PROBE_WINDOW_WORDS * 2,
+                HostReadable,
+            ),
+            density: Self::create_buffer::<u32>(
+                device,
+                Self::density_size(image_size),
+                HostWritable,
+            ),
+            density_parameters: Self::create_buffer::<DensityParams>(device, 1, Uniform),
         }
     }
 
@@ -402,6 +770,12 @@ impl Buffers {
             size: (size * core::mem::size_of::<T>()) as u64,
             usage: match ty {
                 ShaderOnly => wgpu::BufferUsages::STORAGE,
+                HostCopyable => wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                HostClearable => {
+                    wgpu::BufferUsages::STORAGE
+                        | wgpu::BufferUsages::COPY_SRC
+                        | wgpu::BufferUsages::COPY_DST
+                }
                 HostWritable => wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
                 HostReadable => wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
                 Uniform => wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
@@ -410,41 +784,112 @@ impl Buffers {
         })
     }
 
+    /// The number of f64 values in the probe buffer. Each point of the reference orbits
+    /// takes 2 f64s, and Julia sets store the critical orbit after the reference orbit.
+    /// Orbits larger than one binding are streamed through it in windows.
+    fn probe_size(device: &Device, max_iter: usize) -> usize {
+        (max_iter * 4).min(Self::max_binding_size(device) / size_of::<f64>())
+    }
+
+    /// The size in bytes of the bilinear approximation table for a reference orbit
+    /// of `max_iter` points. Only levels of at least 8 iterations are stored, so
+    /// there are at most `max_iter / 4` entries. Tables larger than one binding
+    /// only cover the start of the orbit.
+    fn bla_size(device: &Device, max_iter: usize) -> usize {
+        (size_of::<BlaHeader>() + (max_iter / 4 + 1) * size_of::<BlaEntry>())
+            .min(Self::max_binding_size(device))
+    }
+
+    /// The size in bytes of the largest storage buffer that can be bound
+    fn max_binding_size(device: &Device) -> usize {
+        let limits = device.limits();
+        (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size) as usize
+    }
+
+    /// The number of f64 values in the extended iteration state buffer. The f64 shaders
+    /// store 4 per pixel, and the emulated double precision shader stores 2 f32s.
+    fn extended_state_size(device: &Device, image_size: usize) -> usize {
+        if device.features().contains(wgpu::Features::SHADER_F64) {
+            image_size * 4
+        } else {
+            image_size
+        }
+    }
+
+    /// The number of u32 values in the density buffer: the counts of the 3 channels
+    /// of each pixel, followed by the largest count of each channel.
+    fn density_size(image_size: usize) -> usize {
+        image_size * 3 + 3
+    }
+
     /// Resizes the necessary buffers to the new viewport.
     /// Layouts generated from the buffers will need to be recreated.
     pub fn resize(&mut self, new_view: &Viewport, max_iter: usize, device: &Device) {
         use BuffType::*;
         // replace all sized buffers (not uniforms)
-        self.probe = Self::create_buffer::<f32>(device, max_iter * 2, HostWritable);
+        self.resize_probe(max_iter, device);
         let image_size = new_view.buffer_size();
         self.delta_n = Self::create_buffer::<f32>(device, image_size * 4, ShaderOnly);
         self.delta_prime = Self::create_buffer::<f32>(device, image_size * 4, ShaderOnly);
-        self.step = Self::create_buffer::<u32>(device, image_size, ShaderOnly);
+        self.step = Self::create_buffer::<u32>(device, image_size, HostCopyable);
+        self.step_readback = Self::create_buffer::<u32>(device, image_size, HostReadable);
         self.orbits = Self::create_buffer::<f32>(device, image_size * 4, ShaderOnly);
         self.stripes = Self::create_buffer::<f32>(device, image_size * 4, ShaderOnly);
+        self.glitches = Self::create_buffer::<u32>(device, image_size, HostCopyable);
+        self.glitches_readback = Self::create_buffer::<u32>(device, image_size, HostReadable);
+        self.extended_state = Self::create_buffer::<f64>(
+            device,
+            Self::extended_state_size(device, image_size),
+            ShaderOnly,
+        );
+        self.resume_state = Self::create_buffer::<f32>(device, image_size * 12, ShaderOnly);
+        self.window_steps = Self::create_buffer::<u32>(device, image_size, HostWritable);
+        self.density =
+            Self::create_buffer::<u32>(device, Self::density_size(image_size), HostWritable);
+    }
+
+    /// Resizes the buffers of the reference orbit and its approximations to `max_iter` points,
+    /// keeping the per-pixel buffers. Layouts generated from the buffers will need to be recreated.
+    pub fn resize_probe(&mut self, max_iter: usize, device: &Device) {
+        use BuffType::*;
+        self.probe =
+            Self::create_buffer::<f64>(device, Self::probe_size(device, max_iter), HostWritable);
+        self.bla =
+            Self::create_buffer::<u8>(device, Self::bla_size(device, max_iter), HostWritable);
     }
 }
 
 impl BindGroups {
     /// Creates the bind groups for the compute and render pipelines.
-    /// Returns the bind groups and the pipeline layouts for the compute and render pipelines.
+    /// Returns the bind groups and the pipeline layouts for the compute, render and density pipelines.
     fn init(
         device: &Device,
         buffers: &Buffers,
         texture_view: &TextureView,
-    ) -> (Self, PipelineLayout, PipelineLayout) {
+    ) -> (Self, PipelineLayout, PipelineLayout, PipelineLayout) {
         let Buffers {
             probe,
+            series,
+            bla,
             delta_n,
             delta_prime,
             step,
             orbits,
             stripes,
             compute_parameters,
+            newton_parameters,
             external_coloring,
             internal_coloring,
             render_parameters,
             gradient,
+            glitches,
+            extended_state,
+            resume_state,
+            window_steps,
+            window_requests,
+            density,
+            density_parameters,
+            ..
         } = buffers;
 
         // create the bind groups for the compute shader
@@ -452,11 +897,18 @@ impl BindGroups {
             label: Some("Compute Bind Group Layout"),
             entries: &[
                 Self::create_buffer_layout_entry(0, true),
+                Self::create_buffer_layout_entry(1, true),
                 Self::create_buffer_layout_entry(2, false),
                 Self::create_buffer_layout_entry(3, false),
                 Self::create_buffer_layout_entry(4, false),
                 Self::create_buffer_layout_entry(5, false),
                 Self::create_buffer_layout_entry(6, false),
+                Self::create_buffer_layout_entry(7, true),
+                Self::create_buffer_layout_entry(8, false),
+                Self::create_buffer_layout_entry(9, false),
+                Self::create_buffer_layout_entry(10, false),
+                Self::create_buffer_layout_entry(11, false),
+                Self::create_buffer_layout_entry(12, false),
             ],
         });
 
@@ -467,6 +919,10 @@ impl BindGroups {
                     binding: 0,
                     resource: probe.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: series.as_entire_binding(),
+                },
                 wgpu::BindGroupEntry {
                     binding: 2,
                     resource: delta_n.as_entire_binding(),
@@ -487,6 +943,30 @@ impl BindGroups {
                     binding: 6,
                     resource: stripes.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: bla.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: glitches.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: extended_state.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: resume_state.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 11,
+                    resource: window_steps.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 12,
+                    resource: window_requests.as_entire_binding(),
+                },
             ],
             label: Some("Compute Bind Group"),
         });
@@ -494,15 +974,24 @@ impl BindGroups {
         let params_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("Compute Parameters Bind Group Layout"),
-                entries: &[Self::create_uniform_layout_entry(0)],
+                entries: &[
+                    Self::create_uniform_layout_entry(0),
+                    Self::create_uniform_layout_entry(1),
+                ],
             });
 
         let compute_parameters = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &params_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: compute_parameters.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: compute_parameters.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: newton_parameters.as_entire_binding(),
+                },
+            ],
             label: Some("Compute Parameters Bind Group"),
         });
 
@@ -629,6 +1118,49 @@ impl BindGroups {
                 push_constant_ranges: &[],
             });
 
+        // create the density bind groups, which share the texture with the render pipeline
+
+        let density_buffers_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Density Bind Group Layout"),
+                entries: &[Self::create_buffer_layout_entry(0, false)],
+            });
+
+        let density_buffers = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &density_buffers_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: density.as_entire_binding(),
+            }],
+            label: Some("Density Bind Group"),
+        });
+
+        let density_params_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Density Parameters Bind Group Layout"),
+                entries: &[Self::create_uniform_layout_entry(0)],
+            });
+
+        let density_parameters = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &density_params_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: density_parameters.as_entire_binding(),
+            }],
+            label: Some("Density Parameters Bind Group"),
+        });
+
+        let density_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Density Pipeline Layout"),
+                bind_group_layouts: &[
+                    &density_buffers_layout,
+                    &texture_bind_group_layout,
+                    &density_params_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
         (
             Self {
                 compute_buffers,
@@ -636,9 +1168,12 @@ impl BindGroups {
                 render_buffers,
                 render_parameters: render_parameters_group,
                 render_texture,
+                density_buffers,
+                density_parameters,
             },
             compute_pipeline_layout,
             render_pipeline_layout,
+            density_pipeline_layout,
         )
     }
 
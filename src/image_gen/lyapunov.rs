@@ -0,0 +1,38 @@
+/*!
+# Lyapunov Fractals
+
+Renders the [`RenderMode::Lyapunov`](crate::types::RenderMode::Lyapunov) mode, which measures
+the Lyapunov exponent of a logistic map `x = r x (1 - x)` whose growth rate `r` alternates
+between the two coordinates of each pixel in a sequence such as `AB` or `AABAB`.
+
+The shader writes to the same buffers as the escape time shaders, so the image is colored
+by the usual color step. Stable points, with negative exponents, use the internal coloring.
+ */
+
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use super::gpu_setup::GPUData;
+use super::run_batched_step;
+use crate::types::{Image, LyapunovOptions, StatusMessage};
+
+/// Runs the Lyapunov shader on the GPU, with the warmup iterations before the
+/// iterations the exponent is measured over
+pub(super) fn run_lyapunov_step(
+    image: &Image,
+    options: &LyapunovOptions,
+    gpu_data: &GPUData,
+    cancelled: Arc<AtomicBool>,
+    status_callback: &mut impl FnMut(StatusMessage),
+) {
+    run_batched_step(
+        &gpu_data.lyapunov_pipeline,
+        image,
+        options.warmup as u64 + image.max_iter,
+        options.warmup,
+        options.pattern(),
+        gpu_data,
+        cancelled,
+        status_callback,
+    );
+}
@@ -8,23 +8,49 @@ It is responsible for receiving messages from the main thread, and sending the r
 images back to the main thread.
  */
 
+mod cpu;
+mod density;
 mod gpu_setup;
+mod lyapunov;
+mod newton;
+mod orbit_cache;
 mod probe;
+mod probe_window;
+mod skew;
 
+use std::collections::BTreeSet;
 use std::path::Path;
 use std::sync::Arc;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 
-use eframe::wgpu::{self, Extent3d};
-pub use gpu_setup::{Constants, GPUData, SharedState, get_device_and_queue};
+use color_eyre::Result;
+pub use cpu::{CpuBuffers, CpuData, render_image_cpu, save_to_file_cpu};
+use density::{run_density_step, run_tone_map_step};
+pub use density::{sample_density, tone_map};
+use eframe::wgpu::{self, ComputePipeline, Extent3d};
+pub use gpu_setup::{Constants, GPUData, SharedState, device_descriptor, get_device_and_queue};
 use image::ImageBuffer;
 use little_exif::exif_tag::ExifTag;
 use little_exif::metadata::Metadata;
-use probe::probe;
+use lyapunov::run_lyapunov_step;
+pub use newton::polynomial_roots;
+use newton::run_newton_step;
+pub use orbit_cache::OrbitCache;
+pub use probe::{FloatExp, OrbitPoints, ReferenceOrbit, find_nucleus};
+use probe::{SeriesApproximation, bla_table, probe_cached, series_approximation};
+use probe_window::{PROBE_POINT_SIZE, PROBE_WINDOW_WORDS, ProbeWindows};
+pub use skew::estimate_skew;
 use tracing::debug;
 
-use crate::types::{ColorParams, ComputeParams, Image, ImageDiff, RenderParams, StatusMessage};
+use crate::types::{
+    Algorithm, BlaEntry, BlaHeader, ColorParams, ComplexPoint, ComputeParams, Image, ImageDiff,
+    IterationStats, Nucleus, RenderMode, RenderParams, StatusMessage, Viewport,
+};
+
+/// How much deeper the view may zoom before an automatically selected
+/// reference is searched for again at the new precision
+const AUTO_REFERENCE_ZOOM_RANGE: f64 = 16.0;
 
 macro_rules! time {
     ($name:literal; $($expression:tt)*) => {{
@@ -40,18 +66,93 @@ pub fn is_metadata_supported(path: &Path) -> bool {
     matches!(path.extension(), Some(x) if x == "jpg" || x == "jpeg" || x == "png" || x == "webp" || x == "avif")
 }
 
+/// Renders the image, recomputing only what changed since `last_image`.
+///
+/// Returns the escape iterations of the image if it was recomputed in the escape time mode,
+/// which are used to estimate a better `max_iter`.
+///
+/// Setting `cancelled` stops the render between batches of iterations, reporting
+/// [`StatusMessage::Cancelled`]. The image is left incomplete, so the next render of
+/// the same GPU data should not pass it as `last_image`.
 pub fn render_image(
     gpu_data: &mut GPUData,
-    probed_data: &mut Vec<[f32; 2]>,
+    probed_data: &mut ReferenceOrbit,
     image: &Image,
     last_image: Option<&Image>,
     cancelled: Arc<AtomicBool>,
     mut status_callback: impl FnMut(StatusMessage),
-) {
-    let diff = last_image
+) -> Option<IterationStats> {
+    let mut diff = last_image
         .map(|img| image.comp(img))
         .unwrap_or(ImageDiff::full());
 
+    // density images only use the density buffer, and none of the escape time steps
+    if let Some(options) = image.render_mode.density() {
+        if diff.resize {
+            gpu_data.resize(&image.viewport, image.max_iter as usize, image.get_flags());
+        }
+        if diff.recompute {
+            status_callback(StatusMessage::Progress("Sampling orbits".into(), 0.0));
+            time!(
+                "Sampling orbit densities";
+                run_density_step(image, options, gpu_data, cancelled.clone(), &mut status_callback)
+            );
+            if is_cancelled(&cancelled, &mut status_callback) {
+                return None;
+            }
+        }
+        if diff.recolor {
+            status_callback(StatusMessage::Progress("Rendering Colors".into(), 0.0));
+            time!("Running tone mapping"; run_tone_map_step(image, options, gpu_data));
+        }
+        return None;
+    }
+
+    // Lyapunov and Newton images are colored as usual, but do not use a reference orbit
+    if matches!(
+        image.render_mode,
+        RenderMode::Lyapunov(_) | RenderMode::Newton(_)
+    ) {
+        if diff.resize {
+            gpu_data.resize(&image.viewport, image.max_iter as usize, image.get_flags());
+        }
+        if diff.recompute {
+            match &image.render_mode {
+                RenderMode::Lyapunov(options) => time!(
+                    "Running Lyapunov shader";
+                    run_lyapunov_step(image, options, gpu_data, cancelled.clone(), &mut status_callback)
+                ),
+                RenderMode::Newton(options) => time!(
+                    "Running Newton shader";
+                    run_newton_step(image, options, gpu_data, cancelled.clone(), &mut status_callback)
+                ),
+                RenderMode::Escape | RenderMode::Density(_) => {}
+            }
+            if is_cancelled(&cancelled, &mut status_callback) {
+                return None;
+            }
+        }
+        if diff.recolor {
+            status_callback(StatusMessage::Progress("Rendering Colors".into(), 0.0));
+            time!("Running image render"; run_render_step(image, gpu_data));
+        }
+        return None;
+    }
+
+    let algorithm = gpu_data.algorithm(image);
+    let last_algorithm = gpu_data.algorithm.replace(algorithm);
+    // the reference orbit is probed once it is needed, and uploaded
+    // in the precision of the shader
+    if algorithm.is_perturbed() && !last_algorithm.is_some_and(Algorithm::is_perturbed) {
+        diff.reprobe = true;
+        diff.recompute = true;
+    }
+    let reference = select_reference(gpu_data, image, &mut diff, &cancelled, &mut status_callback);
+    // a higher max iteration continues the last compute step, unless the reference changed
+    let resume_from = diff.resume_from.filter(|_| !diff.reprobe);
+    let reupload = algorithm.is_perturbed()
+        && (last_algorithm.is_some_and(|last| last != algorithm) || resume_from.is_some());
+
     // the actual image generation process
     // - resize the GPU data
     // - probe the point
@@ -63,30 +164,52 @@ pub fn render_image(
         gpu_data.resize(&image.viewport, image.max_iter as usize, image.get_flags());
     }
 
+    if resume_from.is_some() {
+        gpu_data.resize_probe(image.max_iter as usize, image.get_flags());
+        if algorithm.is_perturbed() {
+            status_callback(StatusMessage::Progress("Extending probe".into(), 0.0));
+            let extended = time!(
+                "Extending probe";
+                probed_data.extend(
+                    image.max_iter,
+                    gpu_data.constants.orbit_cache.as_ref(),
+                    &cancelled,
+                )
+            );
+            if is_cancelled(&cancelled, &mut status_callback) {
+                return None;
+            }
+            // an orbit shortened from the cache is loaded again at the new length
+            diff.reprobe |= !extended;
+        }
+    }
+
     if diff.reprobe {
         status_callback(StatusMessage::Progress("Probing point".into(), 0.0));
-        let julia_point = match &image.fractal_kind {
-            crate::types::FractalKind::Mandelbrot => None,
-            crate::types::FractalKind::Julia(pt) => Some(pt),
-        };
-        // probe the point
+        // probe the point, and the critical point of Julia sets
         *probed_data = time!(
             "Probing point";
-            probe::<f32>(&image.probe_location, image.max_iter, image.viewport.zoom, julia_point)
+            ReferenceOrbit::probe(
+                &reference,
+                image.max_iter,
+                image.viewport.zoom,
+                &image.fractal_kind,
+                &image.formulas(),
+                gpu_data.constants.orbit_cache.as_ref(),
+                &cancelled,
+            )
         );
+        if is_cancelled(&cancelled, &mut status_callback) {
+            return None;
+        }
+    }
+    if diff.reprobe || reupload {
         status_callback(StatusMessage::Progress("Uploading probe".into(), 0.0));
         // update the probe buffer
-        time!("probe upload";
-            gpu_data.shared.queue.write_buffer(
-                &gpu_data.buffers.probe,
-                0,
-                bytemuck::cast_slice(&probed_data[..]),
-            );
-            gpu_data.shared.queue.submit([]);
-            let _ = gpu_data.shared.device.poll(wgpu::PollType::wait_indefinitely());
-        );
+        time!("probe upload"; upload_probe(gpu_data, probed_data, algorithm, &cancelled));
     }
 
+    let mut stats = None;
     if diff.recompute {
         status_callback(StatusMessage::Progress(
             format!("Computing iteration 1 of {}", image.max_iter),
@@ -94,7 +217,32 @@ pub fn render_image(
         ));
         time!(
             "Running compute shader";
-            run_compute_step(probed_data, image, gpu_data, cancelled, &mut status_callback)
+            run_compute_step(
+                probed_data,
+                &reference,
+                0,
+                resume_from.unwrap_or(0),
+                image,
+                gpu_data,
+                cancelled.clone(),
+                &mut status_callback,
+            )
+        );
+        if algorithm.is_perturbed() && gpu_data.constants.max_glitch_references > 0 {
+            time!(
+                "Correcting glitches";
+                correct_glitches(probed_data, image, gpu_data, cancelled.clone(), &mut status_callback)
+            );
+        }
+        // the steps of a cancelled render are incomplete
+        if is_cancelled(&cancelled, &mut status_callback) {
+            return None;
+        }
+        stats = time!(
+            "Reading iteration statistics";
+            gpu_data
+                .get_step_data()
+                .map(|steps| IterationStats::from_steps(&steps, image.max_iter))
         );
     }
 
@@ -106,16 +254,382 @@ pub fn render_image(
         status_callback(StatusMessage::Progress("Rendering Colors".into(), 0.0));
         time!("Running image render"; run_render_step(image, gpu_data));
     }
+    stats
+}
+
+/// Reports [`StatusMessage::Cancelled`] if the render was cancelled
+fn is_cancelled(cancelled: &AtomicBool, status_callback: &mut impl FnMut(StatusMessage)) -> bool {
+    let cancelled = cancelled.load(Ordering::Relaxed);
+    if cancelled {
+        status_callback(StatusMessage::Cancelled);
+    }
+    cancelled
+}
+
+/// Returns the reference point to probe for this image. In automatic mode, this is the
+/// nearest minibrot nucleus, which is only searched for again once the previous one has
+/// left the view or lost precision, marking the probe as changed when it is replaced.
+fn select_reference(
+    gpu_data: &mut GPUData,
+    image: &Image,
+    diff: &mut ImageDiff,
+    cancelled: &AtomicBool,
+    status_callback: &mut impl FnMut(StatusMessage),
+) -> ComplexPoint {
+    if !gpu_data.algorithm(image).is_perturbed() || !is_auto_reference(image) {
+        gpu_data.auto_reference = None;
+        return image.probe_location.clone();
+    }
+
+    let viewport = &image.viewport;
+    let valid = gpu_data
+        .auto_reference
+        .as_ref()
+        .is_some_and(|(reference, zoom)| {
+            let (x, y) = viewport.coords_to_px_offset(&reference.x, &reference.y);
+            (x / viewport.width as f64).abs() <= 0.5
+                && (y / viewport.height as f64).abs() <= 0.5
+                && viewport.zoom - zoom <= AUTO_REFERENCE_ZOOM_RANGE
+        });
+    if !valid {
+        status_callback(StatusMessage::Progress(
+            "Searching for a reference".into(),
+            0.0,
+        ));
+        diff.reprobe = true;
+        diff.recompute = true;
+        let reference = nucleus_reference(image, cancelled);
+        // a cancelled search is not kept, so that the next render searches again
+        if cancelled.load(Ordering::Relaxed) {
+            return reference;
+        }
+        // the center is kept if no nucleus is found, so the search is not repeated
+        // until the view moves away
+        gpu_data.auto_reference = Some((reference, viewport.zoom));
+    }
+    gpu_data
+        .auto_reference
+        .as_ref()
+        .map(|(reference, _)| reference.clone())
+        .unwrap_or_else(|| image.probe_location.clone())
+}
+
+/// Whether the reference point of the image is selected automatically. Nuclei are only
+/// searched for in the Mandelbrot sets of analytic formulas.
+fn is_auto_reference(image: &Image) -> bool {
+    image.auto_reference && image.fractal_kind.julia_point().is_none() && image.is_analytic()
+}
+
+/// The nucleus of the lowest-period minibrot in the view, to use as the reference point,
+/// or the center of the view if none is found or the search is cancelled
+fn nucleus_reference(image: &Image, cancelled: &AtomicBool) -> ComplexPoint {
+    let viewport = &image.viewport;
+    let power = image.fractal_kind.power();
+    match time!("Nucleus search"; find_nucleus(viewport, image.max_iter, power, cancelled)) {
+        Some(Nucleus { location, period }) => {
+            debug!("Found a nucleus of period {period}");
+            location
+        }
+        None => viewport.center.clone(),
+    }
+}
+
+/// Packs the hybrid formula sequence of the image into 4 bits per step,
+/// returning the packed sequence and its length (0 if there is no sequence)
+fn hybrid_params(image: &Image) -> (u32, u32) {
+    if image.hybrid.is_empty() {
+        return (0, 0);
+    }
+    let formulas = image.formulas();
+    let packed = formulas
+        .iter()
+        .enumerate()
+        .fold(0, |packed, (i, formula)| packed | formula.id() << (4 * i));
+    (packed, formulas.len() as u32)
+}
+
+/// Converts a reference orbit to f32s, for the approximations of the f32 shader
+fn orbit_f32(probed_data: &[[FloatExp; 2]]) -> Vec<[f32; 2]> {
+    probed_data
+        .iter()
+        .map(|&[x, y]| [x.to_f64() as f32, y.to_f64() as f32])
+        .collect()
+}
+
+/// Converts a reference orbit to the precision of the f64 shader
+fn orbit_f64(probed_data: &[[FloatExp; 2]]) -> Vec<[f64; 2]> {
+    probed_data
+        .iter()
+        .map(|&[x, y]| [x.to_f64(), y.to_f64()])
+        .collect()
+}
+
+/// Converts a reference orbit to the extended exponent form of the f32 shader,
+/// stored as `[re, im, exponent, 0]`. Both parts share the exponent, so that
+/// the largest one has a mantissa in `0.5 <= |mantissa| < 1`.
+fn orbit_floatexp(probed_data: &[[FloatExp; 2]]) -> Vec<[f32; 4]> {
+    probed_data
+        .iter()
+        .map(|&[x, y]| {
+            let exponent = match (x.is_zero(), y.is_zero()) {
+                (true, true) => 0,
+                (true, false) => y.exponent,
+                (false, true) => x.exponent,
+                (false, false) => x.exponent.max(y.exponent),
+            };
+            let mantissa = |part: FloatExp| {
+                if part.is_zero() {
+                    return 0.0;
+                }
+                FloatExp {
+                    mantissa: part.mantissa,
+                    exponent: part.exponent - exponent,
+                }
+                .to_f64() as f32
+            };
+            [mantissa(x), mantissa(y), exponent as f32, 0.0]
+        })
+        .collect()
+}
+
+/// Splits a reference orbit into pairs of f32s for the emulated double precision shader,
+/// stored as `[re_hi, re_lo, im_hi, im_lo]`
+fn orbit_f32x2(probed_data: &[[FloatExp; 2]]) -> Vec<[f32; 4]> {
+    let split = |x: f64| (x as f32, (x - x as f32 as f64) as f32);
+    orbit_f64(probed_data)
+        .into_iter()
+        .map(|[x, y]| {
+            let (x_hi, x_lo) = split(x);
+            let (y_hi, y_lo) = split(y);
+            [x_hi, x_lo, y_hi, y_lo]
+        })
+        .collect()
+}
+
+/// Uploads the reference orbits to the probe buffer, in the precision of the algorithm.
+/// The critical orbit of Julia sets directly follows the orbit of the reference. Only the
+/// heads of orbits that do not fit are uploaded, since [`run_compute_step`] streams the rest.
+fn upload_probe(
+    gpu_data: &GPUData,
+    probed_data: &ReferenceOrbit,
+    algorithm: Algorithm,
+    cancelled: &AtomicBool,
+) {
+    let windows = ProbeWindows::new(
+        gpu_data.buffers.probe.size(),
+        gpu_data.constants.iter_batch_size,
+        probed_data,
+    );
+    let head_len = if windows.is_streamed() {
+        windows.head_len
+    } else {
+        usize::MAX
+    };
+    let heads = read_heads(&probed_data.orbit, &probed_data.critical, head_len);
+    let Some([orbit, critical]) = read_or_cancel(heads, cancelled) else {
+        return;
+    };
+    if windows.is_streamed() {
+        write_probe(gpu_data, 0, &orbit, algorithm);
+        write_probe(gpu_data, head_len, &critical, algorithm);
+    } else {
+        write_probe(gpu_data, 0, &[orbit, critical].concat(), algorithm);
+    }
+    gpu_data.shared.queue.submit([]);
+    let _ = gpu_data
+        .shared
+        .device
+        .poll(wgpu::PollType::wait_indefinitely());
+}
+
+/// Reads the first `len` points of both reference orbits
+fn read_heads(
+    orbit: &OrbitPoints,
+    critical: &OrbitPoints,
+    len: usize,
+) -> Result<[Vec<[FloatExp; 2]>; 2]> {
+    Ok([orbit.read(0..len)?, critical.read(0..len)?])
+}
+
+/// Returns the points of the reference orbits that were read, or cancels the render
+/// if they can not be read, since its pixels would be computed without them
+fn read_or_cancel<T>(points: Result<T>, cancelled: &AtomicBool) -> Option<T> {
+    match points {
+        Ok(points) => Some(points),
+        Err(err) => {
+            tracing::error!("Failed to read the reference orbit: {err}");
+            cancelled.store(true, Ordering::Relaxed);
+            None
+        }
+    }
+}
+
+/// Writes points of the reference orbits to the probe buffer from the point at `offset`,
+/// in the precision of the algorithm
+fn write_probe(gpu_data: &GPUData, offset: usize, points: &[[FloatExp; 2]], algorithm: Algorithm) {
+    let GPUData {
+        shared: SharedState { queue, .. },
+        buffers,
+        ..
+    } = gpu_data;
+    let offset = (offset * PROBE_POINT_SIZE) as u64;
+    match algorithm {
+        Algorithm::Perturbedf64 => {
+            queue.write_buffer(
+                &buffers.probe,
+                offset,
+                bytemuck::cast_slice(&orbit_f64(points)),
+            );
+        }
+        Algorithm::Perturbedf32x2 => {
+            queue.write_buffer(
+                &buffers.probe,
+                offset,
+                bytemuck::cast_slice(&orbit_f32x2(points)),
+            );
+        }
+        _ => {
+            queue.write_buffer(
+                &buffers.probe,
+                offset,
+                bytemuck::cast_slice(&orbit_floatexp(points)),
+            );
+        }
+    }
+}
+
+/// Re-computes the pixels flagged as glitched by the perturbed compute shader with
+/// extra reference points inside the glitched areas, until no glitches remain or the
+/// reference limit is reached. The main reference orbit is restored afterwards.
+fn correct_glitches(
+    probed_data: &ReferenceOrbit,
+    image: &Image,
+    gpu_data: &GPUData,
+    cancelled: Arc<AtomicBool>,
+    status_callback: &mut impl FnMut(StatusMessage),
+) {
+    let texture_size: Extent3d = (&image.viewport).into();
+    let algorithm = gpu_data.algorithm(image);
+    let mut initial_glitches = 0;
+    let mut glitches = 0;
+    let mut pass = 0;
+    while !cancelled.load(Ordering::Relaxed)
+        && let Some(glitch_data) = gpu_data.get_glitch_data()
+    {
+        // pixels glitched in the last pass are marked for the next pass
+        let glitched: Vec<usize> = glitch_data
+            .iter()
+            .enumerate()
+            .filter(|&(_, glitch)| *glitch == pass + 1)
+            .map(|(i, _)| i)
+            .collect();
+        glitches = glitched.len();
+        if pass == 0 {
+            initial_glitches = glitches;
+        }
+        if glitches == 0 || pass >= gpu_data.constants.max_glitch_references {
+            break;
+        }
+        pass += 1;
+
+        status_callback(StatusMessage::Progress(
+            format!("Correcting {glitches} glitched pixels"),
+            0.0,
+        ));
+        let reference = glitch_reference(&glitched, texture_size, &image.viewport);
+        debug!("Glitch correction pass {pass} for {glitches} pixels");
+        // the critical orbit does not depend on the reference. The glitch references of
+        // a view are the same each time it is rendered, so they are cached as well.
+        let orbit = time!(
+            "Probing glitch reference";
+            ReferenceOrbit {
+                orbit: probe_cached(
+                    &reference,
+                    image.max_iter,
+                    image.viewport.zoom,
+                    &image.fractal_kind,
+                    &image.formulas(),
+                    gpu_data.constants.orbit_cache.as_ref(),
+                    &cancelled,
+                ),
+                critical: probed_data.critical.clone(),
+                ..Default::default()
+            }
+        );
+        upload_probe(gpu_data, &orbit, algorithm, &cancelled);
+        run_compute_step(
+            &orbit,
+            &reference,
+            pass,
+            0,
+            image,
+            gpu_data,
+            cancelled.clone(),
+            status_callback,
+        );
+    }
+
+    if pass > 0 {
+        upload_probe(gpu_data, probed_data, algorithm, &cancelled);
+    }
+    if initial_glitches > 0 && !cancelled.load(Ordering::Relaxed) {
+        status_callback(StatusMessage::GlitchesCorrected(
+            initial_glitches - glitches,
+            glitches,
+        ));
+    }
+}
+
+/// Picks a new reference point inside a glitched area: the glitched pixel
+/// closest to the center of all glitched pixels.
+fn glitch_reference(glitched: &[usize], size: Extent3d, viewport: &Viewport) -> ComplexPoint {
+    let width = size.width as usize;
+    let position = |i: usize| ((i % width) as f64, (i / width) as f64);
+    let (sum_x, sum_y) = glitched.iter().fold((0.0, 0.0), |(sum_x, sum_y), &i| {
+        let (x, y) = position(i);
+        (sum_x + x, sum_y + y)
+    });
+    let center = (sum_x / glitched.len() as f64, sum_y / glitched.len() as f64);
+    let distance = |i: usize| {
+        let (x, y) = position(i);
+        (x - center.0).powi(2) + (y - center.1).powi(2)
+    };
+    let closest = glitched
+        .iter()
+        .copied()
+        .min_by(|&a, &b| distance(a).total_cmp(&distance(b)))
+        .unwrap_or_default();
+
+    let (x, y) = position(closest);
+    let (x, y) = viewport.get_real_coords(
+        x * viewport.width as f64 / size.width as f64,
+        y * viewport.height as f64 / size.height as f64,
+    );
+    ComplexPoint { x, y }
 }
 
 /// Runs the compute shader on the GPU. This is the most expensive step, so the output
 /// should be cached as much as possible. This step only needs to be run if the probe
 /// location, max iteration, or image viewport has changed.
+///
+/// `reference` is the location of the reference orbit in `probed_data`. During glitch correction
+/// passes (`glitch_pass` > 0), only the pixels flagged for that pass are computed.
+///
+/// A non-zero `resume_iter` is the max iteration of the last compute step on the same buffers,
+/// whose unescaped pixels are continued from there. Pixels that were computed with the
+/// reference of a glitch correction pass are flagged for the first pass instead.
+///
+/// If the windows requested by the points of a streamed orbit can not be read back,
+/// the render is marked as cancelled, since its pixels would be left incomplete.
+#[expect(clippy::too_many_arguments)]
 fn run_compute_step(
-    probed_data: &[[f32; 2]],
+    probed_data: &ReferenceOrbit,
+    reference: &ComplexPoint,
+    glitch_pass: u32,
+    resume_iter: u64,
     image: &Image,
     gpu_data: &GPUData,
-    _cancelled: Arc<AtomicBool>,
+    cancelled: Arc<AtomicBool>,
     status_callback: &mut impl FnMut(StatusMessage),
 ) {
     let GPUData {
@@ -123,106 +637,384 @@ fn run_compute_step(
         bind_groups,
         direct_f32_pipeline,
         perturbed_f32_pipeline,
+        perturbed_f32x2_pipeline,
+        direct_f64_pipeline,
+        perturbed_f64_pipeline,
         buffers,
         constants,
         ..
     } = gpu_data;
     let texture_size: Extent3d = (&image.viewport).into();
+    let algorithm = gpu_data.algorithm(image);
+    fn f64_pipeline(pipeline: &Option<ComputePipeline>) -> &ComputePipeline {
+        pipeline
+            .as_ref()
+            .expect("f64 algorithms are only selected if the device supports f64")
+    }
+    // the offset of the reference from the center, relative to the size of the view
+    let reference_offset = || {
+        let (x, y) = image
+            .viewport
+            .coords_to_px_offset(&reference.x, &reference.y);
+        (
+            x as f32 / image.viewport.width as f32,
+            y as f32 / image.viewport.height as f32,
+        )
+    };
 
-    let (compute_pipeline, x, y, probe_len) = match image.algorithm() {
-        crate::types::Algorithm::Directf32 => (
-            direct_f32_pipeline,
+    let (compute_pipeline, x, y, probe_len, start_iter) = match algorithm {
+        Algorithm::Directf32 | Algorithm::Directf64 => (
+            match algorithm {
+                Algorithm::Directf64 => f64_pipeline(direct_f64_pipeline),
+                _ => direct_f32_pipeline,
+            },
             image.viewport.center.x.to_f32(),
             image.viewport.center.y.to_f32(),
             image.max_iter as usize,
+            0,
         ),
-        crate::types::Algorithm::Perturbedf32 => {
-            let (x, y) = image
-                .viewport
-                .coords_to_px_offset(&image.probe_location.x, &image.probe_location.y);
+        // the f64 shader does not use the series or bilinear approximations,
+        // since it is used at zooms where they skip few iterations. The emulated
+        // double precision shader does not use them either, since their f32
+        // coefficients would limit its precision.
+        Algorithm::Perturbedf64 | Algorithm::Perturbedf32x2 => {
+            let (x, y) = reference_offset();
+            (
+                match algorithm {
+                    Algorithm::Perturbedf64 => f64_pipeline(perturbed_f64_pipeline),
+                    _ => perturbed_f32x2_pipeline,
+                },
+                x,
+                y,
+                probed_data.orbit.len(),
+                0,
+            )
+        }
+        Algorithm::Perturbedf32 => {
+            // the approximations of a streamed orbit only cover the start that fits in
+            // the table, so only that start is converted to f32s
+            let bla_len =
+                (buffers.bla.size() as usize - size_of::<BlaHeader>()) / size_of::<BlaEntry>() * 4
+                    - 4;
+            let Some(approximated) = read_or_cancel(probed_data.orbit.read(0..bla_len), &cancelled)
+            else {
+                return;
+            };
+            let approximated = &orbit_f32(&approximated);
+            let (x, y) = reference_offset();
+
+            let aspect_scale = image.viewport.aspect_scale();
+            let max_offset = (((x.abs() + 0.5) * 2.0 * aspect_scale.x) as f64)
+                .hypot(((y.abs() + 0.5) * 2.0 * aspect_scale.y) as f64)
+                * image.viewport.max_stretch();
+            let julia = image.fractal_kind.julia_point().is_some();
+            let quadratic = image.is_analytic() && image.fractal_kind.power() == 2;
+
+            // skip the first iterations with a series approximation. Julia sets
+            // start with the delta at the pixel offset, so the series does not apply.
+            // Glitched pixels are too far from the main reference for the series to be valid.
+            // The series coefficients are only derived for the quadratic Mandelbrot formula.
+            let series = if julia || glitch_pass != 0 || !quadratic {
+                SeriesApproximation::default()
+            } else {
+                time!(
+                    "Series approximation";
+                    series_approximation(
+                        approximated,
+                        [reference.x.to_f32(), reference.y.to_f32()],
+                        image.viewport.zoom,
+                        max_offset,
+                        image.max_iter,
+                    )
+                )
+            };
+            debug!("Skipping {} iterations", series.skip);
+            queue.write_buffer(&buffers.series, 0, bytemuck::cast_slice(&[series.params]));
+
+            // the bilinear approximation depends on the size of the pixel offsets,
+            // so it needs to be rebuilt whenever the viewport changes
+            let log2_max_delta_0 = if julia {
+                f64::NEG_INFINITY
+            } else {
+                max_offset.log2() - image.viewport.zoom
+            };
+            let bla = time!("BLA table"; bla_table(
+                approximated,
+                &image.fractal_kind,
+                &image.formulas(),
+                log2_max_delta_0,
+            ));
+            queue.write_buffer(&buffers.bla, 0, bytemuck::cast_slice(&[bla.header]));
+            if !bla.entries.is_empty() {
+                queue.write_buffer(
+                    &buffers.bla,
+                    size_of::<BlaHeader>() as u64,
+                    bytemuck::cast_slice(&bla.entries),
+                );
+            }
             (
                 perturbed_f32_pipeline,
-                x as f32 / image.viewport.width as f32,
-                y as f32 / image.viewport.height as f32,
-                probed_data.len(),
+                x,
+                y,
+                probed_data.orbit.len(),
+                series.skip,
             )
         }
     };
 
+    // the pixels can only be continued if the iterations skipped by
+    // the series approximation end before the last max iteration
+    let resume_iter = if resume_iter > start_iter {
+        resume_iter
+    } else {
+        0
+    };
+
+    // the direct algorithms do not read the reference orbits
+    let windows = match algorithm {
+        Algorithm::Directf32 | Algorithm::Directf64 => {
+            ProbeWindows::whole(constants.iter_batch_size)
+        }
+        _ => ProbeWindows::new(buffers.probe.size(), constants.iter_batch_size, probed_data),
+    };
+    if windows.is_streamed() {
+        debug!(
+            "Streaming the reference orbits in windows of {} iterations",
+            windows.window_len
+        );
+    }
+    let (hybrid, hybrid_len) = hybrid_params(image);
+    let julia_point = match image.fractal_kind.julia_point() {
+        None => (0.0, 0.0),
+        Some(pt) => (pt.x.to_f64(), pt.y.to_f64()),
+    };
+
     // Compute passes have encountered timeouts on some GPUs, so we split the compute passes into
     // multiple smaller passes.
-    for i in 0..=(image.max_iter / constants.iter_batch_size) {
+    let mut iter_offset = start_iter.max(resume_iter);
+    // the windows of the reference orbits needed by the points in the next batch
+    let mut pending = windows.windows_until(iter_offset + windows.batch_size);
+    while iter_offset < image.max_iter {
+        if cancelled.load(Ordering::Relaxed) {
+            return;
+        }
+        let chunk_max_iter = windows.batch_size.min(image.max_iter - iter_offset) as u32;
         // Create encoder for CPU - GPU communication
         let mut encoder =
             device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        // every point starts the batch in the pass of its window
+        encoder.clear_buffer(&buffers.window_steps, 0, None);
+        encoder.clear_buffer(&buffers.window_requests, 0, None);
 
-        // Begin compute dispatch
-        {
-            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: None,
-                timestamp_writes: None,
-            });
-            cpass.set_bind_group(0, &bind_groups.compute_buffers, &[]);
-            cpass.set_bind_group(1, &bind_groups.compute_parameters, &[]);
-            cpass.set_pipeline(compute_pipeline);
-            cpass.dispatch_workgroups(
-                (texture_size.width as f64 / 16.0).ceil() as u32,
-                (texture_size.height as f64 / 16.0).ceil() as u32,
-                1,
+        // run a pass per window until every point has finished the batch. Orbits that
+        // fit are bound whole, so their batches are a single pass.
+        let mut next_pending = if windows.is_streamed() {
+            BTreeSet::new()
+        } else {
+            BTreeSet::from([0])
+        };
+        while let Some(window) = pending.pop_first() {
+            // the passes of the later windows are not submitted after a cancel
+            if cancelled.load(Ordering::Relaxed) {
+                return;
+            }
+            if windows.is_streamed() {
+                let Some(points) =
+                    read_or_cancel(windows.window_points(probed_data, window), &cancelled)
+                else {
+                    return;
+                };
+                write_probe(gpu_data, 2 * windows.head_len, &points, algorithm);
+            }
+
+            // Begin compute dispatch
+            {
+                let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: None,
+                    timestamp_writes: None,
+                });
+                cpass.set_bind_group(0, &bind_groups.compute_buffers, &[]);
+                cpass.set_bind_group(1, &bind_groups.compute_parameters, &[]);
+                cpass.set_pipeline(compute_pipeline);
+                cpass.dispatch_workgroups(
+                    (texture_size.width as f64 / 16.0).ceil() as u32,
+                    (texture_size.height as f64 / 16.0).ceil() as u32,
+                    1,
+                );
+            }
+
+            let command_buffer = encoder.finish();
+            // Update the parameters
+            let parameters = ComputeParams {
+                width: texture_size.width,
+                height: texture_size.height,
+                max_iter: image.max_iter as u32,
+                chunk_max_iter,
+                probe_len: probe_len as u32,
+                critical_len: probed_data.critical.len() as u32,
+                iter_offset: iter_offset as u32,
+                start_iter: start_iter as u32,
+                glitch_pass,
+                power: image.fractal_kind.power(),
+                hybrid,
+                hybrid_len,
+                x,
+                y,
+                cx: reference.x.to_f32(),
+                cy: reference.y.to_f32(),
+                zoom: image.viewport.zoom as f32,
+                julia_x: julia_point.0 as f32,
+                julia_y: julia_point.1 as f32,
+                resume_iter: resume_iter as u32,
+                window_start: windows.window_start(window) as u32,
+                window_len: windows.window_len as u32,
+                head_len: windows.head_len as u32,
+                _padding: 0,
+                transform: image.viewport.linear_transform_f32(),
+                x_f64: image.viewport.center.x.to_f64(),
+                y_f64: image.viewport.center.y.to_f64(),
+                cx_f64: reference.x.to_f64(),
+                cy_f64: reference.y.to_f64(),
+                julia_x_f64: julia_point.0,
+                julia_y_f64: julia_point.1,
+                scale_f64: (-image.viewport.zoom).exp2(),
+            };
+            queue.write_buffer(
+                &buffers.compute_parameters,
+                0,
+                bytemuck::cast_slice(&[parameters]),
             );
+
+            // submit the compute shader command buffer
+            let si = queue.submit(Some(command_buffer));
+            // This slows down render times, so we avoid it in release
+            #[cfg(debug_assertions)]
+            time!("Compute step batch";
+                let _ = device.poll(wgpu::PollType::Wait { submission_index: Some(si), timeout: Some(Duration::from_secs(1)) });
+            );
+            #[cfg(not(debug_assertions))]
+            let _ = si;
+            encoder =
+                device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+
+            // the paused points continue in the passes of the later windows they requested
+            if windows.is_streamed() {
+                let Some(requests) = gpu_data.get_window_requests() else {
+                    // the paused points can not be continued, so the render is
+                    // cancelled rather than colored with their partial iterations
+                    tracing::error!("Failed to read the requested windows of the reference orbit");
+                    cancelled.store(true, Ordering::Relaxed);
+                    return;
+                };
+                let (current, next) = requests.split_at(PROBE_WINDOW_WORDS);
+                pending.extend(ProbeWindows::requested(current).filter(|&other| other > window));
+                next_pending = ProbeWindows::requested(next).collect();
+            }
         }
+        pending = next_pending;
+        iter_offset += chunk_max_iter as u64;
+        status_callback(StatusMessage::Progress(
+            format!("Computing iteration {} of {}", iter_offset, image.max_iter),
+            iter_offset as f64 / image.max_iter as f64,
+        ));
+    }
+}
 
-        let command_buffer = encoder.finish();
-        let julia_point = match &image.fractal_kind {
-            crate::types::FractalKind::Mandelbrot => (0.0, 0.0),
-            crate::types::FractalKind::Julia(pt) => (pt.x.to_f32(), pt.y.to_f32()),
-        };
-        // Update the parameters
+/// Runs a compute shader that does not use a reference orbit, such as the Lyapunov and
+/// Newton shaders, in batches of iterations like [`run_compute_step`]. `sequence` is passed
+/// as the hybrid sequence, and `start_iter` as the start iteration.
+#[expect(clippy::too_many_arguments)]
+fn run_batched_step(
+    pipeline: &ComputePipeline,
+    image: &Image,
+    max_iter: u64,
+    start_iter: u32,
+    sequence: (u32, u32),
+    gpu_data: &GPUData,
+    cancelled: Arc<AtomicBool>,
+    status_callback: &mut impl FnMut(StatusMessage),
+) {
+    let GPUData {
+        shared: SharedState { device, queue, .. },
+        bind_groups,
+        buffers,
+        constants,
+        ..
+    } = gpu_data;
+    let texture_size: Extent3d = (&image.viewport).into();
+    let (hybrid, hybrid_len) = sequence;
+
+    let mut iter_offset = 0;
+    while iter_offset < max_iter {
+        if cancelled.load(Ordering::Relaxed) {
+            return;
+        }
         let parameters = ComputeParams {
             width: texture_size.width,
             height: texture_size.height,
-            max_iter: image.max_iter as u32,
-            chunk_max_iter: if (constants.iter_batch_size + 1) * i > image.max_iter {
-                (image.max_iter % constants.iter_batch_size) as u32
-            } else {
-                constants.iter_batch_size as u32
-            },
-            probe_len: probe_len as u32,
-            iter_offset: (i * constants.iter_batch_size) as u32,
-            x,
-            y,
-            cx: image.probe_location.x.to_f32(),
-            cy: image.probe_location.y.to_f32(),
+            max_iter: max_iter as u32,
+            chunk_max_iter: constants.iter_batch_size.min(max_iter - iter_offset) as u32,
+            probe_len: 0,
+            critical_len: 0,
+            iter_offset: iter_offset as u32,
+            start_iter,
+            glitch_pass: 0,
+            power: image.fractal_kind.power(),
+            hybrid,
+            hybrid_len,
+            x: image.viewport.center.x.to_f32(),
+            y: image.viewport.center.y.to_f32(),
+            cx: 0.0,
+            cy: 0.0,
             zoom: image.viewport.zoom as f32,
-            julia_x: julia_point.0,
-            julia_y: julia_point.1,
+            julia_x: 0.0,
+            julia_y: 0.0,
+            resume_iter: 0,
+            window_start: 0,
+            window_len: u32::MAX,
+            head_len: 0,
+            _padding: 0,
+            transform: image.viewport.linear_transform_f32(),
+            x_f64: image.viewport.center.x.to_f64(),
+            y_f64: image.viewport.center.y.to_f64(),
+            cx_f64: 0.0,
+            cy_f64: 0.0,
+            julia_x_f64: 0.0,
+            julia_y_f64: 0.0,
+            scale_f64: (-image.viewport.zoom).exp2(),
         };
-        if parameters.chunk_max_iter == 0 {
-            break;
-        }
         queue.write_buffer(
             &buffers.compute_parameters,
             0,
             bytemuck::cast_slice(&[parameters]),
         );
 
-        // submit the compute shader command buffer
-        let si = queue.submit(Some(command_buffer));
-        // This slows down render times, so we avoid it in release
-        #[cfg(debug_assertions)]
-        time!("Compute step batch";
-            let _ = device.poll(wgpu::PollType::Wait { submission_index: Some(si), timeout: Some(Duration::from_secs(1)) });
-        );
-        #[cfg(not(debug_assertions))]
-        let _ = si;
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: None,
+                timestamp_writes: None,
+            });
+            cpass.set_bind_group(0, &bind_groups.compute_buffers, &[]);
+            cpass.set_bind_group(1, &bind_groups.compute_parameters, &[]);
+            cpass.set_pipeline(pipeline);
+            cpass.dispatch_workgroups(
+                texture_size.width.div_ceil(16),
+                texture_size.height.div_ceil(16),
+                1,
+            );
+        }
+        let si = queue.submit(Some(encoder.finish()));
+        let _ = device.poll(wgpu::PollType::Wait {
+            submission_index: Some(si),
+            timeout: Some(Duration::from_secs(1)),
+        });
+
+        iter_offset += parameters.chunk_max_iter as u64;
         status_callback(StatusMessage::Progress(
-            format!(
-                "Computing iteration {} of {}",
-                i * constants.iter_batch_size + parameters.chunk_max_iter as u64,
-                image.max_iter
-            ),
-            (i * constants.iter_batch_size + parameters.chunk_max_iter as u64) as f64
-                / image.max_iter as f64,
+            format!("Computing iteration {iter_offset} of {max_iter}"),
+            iter_offset as f64 / max_iter as f64,
         ));
     }
 }
@@ -298,45 +1090,55 @@ pub fn save_to_file(
 ) {
     status_callback(StatusMessage::Progress("Fetching image data".into(), 0.0));
     if let Some(data) = gpu_data.get_texture_data() {
-        status_callback(StatusMessage::Progress("Saving image".into(), 0.0));
-        let mut img = image::DynamicImage::ImageRgba8(
-            ImageBuffer::from_raw(
-                image_settings.viewport.width as u32,
-                image_settings.viewport.height as u32,
-                data,
-            )
-            .expect("image data to be properly formatted"),
-        );
-        img = image::DynamicImage::ImageRgb8(img.flipv().into_rgb8());
-        if let Err(err) = img.save(path) {
-            tracing::error!("Failed to save image: {err}");
-            status_callback(StatusMessage::Progress(
-                format!("Failed to save image: {err}"),
-                0.0,
-            ));
-        } else {
-            // add metadata
-            if is_metadata_supported(path) {
-                let mut meta = Metadata::new();
-                let serialized = serde_json::to_string(image_settings);
-                match serialized {
-                    Err(err) => {
-                        tracing::error!("Failed to save image: {err}");
-                        status_callback(StatusMessage::Progress(
-                            format!("Failed to save image: {err}"),
-                            0.0,
-                        ));
-                    }
-                    Ok(description) => {
-                        meta.set_tag(ExifTag::ImageDescription(description));
-                        meta.set_tag(ExifTag::Software("Corgi".into()));
-                        if let Err(err) = meta.write_to_file(path) {
-                            tracing::error!("Failed to write metadata to file: {err:?}");
-                        }
+        save_image_data(data, image_settings, path, status_callback);
+    }
+}
+
+/// Saves the RGBA data of a rendered image to a file, with the image settings as metadata
+fn save_image_data(
+    data: Vec<u8>,
+    image_settings: &Image,
+    path: &Path,
+    mut status_callback: impl FnMut(StatusMessage),
+) {
+    status_callback(StatusMessage::Progress("Saving image".into(), 0.0));
+    let mut img = image::DynamicImage::ImageRgba8(
+        ImageBuffer::from_raw(
+            image_settings.viewport.width as u32,
+            image_settings.viewport.height as u32,
+            data,
+        )
+        .expect("image data to be properly formatted"),
+    );
+    img = image::DynamicImage::ImageRgb8(img.flipv().into_rgb8());
+    if let Err(err) = img.save(path) {
+        tracing::error!("Failed to save image: {err}");
+        status_callback(StatusMessage::Progress(
+            format!("Failed to save image: {err}"),
+            0.0,
+        ));
+    } else {
+        // add metadata
+        if is_metadata_supported(path) {
+            let mut meta = Metadata::new();
+            let serialized = serde_json::to_string(image_settings);
+            match serialized {
+                Err(err) => {
+                    tracing::error!("Failed to save image: {err}");
+                    status_callback(StatusMessage::Progress(
+                        format!("Failed to save image: {err}"),
+                        0.0,
+                    ));
+                }
+                Ok(description) => {
+                    meta.set_tag(ExifTag::ImageDescription(description));
+                    meta.set_tag(ExifTag::Software("Corgi".into()));
+                    if let Err(err) = meta.write_to_file(path) {
+                        tracing::error!("Failed to write metadata to file: {err:?}");
                     }
                 }
             }
-            status_callback(StatusMessage::Progress("Image save complete".into(), 1.0));
         }
+        status_callback(StatusMessage::Progress("Image save complete".into(), 1.0));
     }
 }
@@ -0,0 +1,140 @@
+/*!
+# Newton Fractals
+
+Renders the [`RenderMode::Newton`](crate::types::RenderMode::Newton) mode, which iterates
+Newton's method `z - R p(z) / p'(z)` on a polynomial from each point until it converges,
+and the Nova fractal `z - R p(z) / p'(z) + c`, which starts from the same point for each c.
+
+The shader writes to the same buffers as the escape time shaders. Converged points use the
+internal coloring, with the index of the root they converged to (or the root closest to
+their limit, for the Nova fractal) and the smooth number of iterations they took.
+ */
+
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use rug::Complex;
+
+use super::gpu_setup::GPUData;
+use super::run_batched_step;
+use crate::types::{Image, MAX_NEWTON_DEGREE, NewtonOptions, NewtonParams, StatusMessage};
+
+/// The maximum number of iterations of the root search
+const MAX_ROOT_ITERATIONS: usize = 1000;
+
+/// Runs the Newton shader on the GPU
+pub(super) fn run_newton_step(
+    image: &Image,
+    options: &NewtonOptions,
+    gpu_data: &GPUData,
+    cancelled: Arc<AtomicBool>,
+    status_callback: &mut impl FnMut(StatusMessage),
+) {
+    let polynomial = options.polynomial();
+    let roots = polynomial_roots(polynomial);
+    let mut params = NewtonParams {
+        degree: polynomial.len().saturating_sub(1) as u32,
+        root_count: roots.len() as u32,
+        nova: options.nova as u32,
+        tolerance: options.tolerance,
+        relaxation: options.relaxation,
+        start: options.start,
+        coefficients: [[0.0; 4]; MAX_NEWTON_DEGREE + 1],
+        roots: [[0.0; 4]; MAX_NEWTON_DEGREE],
+    };
+    for (coefficient, &[re, im]) in params.coefficients.iter_mut().zip(polynomial) {
+        *coefficient = [re, im, 0.0, 0.0];
+    }
+    for (root, [re, im]) in params.roots.iter_mut().zip(roots) {
+        *root = [re as f32, im as f32, 0.0, 0.0];
+    }
+    gpu_data.shared.queue.write_buffer(
+        &gpu_data.buffers.newton_parameters,
+        0,
+        bytemuck::cast_slice(&[params]),
+    );
+
+    run_batched_step(
+        &gpu_data.newton_pipeline,
+        image,
+        image.max_iter,
+        0,
+        (0, 0),
+        gpu_data,
+        cancelled,
+        status_callback,
+    );
+}
+
+/// Finds the roots of a polynomial with the Durand-Kerner method, given the complex
+/// coefficient of each power of z starting from z^0. Trailing zero coefficients are ignored,
+/// and repeated roots are returned once for each repetition.
+///
+/// ```
+/// use corgi::image_gen::polynomial_roots;
+///
+/// // z^3 - 1
+/// let roots = polynomial_roots(&[[-1.0, 0.0], [0.0, 0.0], [0.0, 0.0], [1.0, 0.0]]);
+/// assert_eq!(roots.len(), 3);
+/// for [re, im] in roots {
+///     assert!((re.hypot(im) - 1.0).abs() < 1e-6);
+/// }
+/// ```
+pub fn polynomial_roots(coefficients: &[[f32; 2]]) -> Vec<[f64; 2]> {
+    const PRECISION: u32 = 53;
+    let Some(degree) = coefficients.iter().rposition(|&c| c != [0.0, 0.0]) else {
+        return Vec::new();
+    };
+    // divide by the leading coefficient, so the polynomial is monic
+    let leading = Complex::with_val(
+        PRECISION,
+        (coefficients[degree][0], coefficients[degree][1]),
+    );
+    let monic: Vec<Complex> = coefficients[..=degree]
+        .iter()
+        .map(|&[re, im]| Complex::with_val(PRECISION, (re, im)) / &leading)
+        .collect();
+    let evaluate = |z: &Complex| {
+        monic
+            .iter()
+            .rev()
+            .fold(Complex::new(PRECISION), |p, coefficient| {
+                p * z + coefficient
+            })
+    };
+
+    // start from powers of a point that is neither real nor a root of unity
+    let seed = Complex::with_val(PRECISION, (0.4, 0.9));
+    let mut roots = Vec::with_capacity(degree);
+    let mut root = Complex::with_val(PRECISION, 1);
+    for _ in 0..degree {
+        roots.push(root.clone());
+        root *= &seed;
+    }
+    for _ in 0..MAX_ROOT_ITERATIONS {
+        let mut largest_step = 0.0f64;
+        for i in 0..degree {
+            let denominator = roots
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .fold(Complex::with_val(PRECISION, 1), |product, (_, root)| {
+                    product * Complex::with_val(PRECISION, &roots[i] - root)
+                });
+            if denominator.is_zero() {
+                continue;
+            }
+            let step = evaluate(&roots[i]) / denominator;
+            largest_step =
+                largest_step.max(Complex::with_val(PRECISION, step.abs_ref()).real().to_f64());
+            roots[i] -= step;
+        }
+        if largest_step < 1e-12 {
+            break;
+        }
+    }
+    roots
+        .into_iter()
+        .map(|root| [root.real().to_f64(), root.imag().to_f64()])
+        .collect()
+}
@@ -0,0 +1,407 @@
+/*!
+# Orbit Cache
+
+Reference orbits are computed at full precision, which can take minutes at deep zooms.
+The computed orbits are stored in a cache directory, so that rendering the same location
+again, such as re-opening a file or rendering the frames of an animation, loads them
+instead. Orbits are keyed by their start, precision, power and formulas. Longer orbits
+are shortened when loaded, and shorter ones are continued from the full precision state
+stored with them, appending the new points to their file.
+
+Each file holds the key, the number of points, the points and the full precision tail:
+
+`[magic | key | length | points | tail]`
+
+The points of stored orbits are read from their files when they are needed, so that
+orbits of hundreds of millions of points do not have to fit in memory. Appending to an
+orbit locks its file, so that the GUI and the CLI can share the cache directory.
+ */
+
+use std::cmp::Reverse;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use rug::Float;
+use tracing::{debug, warn};
+
+use super::probe::FloatExp;
+
+/// Identifies the format of the cache files, which is changed along with it
+const MAGIC: &[u8; 8] = b"CORGORB1";
+
+/// The size in bytes of a stored point, whose parts are an f64 mantissa and an i32 exponent
+const STORED_POINT_SIZE: u64 = 24;
+
+/// The length of an orbit whose points are being appended, which is not loaded
+const INCOMPLETE: u64 = u64::MAX;
+
+/// # OrbitCache
+/// A directory of computed reference orbits, which removes the least recently
+/// used orbits once their files take more than `max_size` bytes
+#[derive(Debug, Clone)]
+pub struct OrbitCache {
+    directory: PathBuf,
+    max_size: u64,
+}
+
+/// An orbit loaded from the cache
+pub(crate) struct CachedOrbit {
+    pub orbit: StoredOrbit,
+    /// The full precision z after the last point, or `None` if the orbit
+    /// escaped or was shortened to the requested length
+    pub tail: Option<(Float, Float)>,
+}
+
+/// # StoredOrbit
+/// The points of an orbit in the cache, which are read from its file. The file is kept
+/// open, so that the points can still be read after it is replaced or removed.
+#[derive(Debug, Clone)]
+pub(crate) struct StoredOrbit {
+    file: Arc<Mutex<File>>,
+    /// The position of the first point in the file
+    points_start: u64,
+    /// The number of points of the orbit, which may be less than the file holds
+    pub len: usize,
+}
+
+impl OrbitCache {
+    pub fn new(directory: PathBuf, max_size: u64) -> Self {
+        Self {
+            directory,
+            max_size,
+        }
+    }
+
+    /// Whether the file of an orbit of `len` points stored under `key` fits in the cache,
+    /// with a tail at `precision` if it has one
+    pub(crate) fn fits(&self, key: &str, len: u64, precision: Option<u32>) -> bool {
+        file_size(key, len, precision) <= self.max_size
+    }
+
+    /// Loads at most `max_iter` points of the orbit stored under `key`, reading its
+    /// tail at `precision`. Returns `None` if there is no such orbit, or it can not be read.
+    pub(crate) fn load(&self, key: &str, max_iter: u64, precision: u32) -> Option<CachedOrbit> {
+        let path = self.path(key);
+        if !path.exists() {
+            return None;
+        }
+        match read_orbit(&path, key, max_iter, precision) {
+            Ok(cached) => {
+                if let Some(cached) = &cached {
+                    debug!("Loaded {} cached orbit points", cached.orbit.len);
+                }
+                cached
+            }
+            Err(err) => {
+                warn!("Failed to load cached orbit {}: {err}", path.display());
+                None
+            }
+        }
+    }
+
+    /// Stores the points of the orbit under `key` from the point at `start`, with the full
+    /// precision z after its last point if it can be continued. The points before `start`
+    /// have to be stored already, since only the new points of an orbit that continues the
+    /// stored one are appended to its file. Returns the stored orbit, or `None` if it is
+    /// larger than the whole cache or can not be stored.
+    pub(crate) fn store(
+        &self,
+        key: &str,
+        start: usize,
+        points: &[[FloatExp; 2]],
+        tail: Option<&(Float, Float)>,
+    ) -> Option<StoredOrbit> {
+        let len = start + points.len();
+        if !self.fits(key, len as u64, tail.map(|(real, _)| real.prec())) {
+            debug!("Not caching an orbit of {len} points, which is larger than the cache");
+            return None;
+        }
+        let path = self.path(key);
+        let result = fs::create_dir_all(&self.directory)
+            .map_err(Into::into)
+            .and_then(|_| match append_orbit(&path, key, start, points, tail) {
+                Ok(true) => Ok(()),
+                // there is no stored orbit to continue, or it can not be read
+                _ if start == 0 => write_orbit(&path, key, points, tail),
+                Ok(false) => Err(eyre!("The start of the orbit is not stored")),
+                Err(err) => Err(err),
+            })
+            .and_then(|_| self.evict())
+            .and_then(|_| StoredOrbit::open(&path, key, len));
+        match result {
+            Ok(stored) => Some(stored),
+            Err(err) => {
+                warn!("Failed to store orbit in the cache: {err}");
+                None
+            }
+        }
+    }
+
+    /// The file of the orbit stored under `key`. The hash is only used to find the file,
+    /// since the full key is checked when it is read.
+    fn path(&self, key: &str) -> PathBuf {
+        self.directory
+            .join(format!("{:016x}.orbit", fnv_hash(key.as_bytes())))
+    }
+
+    /// Removes the least recently used orbits until the rest fit in the maximum size
+    fn evict(&self) -> Result<()> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "orbit")
+            {
+                let metadata = fs::metadata(&path)?;
+                files.push((metadata.modified()?, metadata.len(), path));
+            }
+        }
+        // the most recently used first
+        files.sort_by_key(|(modified, ..)| Reverse(*modified));
+        let mut size = 0;
+        for (_, length, path) in files {
+            if size + length > self.max_size {
+                fs::remove_file(path)?;
+            } else {
+                size += length;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StoredOrbit {
+    /// Opens the first `len` points of the orbit stored at `path`
+    fn open(path: &Path, key: &str, len: usize) -> Result<Self> {
+        Ok(Self {
+            file: Arc::new(Mutex::new(File::open(path)?)),
+            points_start: points_start(key),
+            len,
+        })
+    }
+
+    /// Reads the points in `range`, which has to be within the orbit
+    pub fn read(&self, range: Range<usize>) -> Result<Vec<[FloatExp; 2]>> {
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(
+            self.points_start + range.start as u64 * STORED_POINT_SIZE,
+        ))?;
+        let mut reader = BufReader::new(&mut *file);
+        let mut points = Vec::with_capacity(range.len());
+        for _ in range {
+            points.push([read_float_exp(&mut reader)?, read_float_exp(&mut reader)?]);
+        }
+        Ok(points)
+    }
+}
+
+/// The position of the first point in the file of the orbit stored under `key`
+fn points_start(key: &str) -> u64 {
+    (MAGIC.len() + size_of::<u64>() + key.len() + size_of::<u64>()) as u64
+}
+
+/// An upper bound of the size of the file of an orbit of `len` points stored under `key`,
+/// whose tail is written at `precision` if it has one
+fn file_size(key: &str, len: u64, precision: Option<u32>) -> u64 {
+    // the sign, point and exponent of a hexadecimal float take at most 16 more bytes
+    let tail = precision.map_or(0, |precision| {
+        2 * (size_of::<u64>() as u64 + precision.div_ceil(4) as u64 + 16)
+    });
+    points_start(key) + len * STORED_POINT_SIZE + 1 + tail
+}
+
+/// The 64-bit FNV-1a hash of `bytes`, which unlike the hasher of the standard
+/// library is the same in every build, so that the cache files can be found again
+fn fnv_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Reads the header of a cached orbit, returning its length, or `None`
+/// if it was stored under another key with the same hash
+fn read_header(reader: &mut impl Read, key: &str) -> Result<Option<u64>> {
+    let mut magic = [0; MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(eyre!("Unknown file format"));
+    }
+    if read_string(reader)? != key {
+        return Ok(None);
+    }
+    let length = read_u64(reader)?;
+    if length == INCOMPLETE {
+        return Err(eyre!("The orbit was not completely written"));
+    }
+    Ok(Some(length))
+}
+
+/// Opens a cached orbit, which is `None` if it was stored under another key
+/// with the same hash. The file is locked while its header and tail are read,
+/// so that an orbit that is being appended to is read once it is written.
+fn read_orbit(
+    path: &Path,
+    key: &str,
+    max_iter: u64,
+    precision: u32,
+) -> Result<Option<CachedOrbit>> {
+    let mut file = File::open(path)?;
+    // mark the orbit as recently used
+    let _ = file.set_modified(SystemTime::now());
+    file.lock_shared()?;
+    let Some(length) = read_header(&mut BufReader::new(&file), key)? else {
+        return Ok(None);
+    };
+
+    let tail = if length > max_iter {
+        None
+    } else {
+        file.seek(SeekFrom::Start(
+            points_start(key) + length * STORED_POINT_SIZE,
+        ))?;
+        let mut reader = BufReader::new(&file);
+        let mut has_tail = [0];
+        reader.read_exact(&mut has_tail)?;
+        if has_tail[0] == 1 {
+            let mut read_float = || -> Result<Float> {
+                let parsed = Float::parse_radix(read_string(&mut reader)?, 16)?;
+                Ok(Float::with_val(precision, parsed))
+            };
+            Some((read_float()?, read_float()?))
+        } else {
+            None
+        }
+    };
+    // the points are not changed by appending, so they are read without the lock
+    file.unlock()?;
+    let orbit = StoredOrbit {
+        file: Arc::new(Mutex::new(file)),
+        points_start: points_start(key),
+        len: length.min(max_iter) as usize,
+    };
+    Ok(Some(CachedOrbit { orbit, tail }))
+}
+
+/// Writes an orbit to a temporary file first, so that an interrupted
+/// write does not leave a truncated orbit behind
+fn write_orbit(
+    path: &Path,
+    key: &str,
+    orbit: &[[FloatExp; 2]],
+    tail: Option<&(Float, Float)>,
+) -> Result<()> {
+    let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+    let mut writer = BufWriter::new(File::create(&temporary)?);
+    writer.write_all(MAGIC)?;
+    write_bytes(&mut writer, key.as_bytes())?;
+    writer.write_all(&(orbit.len() as u64).to_le_bytes())?;
+    write_points(&mut writer, orbit, tail)?;
+    writer.into_inner().map_err(|err| err.into_error())?;
+    fs::rename(temporary, path)?;
+    Ok(())
+}
+
+/// Appends the points of an orbit from the point at `start` to the orbit stored at
+/// `path`, replacing its tail. Returns `false` if no orbit is stored under `key`, or it
+/// ends before `start`. The length is marked as incomplete until the points are written,
+/// so that an interrupted append is probed again instead of being loaded. The file is
+/// locked while appending, so that another process appending to the same orbit waits
+/// for it and reads the new length.
+fn append_orbit(
+    path: &Path,
+    key: &str,
+    start: usize,
+    points: &[[FloatExp; 2]],
+    tail: Option<&(Float, Float)>,
+) -> Result<bool> {
+    if !path.exists() {
+        return Ok(false);
+    }
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    file.lock()?;
+    let Some(length) = read_header(&mut BufReader::new(&file), key)? else {
+        return Ok(false);
+    };
+    if length < start as u64 {
+        return Ok(false);
+    }
+    // a longer stored orbit already holds all of the points
+    let new_points = &points[(length as usize - start).min(points.len())..];
+    if new_points.is_empty() {
+        return Ok(true);
+    }
+
+    let length_start = points_start(key) - size_of::<u64>() as u64;
+    file.seek(SeekFrom::Start(length_start))?;
+    file.write_all(&INCOMPLETE.to_le_bytes())?;
+    file.seek(SeekFrom::Current((length * STORED_POINT_SIZE) as i64))?;
+    let mut writer = BufWriter::new(&file);
+    write_points(&mut writer, new_points, tail)?;
+    writer.into_inner().map_err(|err| err.into_error())?;
+    let end = file.stream_position()?;
+    file.set_len(end)?;
+    file.seek(SeekFrom::Start(length_start))?;
+    file.write_all(&(start as u64 + points.len() as u64).to_le_bytes())?;
+    Ok(true)
+}
+
+/// Writes the points of an orbit, followed by its tail
+fn write_points(
+    writer: &mut impl Write,
+    orbit: &[[FloatExp; 2]],
+    tail: Option<&(Float, Float)>,
+) -> Result<()> {
+    for point in orbit.iter().flatten() {
+        writer.write_all(&point.mantissa.to_le_bytes())?;
+        writer.write_all(&point.exponent.to_le_bytes())?;
+    }
+    match tail {
+        Some((real, imag)) => {
+            writer.write_all(&[1])?;
+            write_bytes(writer, real.to_string_radix(16, None).as_bytes())?;
+            write_bytes(writer, imag.to_string_radix(16, None).as_bytes())?;
+        }
+        None => writer.write_all(&[0])?,
+    }
+    Ok(())
+}
+
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> Result<()> {
+    writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_string(reader: &mut impl Read) -> Result<String> {
+    let length = read_u64(reader)?;
+    let mut bytes = Vec::new();
+    reader.take(length).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != length {
+        return Err(eyre!("Unexpected end of file"));
+    }
+    Ok(String::from_utf8(bytes)?)
+}
+
+fn read_float_exp(reader: &mut impl Read) -> Result<FloatExp> {
+    let mut mantissa = [0; 8];
+    let mut exponent = [0; 4];
+    reader.read_exact(&mut mantissa)?;
+    reader.read_exact(&mut exponent)?;
+    Ok(FloatExp {
+        mantissa: f64::from_le_bytes(mantissa),
+        exponent: i32::from_le_bytes(exponent),
+    })
+}
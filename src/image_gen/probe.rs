@@ -1,8 +1,44 @@
 use std::fmt::Debug;
+use std::ops::{Add, Mul, Range};
+use std::sync::atomic::{AtomicBool, Ordering};
 
+use color_eyre::Result;
 use rug::Float;
+use rug::ops::PowAssign;
+use tracing::warn;
 
-use crate::types::{ComplexPoint, ESCAPE_RADIUS, get_precision};
+use super::orbit_cache::{CachedOrbit, OrbitCache, StoredOrbit};
+use crate::types::{
+    BlaEntry, BlaHeader, ComplexPoint, ESCAPE_RADIUS, Formula, FractalKind, MAX_BLA_LEVELS,
+    Nucleus, SeriesParams, Viewport, complex_pow, get_precision,
+};
+
+/// The maximum truncation error of the series approximation, relative to the
+/// magnitude of the approximated delta. This is about the precision of an f32,
+/// since the shader can not be more accurate than that anyway.
+const SERIES_TOLERANCE: f64 = 1.0 / (1u64 << 24) as f64;
+
+/// The relative size of the squared delta term below which a single perturbed
+/// iteration is treated as linear by the bilinear approximation
+const BLA_EPSILON: f64 = 1.0 / (1u64 << 24) as f64;
+
+/// The smallest level stored in the bilinear approximation table. The lower levels
+/// would take most of the memory while skipping only a few iterations each.
+const BLA_MIN_LEVEL: u32 = 3;
+
+/// The maximum number of Newton steps taken when searching for a nucleus
+const MAX_NEWTON_STEPS: u32 = 64;
+
+/// The size of a Newton step, relative to the radius of the view,
+/// below which the nucleus is considered found
+const NEWTON_TOLERANCE: f64 = 1.0 / (1u64 << 24) as f64;
+
+/// The number of full precision iterations between checks for cancellation
+pub(super) const CANCEL_CHECK_INTERVAL: u64 = 1 << 12;
+
+/// The number of points of an orbit stored in the cache that are probed between the
+/// writes to its file, which is the most of its points that are held in memory
+const ORBIT_CHUNK_LEN: u64 = 1 << 20;
 
 /// # FromFloat
 /// A trait to convert a `rug::Float` to another type.
@@ -24,64 +60,1098 @@ impl FromFloat for f32 {
     }
 }
 
-/// Generates a vector of iterated points for a given complex number in the mandelbrot set.
-/// The resulting vector will be of length `max_iter` or less if the point escapes.
+/// # FloatExp
+/// A float with an extended exponent, `mantissa * 2^exponent`. The reference orbit is
+/// stored in this form, since its points can get too close to zero for an f64
+/// at extreme zooms, especially near the centers of minibrots.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FloatExp {
+    /// The mantissa, in `0.5 <= |mantissa| < 1`, or zero
+    pub mantissa: f64,
+    pub exponent: i32,
+}
+
+impl FloatExp {
+    /// Converts to an f64, which is rounded to zero if it is too small
+    pub fn to_f64(self) -> f64 {
+        // the scale is applied in two steps, since a single power of two
+        // is out of range for the smallest subnormal values
+        let half = self.exponent / 2;
+        self.mantissa * 2f64.powi(half) * 2f64.powi(self.exponent - half)
+    }
+
+    /// Whether the value is zero, whose exponent has no meaning
+    pub fn is_zero(self) -> bool {
+        self.mantissa == 0.0
+    }
+}
+
+impl FromFloat for FloatExp {
+    fn from_float(x: &Float) -> FloatExp {
+        let (mantissa, exponent) = x.to_f64_exp();
+        FloatExp { mantissa, exponent }
+    }
+}
+
+/// # ReferenceOrbit
+/// The high precision orbits that the perturbed compute shaders iterate relative to
+#[derive(Debug, Clone, Default)]
+pub struct ReferenceOrbit {
+    /// The orbit of the reference point
+    pub orbit: OrbitPoints,
+    /// The orbit of the critical point zero under the Julia formula, which every
+    /// point of a Julia set can be rebased to. Empty for other fractals.
+    pub critical: OrbitPoints,
+    /// The full precision state after the last point of each orbit, which is `None`
+    /// once the orbit escaped, or if it was not probed with [`ReferenceOrbit::probe`]
+    pub orbit_tail: Option<OrbitTail>,
+    pub critical_tail: Option<OrbitTail>,
+}
+
+impl ReferenceOrbit {
+    /// Probes the orbit of the reference point, and the critical orbit of Julia sets,
+    /// keeping their full precision state so that they can be extended later.
+    /// Orbits found in the cache are loaded instead, and probed orbits are stored in it
+    /// unless they are larger than the whole cache.
+    pub fn probe(
+        reference: &ComplexPoint,
+        max_iter: u64,
+        zoom: f64,
+        fractal_kind: &FractalKind,
+        formulas: &[Formula],
+        cache: Option<&OrbitCache>,
+        cancelled: &AtomicBool,
+    ) -> Self {
+        let (orbit, orbit_tail) = cached_orbit(
+            Some(OrbitTail::point(reference, zoom, fractal_kind, formulas)),
+            max_iter,
+            cache,
+            cancelled,
+        );
+        let (critical, critical_tail) = cached_orbit(
+            OrbitTail::critical(zoom, fractal_kind, formulas),
+            max_iter,
+            cache,
+            cancelled,
+        );
+        Self {
+            orbit,
+            critical,
+            orbit_tail,
+            critical_tail,
+        }
+    }
+
+    /// Continues both orbits up to `max_iter` points, unless they escaped, and stores
+    /// them in the cache. The points are identical to those of an orbit probed with the
+    /// higher max iteration. Returns `false` if an orbit can not be continued, since it
+    /// was loaded from a longer cached orbit without its full precision state.
+    ///
+    /// ```
+    /// use std::sync::atomic::AtomicBool;
+    ///
+    /// use corgi::image_gen::ReferenceOrbit;
+    /// use corgi::types::Image;
+    ///
+    /// let image = Image::default();
+    /// let cancelled = AtomicBool::new(false);
+    /// let probe = |max_iter| {
+    ///     ReferenceOrbit::probe(
+    ///         &image.probe_location,
+    ///         max_iter,
+    ///         image.viewport.zoom,
+    ///         &image.fractal_kind,
+    ///         &image.formulas(),
+    ///         None,
+    ///         &cancelled,
+    ///     )
+    /// };
+    /// let mut orbit = probe(1000);
+    /// assert!(orbit.extend(4000, None, &cancelled));
+    /// assert_eq!(orbit.orbit.len(), 4000);
+    /// assert_eq!(
+    ///     orbit.orbit.read(0..4000).unwrap(),
+    ///     probe(4000).orbit.read(0..4000).unwrap(),
+    /// );
+    /// ```
+    pub fn extend(
+        &mut self,
+        max_iter: u64,
+        cache: Option<&OrbitCache>,
+        cancelled: &AtomicBool,
+    ) -> bool {
+        let orbit = extend_orbit(
+            &mut self.orbit,
+            &mut self.orbit_tail,
+            max_iter,
+            cache,
+            cancelled,
+        );
+        let critical = extend_orbit(
+            &mut self.critical,
+            &mut self.critical_tail,
+            max_iter,
+            cache,
+            cancelled,
+        );
+        orbit && critical
+    }
+}
+
+/// # OrbitPoints
+/// The points of a reference orbit. Orbits stored in the cache are read from their file
+/// when their points are needed, so that orbits of hundreds of millions of points do not
+/// have to fit in memory. Other orbits are held in memory.
+#[derive(Debug, Clone)]
+pub struct OrbitPoints(Points);
+
+#[derive(Debug, Clone)]
+enum Points {
+    Memory(Vec<[FloatExp; 2]>),
+    Stored(StoredOrbit),
+}
+
+impl OrbitPoints {
+    /// The number of points of the orbit
+    pub fn len(&self) -> usize {
+        match &self.0 {
+            Points::Memory(points) => points.len(),
+            Points::Stored(stored) => stored.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reads the points in `range`, which is shortened to end with the orbit
+    pub fn read(&self, range: Range<usize>) -> Result<Vec<[FloatExp; 2]>> {
+        let range = range.start.min(self.len())..range.end.min(self.len());
+        match &self.0 {
+            Points::Memory(points) => Ok(points[range].to_vec()),
+            Points::Stored(stored) => stored.read(range),
+        }
+    }
+
+    /// Reads the points of a stored orbit into memory, to continue it there
+    fn in_memory(&mut self) -> Result<&mut Vec<[FloatExp; 2]>> {
+        if let Points::Stored(stored) = &self.0 {
+            self.0 = Points::Memory(stored.read(0..stored.len)?);
+        }
+        let Points::Memory(points) = &mut self.0 else {
+            unreachable!("the points were read into memory");
+        };
+        Ok(points)
+    }
+
+    /// Appends the points that continue the orbit up to `tail`, storing them in the cache
+    /// under `key`. Orbits that can not be stored continue in memory instead.
+    fn append(
+        &mut self,
+        points: Vec<[FloatExp; 2]>,
+        cache: &OrbitCache,
+        key: &str,
+        tail: Option<&OrbitTail>,
+    ) -> Result<()> {
+        let tail = tail.map(|tail| &tail.z);
+        match &mut self.0 {
+            Points::Stored(stored) => match cache.store(key, stored.len, &points, tail) {
+                Some(appended) => *stored = appended,
+                None => self.in_memory()?.extend(points),
+            },
+            Points::Memory(memory) => {
+                memory.extend(points);
+                if let Some(stored) = cache.store(key, 0, memory, tail) {
+                    self.0 = Points::Stored(stored);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for OrbitPoints {
+    fn default() -> Self {
+        Self(Points::Memory(Vec::new()))
+    }
+}
+
+impl From<Vec<[FloatExp; 2]>> for OrbitPoints {
+    fn from(points: Vec<[FloatExp; 2]>) -> Self {
+        Self(Points::Memory(points))
+    }
+}
+
+/// # OrbitTail
+/// The full precision state of an orbit of `z = f(z) + c` after its last point,
+/// with the temporaries used to iterate it
+#[derive(Debug, Clone)]
+pub struct OrbitTail {
+    z: (Float, Float),
+    c: (Float, Float),
+    power: u32,
+    formulas: Vec<Formula>,
+    scratch: [Float; 4],
+    /// Identifies the orbit in the cache by its start
+    key: String,
+}
+
+impl OrbitTail {
+    /// The start of an orbit at `z`
+    fn new(z: (Float, Float), c: (Float, Float), power: u32, formulas: &[Formula]) -> Self {
+        let precision = z.0.prec();
+        let key = format!(
+            "{precision} {} {} {} {} {power} {formulas:?}",
+            z.0.to_string_radix(16, None),
+            z.1.to_string_radix(16, None),
+            c.0.to_string_radix(16, None),
+            c.1.to_string_radix(16, None),
+        );
+        Self {
+            z,
+            c,
+            power,
+            formulas: formulas.to_vec(),
+            scratch: [(); 4].map(|_| Float::new(precision)),
+            key,
+        }
+    }
+
+    /// The start of the orbit of a point in the fractal
+    fn point(
+        ComplexPoint { x, y }: &ComplexPoint,
+        zoom: f64,
+        fractal_kind: &FractalKind,
+        formulas: &[Formula],
+    ) -> Self {
+        let precision = julia_precision(get_precision(zoom), fractal_kind);
+
+        // c = x + yi, z = 0 + 0i
+        let mut c = (Float::with_val(precision, x), Float::with_val(precision, y));
+        let mut z = (
+            Float::with_val(precision, 0.0),
+            Float::with_val(precision, 0.0),
+        );
+        // Julia sets start at the point instead, with the constant c of the fractal kind
+        if let Some(ComplexPoint { x: r, y: i }) = fractal_kind.julia_point() {
+            z = c;
+            c = (Float::with_val(precision, r), Float::with_val(precision, i));
+        }
+        Self::new(z, c, fractal_kind.power(), formulas)
+    }
+
+    /// The start of the orbit of the critical point zero of a Julia set,
+    /// or `None` for other fractals
+    fn critical(zoom: f64, fractal_kind: &FractalKind, formulas: &[Formula]) -> Option<Self> {
+        let ComplexPoint { x: r, y: i } = fractal_kind.julia_point()?;
+        let precision = julia_precision(get_precision(zoom), fractal_kind);
+        Some(Self::new(
+            (
+                Float::with_val(precision, 0.0),
+                Float::with_val(precision, 0.0),
+            ),
+            (Float::with_val(precision, r), Float::with_val(precision, i)),
+            fractal_kind.power(),
+            formulas,
+        ))
+    }
+}
+
+/// Generates a vector of iterated points for a given complex number in the fractal.
+/// Each iteration `n` uses the formula `formulas[n % formulas.len()]`.
+/// The resulting vector will be of length `max_iter` or less if the point escapes,
+/// or if the probe is stopped early by setting `cancelled`.
 pub fn probe<T>(
-    ComplexPoint { x, y }: &ComplexPoint,
+    location: &ComplexPoint,
+    max_iter: u64,
+    zoom: f64,
+    fractal_kind: &FractalKind,
+    formulas: &[Formula],
+    cancelled: &AtomicBool,
+) -> Vec<[T; 2]>
+where
+    T: FromFloat + Debug,
+{
+    let mut tail = Some(OrbitTail::point(location, zoom, fractal_kind, formulas));
+    start_orbit(&mut tail, max_iter, cancelled)
+}
+
+/// Generates the orbit of a point like [`probe`], loading it from the cache if it is
+/// stored there, and storing it otherwise. Unlike [`ReferenceOrbit::probe`], the full
+/// precision state is not kept, so the orbit can not be extended.
+pub fn probe_cached(
+    location: &ComplexPoint,
+    max_iter: u64,
+    zoom: f64,
+    fractal_kind: &FractalKind,
+    formulas: &[Formula],
+    cache: Option<&OrbitCache>,
+    cancelled: &AtomicBool,
+) -> OrbitPoints {
+    let tail = OrbitTail::point(location, zoom, fractal_kind, formulas);
+    cached_orbit(Some(tail), max_iter, cache, cancelled).0
+}
+
+/// Generates the orbit of the critical point zero of a Julia set, which is empty
+/// for other fractals. Unlike the orbit of a point in the view, it does not depend
+/// on the reference, so it only changes with the Julia constant.
+pub fn probe_critical<T>(
     max_iter: u64,
     zoom: f64,
-    julia_point: Option<&ComplexPoint>,
+    fractal_kind: &FractalKind,
+    formulas: &[Formula],
+    cancelled: &AtomicBool,
+) -> Vec<[T; 2]>
+where
+    T: FromFloat + Debug,
+{
+    let mut tail = OrbitTail::critical(zoom, fractal_kind, formulas);
+    start_orbit(&mut tail, max_iter, cancelled)
+}
+
+/// The precision needed for the orbits of a fractal. The Julia constant is kept at
+/// its full precision, since it may have been picked at a much deeper zoom than the view.
+fn julia_precision(precision: u32, fractal_kind: &FractalKind) -> u32 {
+    match fractal_kind.julia_point() {
+        Some(ComplexPoint { x, y }) => precision.max(x.prec()).max(y.prec()),
+        None => precision,
+    }
+}
+
+/// Generates the orbit starting at `tail`, which is empty if there is no tail
+fn start_orbit<T>(
+    tail: &mut Option<OrbitTail>,
+    max_iter: u64,
+    cancelled: &AtomicBool,
 ) -> Vec<[T; 2]>
 where
     T: FromFloat + Debug,
 {
-    let mut probed_point = Vec::new();
-    let precision = get_precision(zoom);
+    let mut orbit = Vec::new();
+    if let Some(OrbitTail {
+        z: (z_real, z_imag),
+        ..
+    }) = tail
+    {
+        orbit.push([T::from_float(z_real), T::from_float(z_imag)]);
+    }
+    iterate_orbit(&mut orbit, 0, tail, max_iter, cancelled);
+    orbit
+}
+
+/// Generates the orbit starting at `tail` like [`start_orbit`], continuing the orbit
+/// stored in the cache if there is one, and returns it with its final tail
+fn cached_orbit(
+    mut tail: Option<OrbitTail>,
+    max_iter: u64,
+    cache: Option<&OrbitCache>,
+    cancelled: &AtomicBool,
+) -> (OrbitPoints, Option<OrbitTail>) {
+    let Some(start) = &mut tail else {
+        return (OrbitPoints::default(), None);
+    };
+    let cached = cache.and_then(|cache| cache.load(&start.key, max_iter, start.z.0.prec()));
+    let mut orbit = match cached {
+        Some(CachedOrbit {
+            orbit,
+            tail: Some(z),
+        }) => {
+            start.z = z;
+            OrbitPoints(Points::Stored(orbit))
+        }
+        Some(CachedOrbit { orbit, tail: None }) => {
+            tail = None;
+            OrbitPoints(Points::Stored(orbit))
+        }
+        None => vec![[
+            FloatExp::from_float(&start.z.0),
+            FloatExp::from_float(&start.z.1),
+        ]]
+        .into(),
+    };
+    extend_orbit(&mut orbit, &mut tail, max_iter, cache, cancelled);
+    (orbit, tail)
+}
+
+/// Continues `orbit` from its tail with [`iterate_orbit`]. Orbits that fit in the cache
+/// are stored in it in chunks as they grow, and larger ones are continued in memory.
+/// Returns `false` if it has no tail to continue from, although it is shorter than
+/// `max_iter` and did not escape, or if its stored points can not be read.
+fn extend_orbit(
+    orbit: &mut OrbitPoints,
+    tail: &mut Option<OrbitTail>,
+    max_iter: u64,
+    cache: Option<&OrbitCache>,
+    cancelled: &AtomicBool,
+) -> bool {
+    let Some((key, precision)) = tail
+        .as_ref()
+        .map(|tail| (tail.key.clone(), tail.z.0.prec()))
+    else {
+        let last = orbit.read(orbit.len().saturating_sub(1)..orbit.len());
+        let escaped = last.is_ok_and(|last| {
+            last.first().is_none_or(|[real, imag]| {
+                let (real, imag) = (real.to_f64(), imag.to_f64());
+                real * real + imag * imag > ESCAPE_RADIUS
+            })
+        });
+        return escaped || orbit.len() as u64 >= max_iter;
+    };
+    let result = match cache.filter(|cache| cache.fits(&key, max_iter, Some(precision))) {
+        Some(cache) => extend_stored_orbit(orbit, tail, &key, max_iter, cache, cancelled),
+        None => orbit
+            .in_memory()
+            .map(|points| iterate_orbit(points, 0, tail, max_iter, cancelled)),
+    };
+    if let Err(err) = result {
+        warn!("Failed to read the stored orbit: {err}");
+        *tail = None;
+        return false;
+    }
+    true
+}
+
+/// Continues an orbit that fits in the cache, storing each chunk of
+/// [`ORBIT_CHUNK_LEN`] points once it is probed
+fn extend_stored_orbit(
+    orbit: &mut OrbitPoints,
+    tail: &mut Option<OrbitTail>,
+    key: &str,
+    max_iter: u64,
+    cache: &OrbitCache,
+    cancelled: &AtomicBool,
+) -> Result<()> {
+    while (orbit.len() as u64) < max_iter && tail.is_some() && !cancelled.load(Ordering::Relaxed) {
+        let start = orbit.len();
+        let mut points = Vec::new();
+        let end = max_iter.min(start as u64 + ORBIT_CHUNK_LEN);
+        iterate_orbit(&mut points, start, tail, end, cancelled);
+        orbit.append(points, cache, key, tail.as_ref())?;
+    }
+    Ok(())
+}
+
+/// Iterates `z = f(z) + c` from the tail of `orbit`, until it escapes, `max_iter` points
+/// are generated, or `cancelled` is set. The tail is removed once the orbit escapes.
+/// `start` is the number of points before the first one of `orbit`, which only holds
+/// the latest points of orbits that are stored as they are probed.
+/// Apart from growing the orbit, no memory is allocated for the built-in formulas.
+fn iterate_orbit<T>(
+    orbit: &mut Vec<[T; 2]>,
+    start: usize,
+    tail: &mut Option<OrbitTail>,
+    max_iter: u64,
+    cancelled: &AtomicBool,
+) where
+    T: FromFloat + Debug,
+{
+    let Some(OrbitTail {
+        z: (z_real, z_imag),
+        c: (c_real, c_imag),
+        power,
+        formulas,
+        scratch,
+        ..
+    }) = tail
+    else {
+        return;
+    };
+
+    // resolve the formulas once, since custom formulas are behind a lock
+    let formulas: Vec<_> = formulas.iter().map(|formula| formula.formula()).collect();
+
+    // point `n` of the orbit is the result of step `n - 1`
+    for step in (start + orbit.len()) as u64 - 1..max_iter - 1 {
+        if step % CANCEL_CHECK_INTERVAL == 0 && cancelled.load(Ordering::Relaxed) {
+            break;
+        }
+        // iterate values, according to the formula (z = z^power + c for the Mandelbrot set)
+        formulas[step as usize % formulas.len()].step_in_place(z_real, z_imag, scratch, *power);
+        *z_real += &*c_real;
+        *z_imag += &*c_imag;
+
+        orbit.push([T::from_float(z_real), T::from_float(z_imag)]);
 
-    // c = x + yi
-    let mut c_real = Float::with_val(precision, x);
-    let mut c_imag = Float::with_val(precision, y);
+        let (real, imag) = (z_real.to_f64(), z_imag.to_f64());
+        let radius_squared = real * real + imag * imag;
 
-    // z = 0 + 0i
+        if radius_squared > ESCAPE_RADIUS {
+            *tail = None;
+            break;
+        }
+    }
+}
+
+/// Searches for the nucleus of the lowest-period minibrot in the view, to use as a reference
+/// point. The period is detected by iterating a ball covering the view until it contains
+/// the origin, and the nucleus is then found by Newton's method starting at the center.
+/// Returns `None` if no period is found within `max_iter` iterations, if the
+/// nucleus is not inside or near the view, or if `cancelled` is set.
+pub fn find_nucleus(
+    viewport: &Viewport,
+    max_iter: u64,
+    power: u32,
+    cancelled: &AtomicBool,
+) -> Option<Nucleus> {
+    let period = ball_period(viewport, max_iter, power, cancelled)?;
+    let location = newton_nucleus(viewport, period, power, cancelled)?;
+
+    // the Newton iteration may converge to a far away nucleus if the start was
+    // outside of its basin. Only accept nuclei within one view of the center.
+    let (x, y) = viewport.coords_to_px_offset(&location.x, &location.y);
+    if (x / viewport.width as f64).abs() > 1.0 || (y / viewport.height as f64).abs() > 1.0 {
+        return None;
+    }
+    Some(Nucleus { location, period })
+}
+
+/// Finds the period of the view by iterating a ball containing all of its points.
+/// The first iteration at which the ball contains the origin is the period of the
+/// lowest-period nucleus that may lie inside of the view.
+fn ball_period(
+    viewport: &Viewport,
+    max_iter: u64,
+    power: u32,
+    cancelled: &AtomicBool,
+) -> Option<u64> {
+    let precision = get_precision(viewport.zoom);
+    let aspect_scale = viewport.aspect_scale();
+
+    // the radius is kept at a low precision; it only needs the exponent range
+    let mut view_radius = Float::with_val(53, 2.0);
+    view_radius.pow_assign(-viewport.zoom);
+    view_radius *= (aspect_scale.x as f64).hypot(aspect_scale.y as f64) * viewport.max_stretch();
+
+    let c_real = Float::with_val(precision, &viewport.center.x);
+    let c_imag = Float::with_val(precision, &viewport.center.y);
     let mut z_real = Float::with_val(precision, 0.0);
     let mut z_imag = Float::with_val(precision, 0.0);
-    if let Some(ComplexPoint { x: r, y: i }) = julia_point {
-        z_real = c_real.clone();
-        z_imag = c_imag.clone();
-        c_real = Float::with_val(precision, r);
-        c_imag = Float::with_val(precision, i);
+    let mut radius = Float::with_val(53, 0.0);
+
+    for step in 1..=max_iter {
+        if step % CANCEL_CHECK_INTERVAL == 0 && cancelled.load(Ordering::Relaxed) {
+            return None;
+        }
+        // |(Z + e)^d - Z^d| <= sum_(k=1)^d C(d, k) |Z|^(d-k) r^k, evaluated with
+        // Horner's method to avoid cancellation when r is much smaller than |Z|
+        let magnitude = Float::with_val(53, z_real.hypot_ref(&z_imag));
+        let mut coefficient = 1.0;
+        let mut magnitude_power = Float::with_val(53, 1.0);
+        let mut sum = Float::with_val(53, 1.0);
+        for k in (1..power).rev() {
+            coefficient *= (k + 1) as f64 / (power - k) as f64;
+            magnitude_power *= &magnitude;
+            sum = sum * &radius + magnitude_power.clone() * coefficient;
+        }
+        radius = sum * &radius + &view_radius;
+
+        let (real, imag) = complex_pow(&z_real, &z_imag, power);
+        z_real = real + &c_real;
+        z_imag = imag + &c_imag;
+
+        let magnitude = Float::with_val(53, z_real.hypot_ref(&z_imag));
+        if magnitude < radius {
+            return Some(step);
+        }
+        // the whole ball has escaped, so no point in the view is periodic
+        if magnitude - &radius > 2.0 {
+            return None;
+        }
     }
+    None
+}
 
-    // z^2 (temp values for optimized computation)
-    let mut z_squared_real = z_real.clone() * z_real.clone();
-    let mut z_squared_imag = z_imag.clone() * z_imag.clone();
+/// Finds the nucleus of the given period closest to the center of the view with
+/// Newton's method, solving z_period(c) = 0 at the precision of the view.
+fn newton_nucleus(
+    viewport: &Viewport,
+    period: u64,
+    power: u32,
+    cancelled: &AtomicBool,
+) -> Option<ComplexPoint> {
+    // a few extra bits keep the last steps from being rounded away
+    let precision = get_precision(viewport.zoom) + 16;
+    let mut tolerance = Float::with_val(53, 2.0);
+    tolerance.pow_assign(-viewport.zoom);
+    tolerance *= NEWTON_TOLERANCE;
 
-    probed_point.push([T::from_float(&z_real), T::from_float(&z_imag)]);
-    for _step in 0..max_iter - 1 {
-        // iterate values, according to z = z^2 + c
-        //
-        // uses an optimized computation method from wikipedia for z:
-        //   z.i := 2 × z.r × z.i + c.i
-        //   z.r := r2 - i2 + c.r
-        //   r2 := z.r × z.r
-        //   i2 := z.i × z.i
+    let mut c_real = Float::with_val(precision, &viewport.center.x);
+    let mut c_imag = Float::with_val(precision, &viewport.center.y);
 
-        // compute z
-        z_imag = (z_real.clone() + z_real.clone()) * z_imag.clone() + c_imag.clone();
-        z_real = z_squared_real.clone() - z_squared_imag.clone() + c_real.clone();
+    for _ in 0..MAX_NEWTON_STEPS {
+        let mut z_real = Float::with_val(precision, 0.0);
+        let mut z_imag = Float::with_val(precision, 0.0);
+        let mut dz_real = Float::with_val(precision, 0.0);
+        let mut dz_imag = Float::with_val(precision, 0.0);
+        for step in 0..period {
+            if step % CANCEL_CHECK_INTERVAL == 0 && cancelled.load(Ordering::Relaxed) {
+                return None;
+            }
+            // dz = d z^(d-1) dz + 1
+            let (real, imag) = complex_pow(&z_real, &z_imag, power - 1);
+            let new_dz_real = (real.clone() * &dz_real - imag.clone() * &dz_imag) * power + 1u32;
+            dz_imag = (real * &dz_imag + imag * &dz_real) * power;
+            dz_real = new_dz_real;
+            // z = z^d + c
+            let (real, imag) = complex_pow(&z_real, &z_imag, power);
+            z_real = real + &c_real;
+            z_imag = imag + &c_imag;
+        }
+
+        // c = c - z / dz
+        let dz_norm = dz_real.clone() * &dz_real + dz_imag.clone() * &dz_imag;
+        if dz_norm.is_zero() || !dz_norm.is_finite() {
+            return None;
+        }
+        let step_real = (z_real.clone() * &dz_real + z_imag.clone() * &dz_imag) / &dz_norm;
+        let step_imag = (z_imag * &dz_real - z_real * &dz_imag) / &dz_norm;
+        if !step_real.is_finite() || !step_imag.is_finite() {
+            return None;
+        }
+        c_real -= &step_real;
+        c_imag -= &step_imag;
 
-        // compute z^2
-        z_squared_real = z_real.clone() * z_real.clone();
-        z_squared_imag = z_imag.clone() * z_imag.clone();
+        if Float::with_val(53, step_real.hypot_ref(&step_imag)) < tolerance {
+            let precision = get_precision(viewport.zoom);
+            return Some(ComplexPoint {
+                x: Float::with_val(precision, c_real),
+                y: Float::with_val(precision, c_imag),
+            });
+        }
+    }
+    None
+}
 
-        probed_point.push([T::from_float(&z_real), T::from_float(&z_imag)]);
+/// A complex number with an extended exponent range, stored as
+/// `(re + im i) * 2^exp`. This is used for values that would
+/// over- or underflow an f64 at deep zoom levels.
+#[derive(Debug, Default, Clone, Copy)]
+struct ComplexExp {
+    re: f64,
+    im: f64,
+    exp: i64,
+}
 
-        let radius_squared = z_squared_real.to_f64() + z_squared_imag.to_f64();
+impl ComplexExp {
+    fn new(re: f64, im: f64, exp: i64) -> Self {
+        Self { re, im, exp }.normalize()
+    }
 
-        if radius_squared > ESCAPE_RADIUS {
+    /// Moves the magnitude of the mantissa into the exponent, so that the
+    /// larger component of the mantissa is in [0.5, 1)
+    fn normalize(self) -> Self {
+        let max = self.re.abs().max(self.im.abs());
+        if max == 0.0 || !max.is_finite() {
+            return Self::default();
+        }
+        let shift = ((max.to_bits() >> 52) & 0x7ff) as i64 - 1022;
+        let scale = 2f64.powi(-shift as i32);
+        Self {
+            re: self.re * scale,
+            im: self.im * scale,
+            exp: self.exp + shift,
+        }
+    }
+
+    /// The base 2 logarithm of the magnitude of this number
+    fn log2_norm(&self) -> f64 {
+        if self.re == 0.0 && self.im == 0.0 {
+            return f64::NEG_INFINITY;
+        }
+        self.re.hypot(self.im).log2() + self.exp as f64
+    }
+
+    /// Converts this number to the `(re, im, exp, 0)` format used in the shaders
+    fn to_gpu(self) -> [f32; 4] {
+        [self.re as f32, self.im as f32, self.exp as f32, 0.0]
+    }
+}
+
+impl From<[f32; 2]> for ComplexExp {
+    fn from([re, im]: [f32; 2]) -> Self {
+        Self::new(re as f64, im as f64, 0)
+    }
+}
+
+impl Add for ComplexExp {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        let (large, small) = if self.exp >= rhs.exp {
+            (self, rhs)
+        } else {
+            (rhs, self)
+        };
+        // beyond this difference, the smaller value has no effect on the sum
+        let diff = large.exp - small.exp;
+        if diff > 64 {
+            return large;
+        }
+        let scale = 2f64.powi(-diff as i32);
+        Self::new(
+            large.re + small.re * scale,
+            large.im + small.im * scale,
+            large.exp,
+        )
+    }
+}
+
+impl Mul for ComplexExp {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+            self.exp + rhs.exp,
+        )
+    }
+}
+
+/// Accumulates the internal and external coloring values along the reference orbit,
+/// in the same way the perturbed shader does for each pixel.
+#[derive(Debug, Clone)]
+struct ReferenceTraps {
+    c: [f32; 2],
+    orbits: [f32; 4],
+    stripes: [f32; 4],
+    angles: f32,
+    total_angle: f32,
+    closest: f32,
+    min_iter: u32,
+    line: [f32; 2],
+    previous: [f32; 2],
+}
+
+pub(super) fn length_squared([x, y]: [f32; 2]) -> f32 {
+    x * x + y * y
+}
+
+pub(super) fn normalize([x, y]: [f32; 2]) -> [f32; 2] {
+    let length = length_squared([x, y]).sqrt();
+    [x / length, y / length]
+}
+
+impl ReferenceTraps {
+    fn new(c: [f32; 2]) -> Self {
+        Self {
+            c,
+            orbits: [f32::MAX; 4],
+            stripes: [0.0; 4],
+            angles: 0.0,
+            total_angle: 0.0,
+            closest: length_squared(c),
+            min_iter: 1,
+            line: normalize([-c[0], -c[1]]),
+            previous: [0.0; 2],
+        }
+    }
+
+    /// Adds the point `z` at iteration `n` of the reference orbit
+    fn push(&mut self, n: u64, z: [f32; 2]) {
+        if n > 1 {
+            let direction = normalize([z[0] - self.previous[0], z[1] - self.previous[1]]);
+            self.angles += (direction[0] * self.line[0] + direction[1] * self.line[1]).acos();
+            self.line = normalize([self.previous[0] - z[0], self.previous[1] - z[1]]);
+            let distance = length_squared([z[0] - self.c[0], z[1] - self.c[1]]);
+            if distance < self.closest {
+                self.total_angle = self.angles;
+                self.min_iter = n as u32;
+                self.closest = distance;
+            }
+
+            for (trap, value) in self.orbits.iter_mut().zip(orbit_values(z)) {
+                *trap = trap.min(value);
+            }
+        }
+        self.previous = z;
+
+        for (sum, value) in self.stripes.iter_mut().zip(stripe_values(z)) {
+            *sum += value;
+        }
+    }
+}
+
+/// The orbit trap values of a point, matching `get_orbit_values` in the shaders
+pub(super) fn orbit_values(z: [f32; 2]) -> [f32; 4] {
+    let radius_squared = length_squared(z);
+    [
+        radius_squared,
+        (radius_squared - 2.0).abs(),
+        z[0].abs().min(z[1].abs()),
+        (z[0].abs() + z[1].abs() - 2.0).abs(),
+    ]
+}
+
+/// The stripe values of a point, matching `get_stripe_values` in the shaders
+pub(super) fn stripe_values(z: [f32; 2]) -> [f32; 4] {
+    if z == [0.0; 2] {
+        return [0.0; 4];
+    }
+    let [x, y] = normalize(z);
+    [
+        0.5 + 0.5 * (5.0 * z[0].atan2(z[1])).sin(),
+        0.5 + 0.5 * x,
+        0.5 + 0.5 * y,
+        0.0,
+    ]
+}
+
+/// The result of a series approximation of the first iterations of the perturbed
+/// delta around the reference orbit.
+#[derive(Debug, Default, Clone)]
+pub struct SeriesApproximation {
+    /// The number of iterations that can be skipped
+    pub skip: u64,
+    pub params: SeriesParams,
+}
+
+/// Approximates the delta of each pixel from the reference orbit as a cubic polynomial
+///
+///   delta_n = a_n u + b_n u^2 + c_n u^3
+///
+/// where `u` is the pixel offset in the scaled units of the perturbed shader
+/// (`delta_0 = u 2^-zoom`), and finds the number of iterations for which this is
+/// accurate for all offsets up to `max_offset`.
+///
+/// The validity of the approximation is checked by estimating the truncation error
+/// from the next term of the series.
+pub fn series_approximation(
+    orbit: &[[f32; 2]],
+    c: [f32; 2],
+    zoom: f64,
+    max_offset: f64,
+    max_iter: u64,
+) -> SeriesApproximation {
+    let delta_scale = ComplexExp::new(2f64.powf(-zoom.fract()), 0.0, -zoom.trunc() as i64);
+    let two = ComplexExp::new(2.0, 0.0, 0);
+    let log2_offset = max_offset.log2();
+
+    let mut a = ComplexExp::default();
+    let mut b = ComplexExp::default();
+    let mut c_coeff = ComplexExp::default();
+    let mut d = ComplexExp::default();
+    let mut traps = ReferenceTraps::new(c);
+    let mut skip = 0;
+
+    // the last point of the orbit can not be iterated from, and the
+    // shader needs at least one iteration to compute the final values
+    let limit = (orbit.len() as u64).min(max_iter).saturating_sub(1);
+    for (n, &x_n) in orbit.iter().enumerate().take(limit as usize) {
+        let two_x = two * ComplexExp::from(x_n);
+        let next_a = two_x * a + delta_scale;
+        let next_b = two_x * b + a * a;
+        let next_c = two_x * c_coeff + two * a * b;
+        let next_d = two_x * d + two * a * c_coeff + b * b;
+
+        let error = next_d.log2_norm() + 4.0 * log2_offset;
+        let magnitude = next_a.log2_norm() + log2_offset;
+        if error > magnitude + SERIES_TOLERANCE.log2() {
             break;
         }
+
+        traps.push(n as u64, x_n);
+        (a, b, c_coeff, d) = (next_a, next_b, next_c, next_d);
+        skip = n as u64 + 1;
+    }
+
+    SeriesApproximation {
+        skip,
+        params: SeriesParams {
+            a: a.to_gpu(),
+            b: b.to_gpu(),
+            c: c_coeff.to_gpu(),
+            orbits: traps.orbits,
+            stripes: traps.stripes,
+            angles: [
+                traps.angles,
+                traps.total_angle,
+                traps.closest,
+                f32::from_bits(traps.min_iter),
+            ],
+            line: [
+                traps.line[0],
+                traps.line[1],
+                traps.previous[0],
+                traps.previous[1],
+            ],
+        },
+    }
+}
+
+/// A bilinear approximation table, ready for upload to the GPU
+#[derive(Debug, Default, Clone)]
+pub struct BlaTable {
+    pub header: BlaHeader,
+    pub entries: Vec<BlaEntry>,
+}
+
+/// A node of the bilinear approximation tree, covering the iterations
+/// from `m` to `m + 2^level` of the reference orbit.
+#[derive(Debug, Clone)]
+struct BlaNode {
+    a: ComplexExp,
+    b: ComplexExp,
+    /// The base 2 logarithm of the largest delta for which this node is valid
+    log2_radius: f64,
+    /// The orbit traps of the points strictly inside the node
+    orbits: [f32; 4],
+    /// The stripe and angle sums of the points strictly inside the node
+    stripes: [f32; 4],
+}
+
+impl BlaNode {
+    /// A single iteration at `x_n`. The linear term of the perturbed formula
+    /// is `power * x_n^(power - 1)`, and the squared term stays negligible
+    /// while the delta is small relative to `x_n / (power - 1)`.
+    fn single(x_n: [f32; 2], power: u32) -> Self {
+        let x = ComplexExp::from(x_n);
+        let a = (2..power).fold(ComplexExp::new(power as f64, 0.0, 0), |a, _| a * x);
+        Self {
+            a: a * x,
+            b: ComplexExp::new(1.0, 0.0, 0),
+            log2_radius: (length_squared(x_n) as f64).sqrt().log2() - ((power - 1) as f64).log2()
+                + BLA_EPSILON.log2(),
+            orbits: [f32::MAX; 4],
+            stripes: [0.0; 4],
+        }
+    }
+
+    /// Combines this node with the following node `next`, where `mid` is the
+    /// index of the point between them.
+    fn merge(&self, next: &Self, orbit: &[[f32; 2]], mid: usize, log2_max_delta_0: f64) -> Self {
+        // the delta after this node must be within the radius of the next node,
+        // including the contribution of delta_0
+        let log2_b_delta_0 = self.b.log2_norm() + log2_max_delta_0;
+        let log2_next_radius = if log2_b_delta_0 < next.log2_radius {
+            next.log2_radius + (-(log2_b_delta_0 - next.log2_radius).exp2()).ln_1p() / 2f64.ln()
+        } else {
+            f64::NEG_INFINITY
+        };
+
+        let mut orbits = [0.0; 4];
+        let mut stripes = [0.0; 4];
+        let mid_orbits = orbit_values(orbit[mid]);
+        let mid_stripes = stripe_values(orbit[mid]);
+        for i in 0..4 {
+            orbits[i] = self.orbits[i].min(mid_orbits[i]).min(next.orbits[i]);
+            stripes[i] = self.stripes[i] + mid_stripes[i] + next.stripes[i];
+        }
+        stripes[3] += angle_at(orbit, mid);
+
+        Self {
+            a: next.a * self.a,
+            b: next.a * self.b + next.b,
+            log2_radius: self.log2_radius.min(log2_next_radius - self.a.log2_norm()),
+            orbits,
+            stripes,
+        }
+    }
+
+    fn to_gpu(&self) -> BlaEntry {
+        let mut a = self.a.to_gpu();
+        a[3] = self.log2_radius as f32;
+        BlaEntry {
+            a,
+            b: self.b.to_gpu(),
+            orbits: self.orbits,
+            stripes: self.stripes,
+        }
+    }
+}
+
+/// The angle between the steps before and after point `n` of the orbit,
+/// as accumulated for the total angle in the shaders
+fn angle_at(orbit: &[[f32; 2]], n: usize) -> f32 {
+    if n < 2 {
+        return 0.0;
+    }
+    let [x, y] = orbit[n];
+    let [x1, y1] = orbit[n - 1];
+    let [x2, y2] = orbit[n - 2];
+    let direction = normalize([x - x1, y - y1]);
+    let line = normalize([x2 - x1, y2 - y1]);
+    (direction[0] * line[0] + direction[1] * line[1]).acos()
+}
+
+/// Builds a table of bilinear approximations for skipping iterations of the perturbed
+/// delta around the reference orbit.
+///
+/// Each level `l` of the table contains the approximations for blocks of `2^l` iterations,
+/// aligned to multiples of `2^l` after the start of the table. The validity radius of each
+/// entry accounts for pixel offsets up to `log2_max_delta_0`, which should be negative
+/// infinity for Julia sets, since they have no delta_0 term.
+///
+/// The table is empty for formulas that are not complex analytic, since their
+/// perturbed iterations are only piecewise linear in the delta.
+pub fn bla_table(
+    orbit: &[[f32; 2]],
+    fractal_kind: &FractalKind,
+    formulas: &[Formula],
+    log2_max_delta_0: f64,
+) -> BlaTable {
+    let julia = fractal_kind.julia_point().is_some();
+    let power = fractal_kind.power();
+    // the first mandelbrot iteration from z = 0 is not linear in the delta,
+    // so the table starts after it
+    let start = if julia { 0 } else { 1 };
+    // the reference orbit is only followed until it escapes
+    let end = orbit
+        .iter()
+        .position(|&z| length_squared(z) > 4.0)
+        .unwrap_or(orbit.len().saturating_sub(1));
+
+    let mut header = BlaHeader {
+        start: start as u32,
+        min_level: BLA_MIN_LEVEL,
+        ..Default::default()
+    };
+    let mut entries = Vec::new();
+    if end <= start || formulas.iter().any(|&f| f != Formula::MANDELBROT) {
+        return BlaTable { header, entries };
+    }
+
+    // the single iterations are merged into pairs right away, to avoid
+    // keeping a node for every iteration in memory
+    let mut level: Vec<BlaNode> = (start..end - 1)
+        .step_by(2)
+        .map(|n| {
+            BlaNode::single(orbit[n], power).merge(
+                &BlaNode::single(orbit[n + 1], power),
+                orbit,
+                n + 1,
+                log2_max_delta_0,
+            )
+        })
+        .collect();
+    let mut size = 2;
+    for l in 2..MAX_BLA_LEVELS as u32 - 1 {
+        if level.len() < 2 {
+            break;
+        }
+        level = level
+            .chunks_exact(2)
+            .enumerate()
+            .map(|(i, pair)| {
+                let mid = start + (2 * i + 1) * size;
+                pair[0].merge(&pair[1], orbit, mid, log2_max_delta_0)
+            })
+            .collect();
+        size *= 2;
+
+        if l >= BLA_MIN_LEVEL {
+            header.offsets[l as usize] = entries.len() as u32;
+            header.max_level = l;
+            entries.extend(level.iter().map(BlaNode::to_gpu));
+        }
+    }
+    for offset in header
+        .offsets
+        .iter_mut()
+        .skip(header.max_level as usize + 1)
+    {
+        *offset = entries.len() as u32;
     }
 
-    probed_point
+    BlaTable { header, entries }
 }
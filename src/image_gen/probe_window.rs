@@ -0,0 +1,142 @@
+/*!
+# Probe Windows
+
+Reference orbits of hundreds of millions of iterations do not fit in one storage buffer
+binding, so they are streamed to the GPU in windows of whole batches of iterations. The
+probe buffer then holds the first points of the reference and critical orbits, followed
+by the current window and a margin of the points after it:
+
+`[reference head | critical head | window | margin]`
+
+Each batch runs one pass of the compute shader per window, in ascending order. Points
+only iterate in the pass of the window that holds their reference iteration, and pause
+at the end of it, requesting the window they need in a bit set that is read back after
+each pass. Points that are rebased to the start of an orbit finish the batch in its head,
+which is longer than a batch. The windows of orbits stored in the orbit cache are
+read from their files, so only the current window is held in memory.
+ */
+
+use std::collections::BTreeSet;
+
+use color_eyre::Result;
+
+use super::probe::{FloatExp, ReferenceOrbit};
+
+/// The number of points kept after each window, which are read past the last iteration
+/// of a point, such as by the attracting cycle search. Matches `PROBE_MARGIN` in the shaders.
+pub(crate) const PROBE_MARGIN: usize = 2048;
+
+/// The number of u32 words in each bit set of requested windows.
+/// Matches `PROBE_WINDOW_WORDS` in the shaders.
+pub(crate) const PROBE_WINDOW_WORDS: usize = 128;
+
+/// The size in bytes of a point of the reference orbits in the probe buffer,
+/// which is the same for all perturbed algorithms
+pub(crate) const PROBE_POINT_SIZE: usize = 16;
+
+/// # ProbeWindows
+/// The layout of the reference orbits in the probe buffer
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ProbeWindows {
+    /// The length of the reference orbit, which is followed by the critical orbit
+    probe_len: usize,
+    critical_len: usize,
+    /// The number of points of each orbit in front of the window, or 0 if both orbits fit
+    pub head_len: usize,
+    /// The number of points in each window, a multiple of the batch size
+    pub window_len: usize,
+    /// The number of iterations of each batch, which is lowered for small probe buffers
+    pub batch_size: u64,
+}
+
+impl ProbeWindows {
+    /// Lays out the orbits in a probe buffer of `size` bytes, streaming them if they do not fit
+    pub fn new(size: u64, batch_size: u64, orbit: &ReferenceOrbit) -> Self {
+        let capacity = size as usize / PROBE_POINT_SIZE;
+        let probe_len = orbit.orbit.len();
+        let critical_len = orbit.critical.len();
+        if probe_len + critical_len <= capacity {
+            return Self {
+                probe_len,
+                critical_len,
+                ..Self::whole(batch_size)
+            };
+        }
+        // keep most of the buffer for the window
+        let batch_size = batch_size.min(capacity as u64 / 8);
+        let head_len = batch_size as usize + PROBE_MARGIN;
+        let window_len =
+            (capacity - 2 * head_len - PROBE_MARGIN) / batch_size as usize * batch_size as usize;
+        Self {
+            probe_len,
+            critical_len,
+            head_len,
+            window_len,
+            batch_size,
+        }
+    }
+
+    /// A layout for orbits that fit in the probe buffer, or for algorithms without them
+    pub fn whole(batch_size: u64) -> Self {
+        Self {
+            probe_len: 0,
+            critical_len: 0,
+            head_len: 0,
+            window_len: u32::MAX as usize,
+            batch_size,
+        }
+    }
+
+    /// Whether the orbits are streamed in windows
+    pub fn is_streamed(&self) -> bool {
+        self.head_len != 0
+    }
+
+    /// The first reference iteration of a window
+    pub fn window_start(&self, window: usize) -> usize {
+        window * self.window_len
+    }
+
+    /// The points of a window and the margin after it, indexing the
+    /// critical orbit after the reference orbit
+    pub fn window_points(
+        &self,
+        orbit: &ReferenceOrbit,
+        window: usize,
+    ) -> Result<Vec<[FloatExp; 2]>> {
+        let start = self.window_start(window);
+        let end = start + self.window_len + PROBE_MARGIN;
+        let reference = orbit.orbit.read(start..end)?;
+        let critical = orbit
+            .critical
+            .read(start.saturating_sub(self.probe_len)..end.saturating_sub(self.probe_len))?;
+        Ok([reference, critical].concat())
+    }
+
+    /// The windows that can hold the reference iterations of points at `iteration`,
+    /// which are at most that far along the reference orbit or the critical orbit
+    pub fn windows_until(&self, iteration: u64) -> BTreeSet<usize> {
+        if !self.is_streamed() {
+            return BTreeSet::from([0]);
+        }
+        let iteration = iteration as usize;
+        let mut windows: BTreeSet<usize> =
+            (0..=iteration.min(self.probe_len) / self.window_len).collect();
+        if self.critical_len != 0 {
+            windows.extend(
+                self.probe_len / self.window_len
+                    ..=(self.probe_len + iteration.min(self.critical_len)) / self.window_len,
+            );
+        }
+        windows
+    }
+
+    /// The windows in a bit set of requested windows
+    pub fn requested(words: &[u32]) -> impl Iterator<Item = usize> + '_ {
+        words.iter().enumerate().flat_map(|(index, word)| {
+            (0..32)
+                .filter(move |bit| word & (1 << bit) != 0)
+                .map(move |bit| index * 32 + bit)
+        })
+    }
+}
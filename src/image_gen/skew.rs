@@ -0,0 +1,168 @@
+/*!
+# Skew Estimation
+
+Estimates the [`Viewport::skew`](crate::types::Viewport::skew) that unstretches a view.
+Near some embedded Julia sets of deep zooms, and especially with the non-conformal formulas
+such as the Burning Ship, the features of the view are strongly stretched in one direction.
+The stretch is measured by the Jacobian of the orbit of the center with respect to the
+offsets from the center, which maps circles of offsets to ellipses.
+ */
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rug::{Assign, Float};
+
+use super::probe::CANCEL_CHECK_INTERVAL;
+use crate::types::{ESCAPE_RADIUS, Image, get_precision, matrix_inverse, matrix_mul};
+
+/// The extra bits of precision for the finite differences of the formula steps,
+/// half of which are lost to cancellation
+const EXTRA_PRECISION: u32 = 64;
+
+/// Estimates the skew which makes the orbit of the center of the view conformal, so that
+/// the features around the center are not stretched. The Jacobian of the orbit is iterated
+/// until the center escapes or `max_iter` is reached. The rotation of the viewport is kept,
+/// and the skew is symmetric with a determinant of 1, so that it does not rotate or zoom the view.
+///
+/// Returns `None` if the Jacobian is singular or not finite, or if `cancelled` is set.
+///
+/// ```
+/// use std::sync::atomic::AtomicBool;
+///
+/// use corgi::image_gen::estimate_skew;
+/// use corgi::types::{FractalKind, Image};
+///
+/// let cancelled = AtomicBool::new(false);
+/// // the Mandelbrot set is conformal, so it has no skew
+/// let [[a, b], [c, d]] = estimate_skew(&Image::default(), &cancelled).unwrap();
+/// assert!((a - 1.0).abs() < 1e-6 && b.abs() < 1e-6 && c.abs() < 1e-6 && (d - 1.0).abs() < 1e-6);
+///
+/// let image = Image {
+///     fractal_kind: FractalKind::BurningShip,
+///     ..Default::default()
+/// };
+/// let [[a, b], [c, d]] = estimate_skew(&image, &cancelled).unwrap();
+/// assert!((a * d - b * c - 1.0).abs() < 1e-6 && (b - c).abs() < 1e-6);
+/// ```
+pub fn estimate_skew(image: &Image, cancelled: &AtomicBool) -> Option<[[f64; 2]; 2]> {
+    const IDENTITY: [[f64; 2]; 2] = [[1.0, 0.0], [0.0, 1.0]];
+    let viewport = &image.viewport;
+    let precision = get_precision(viewport.zoom) + EXTRA_PRECISION;
+    let formulas = image.formulas();
+    let power = image.fractal_kind.power();
+
+    let center = (
+        Float::with_val(precision, &viewport.center.x),
+        Float::with_val(precision, &viewport.center.y),
+    );
+    // the derivative is by c for the Mandelbrot mode, and by the starting point for Julia sets
+    let julia = image.fractal_kind.julia_point();
+    let (mut z, c, mut jacobian) = match julia {
+        None => (
+            (Float::new(precision), Float::new(precision)),
+            center,
+            [[0.0; 2]; 2],
+        ),
+        Some(point) => (
+            center,
+            (
+                Float::with_val(precision, &point.x),
+                Float::with_val(precision, &point.y),
+            ),
+            IDENTITY,
+        ),
+    };
+    // the Jacobian is stored as `jacobian * 2^exponent`, since it overflows an f64 at deep zooms
+    let mut exponent = 0;
+
+    // the steps are taken in place, so that no memory is allocated in the loop
+    let one = Float::with_val(precision, 1.0);
+    let mut epsilon = Float::new(precision);
+    let mut step_x = (Float::new(precision), Float::new(precision));
+    let mut step_y = (Float::new(precision), Float::new(precision));
+    let mut scratch = [(); 4].map(|_| Float::new(precision));
+    let mut magnitude = Float::new(53);
+
+    for n in 0..image.max_iter {
+        if n % CANCEL_CHECK_INTERVAL == 0 && cancelled.load(Ordering::Relaxed) {
+            return None;
+        }
+        let formula = formulas[n as usize % formulas.len()].formula();
+
+        // the derivative of the step, from finite differences at half of the precision
+        epsilon.assign(z.0.hypot_ref(&z.1));
+        epsilon.max_mut(&one);
+        epsilon >>= precision / 2;
+        step_x.0.assign(&z.0 + &epsilon);
+        step_x.1.assign(&z.1);
+        formula.step_in_place(&mut step_x.0, &mut step_x.1, &mut scratch, power);
+        step_y.0.assign(&z.0);
+        step_y.1.assign(&z.1 + &epsilon);
+        formula.step_in_place(&mut step_y.0, &mut step_y.1, &mut scratch, power);
+        formula.step_in_place(&mut z.0, &mut z.1, &mut scratch, power);
+        let difference = |value: &mut Float, step: &Float| {
+            *value -= step;
+            *value /= &epsilon;
+            value.to_f64()
+        };
+        let derivative = [
+            [
+                difference(&mut step_x.0, &z.0),
+                difference(&mut step_y.0, &z.0),
+            ],
+            [
+                difference(&mut step_x.1, &z.1),
+                difference(&mut step_y.1, &z.1),
+            ],
+        ];
+
+        jacobian = matrix_mul(derivative, jacobian);
+        if julia.is_none() {
+            let one = (-exponent as f64).exp2();
+            jacobian[0][0] += one;
+            jacobian[1][1] += one;
+        }
+        let max = jacobian
+            .iter()
+            .flatten()
+            .fold(0.0f64, |max, x| max.max(x.abs()));
+        if !max.is_finite() {
+            return None;
+        }
+        if max > 0.0 {
+            let shift = max.log2().floor() as i32;
+            jacobian = jacobian.map(|row| row.map(|x| x * (-shift as f64).exp2()));
+            exponent += shift;
+        }
+
+        z.0 += &c.0;
+        z.1 += &c.1;
+        magnitude.assign(z.0.hypot_ref(&z.1));
+        if magnitude > ESCAPE_RADIUS {
+            break;
+        }
+    }
+
+    // the offsets are rotated before the Jacobian is applied, so the skew S needs
+    // (J R S)^T (J R S) = S (R^T J^T J R) S to be a multiple of the identity
+    let (sin, cos) = viewport.angle.sin_cos();
+    let jacobian = matrix_mul(jacobian, [[cos, -sin], [sin, cos]]);
+    let transpose = [
+        [jacobian[0][0], jacobian[1][0]],
+        [jacobian[0][1], jacobian[1][1]],
+    ];
+    let [[a, b], [c, d]] = matrix_mul(transpose, jacobian);
+
+    // S is the inverse of the square root of the symmetric matrix, scaled to a determinant of 1
+    let det = a * d - b * c;
+    if det <= 0.0 || !det.is_finite() {
+        return None;
+    }
+    let root_det = det.sqrt();
+    let scale = (a + d + 2.0 * root_det).sqrt() * root_det.sqrt();
+    let skew = matrix_inverse([
+        [(a + root_det) / scale, b / scale],
+        [c / scale, (d + root_det) / scale],
+    ])?;
+    skew.iter().flatten().all(|x| x.is_finite()).then_some(skew)
+}
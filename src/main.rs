@@ -9,6 +9,7 @@ use std::fs::read_to_string;
 use std::io::Write;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
 use std::time::Instant;
 
@@ -16,9 +17,10 @@ use clap::Parser;
 use color_eyre::Result;
 use color_eyre::eyre::eyre;
 use corgi::image_gen::{
-    Constants, GPUData, SharedState, get_device_and_queue, render_image, save_to_file,
+    Constants, CpuData, GPUData, ReferenceOrbit, SharedState, device_descriptor,
+    get_device_and_queue, render_image, render_image_cpu, save_to_file, save_to_file_cpu,
 };
-use corgi::types::{Image, OptLevel, StatusMessage};
+use corgi::types::{AlgorithmPolicy, Image, OptLevel, StatusMessage};
 use directories::ProjectDirs;
 use eframe::{egui, egui_wgpu, wgpu};
 use pollster::FutureExt;
@@ -29,6 +31,16 @@ use tracing_subscriber::FmtSubscriber;
 use crate::app::{CorgiApp, CorgiCliOptions};
 use crate::config::{Cache, Config, Context, Theme};
 
+/// The most renders of the CLI while automatically adjusting max_iter
+const MAX_AUTO_MAX_ITER_PASSES: usize = 8;
+
+/// The renderer of the CLI, with its state between the passes of automatic max_iter
+#[expect(clippy::large_enum_variant)]
+enum Backend {
+    Gpu(Box<GPUData>, ReferenceOrbit),
+    Cpu(CpuData),
+}
+
 fn load_from_toml<T: for<'a> Deserialize<'a> + Default>(path: &PathBuf) -> T {
     if path.exists()
         && let Ok(text) = read_to_string(path)
@@ -56,8 +68,6 @@ fn main() -> Result<()> {
 
     // cli only render
     if let Some(path) = cli_options.output_file {
-        let (device, queue) = get_device_and_queue().block_on()?;
-
         let Some(settings_file) = cli_options.settings_file else {
             return Err(eyre!("No settings file specified, exiting."));
         };
@@ -66,15 +76,53 @@ fn main() -> Result<()> {
         }
         let mut image = Image::load_from_file(&settings_file)?;
         image.optimization_level = OptLevel::AccuracyOptimized;
-        let mut gpu_data = GPUData::init(
-            &image.viewport,
-            image.max_iter as usize,
-            SharedState::new(device, queue),
-            "cli renderer",
-            Constants {
-                iter_batch_size: 100_000,
+        // renders use the same config as the UI, so that both render a file the same way
+        let config: Config = ProjectDirs::from("com", "kiranwells", "corgi")
+            .map(|proj_dirs| load_from_toml(&proj_dirs.config_dir().join("config.toml")))
+            .unwrap_or_default();
+        let constants = Constants {
+            iter_batch_size: 100_000,
+            max_glitch_references: config.max_glitch_references,
+            algorithm_policy: AlgorithmPolicy {
+                double_single: cli_options.double_single || config.algorithm_policy.double_single,
+                ..config.algorithm_policy.clone()
             },
-        );
+            orbit_cache: config.orbit_cache(),
+        };
+        let device = if cli_options.cpu {
+            None
+        } else {
+            match get_device_and_queue().block_on() {
+                Ok(device) => Some(device),
+                Err(err) => {
+                    tracing::warn!("No GPU found, rendering on the CPU: {err}");
+                    None
+                }
+            }
+        };
+        let mut backend = match device {
+            Some((device, queue)) => Backend::Gpu(
+                Box::new(GPUData::init(
+                    &image.viewport,
+                    image.max_iter as usize,
+                    SharedState::new(device, queue),
+                    "cli renderer",
+                    constants,
+                )),
+                ReferenceOrbit::default(),
+            ),
+            None => {
+                let cpu_data = CpuData::new(
+                    cli_options.threads,
+                    constants.algorithm_policy,
+                    constants.max_glitch_references,
+                )?;
+                // fail before rendering anything if the fallback can not render the image
+                cpu_data.check_supported(&image)?;
+                println!("Rendering on the CPU with {} threads", cpu_data.threads());
+                Backend::Cpu(cpu_data)
+            }
+        };
         let now = Instant::now();
         fn status_callback(sm: StatusMessage) {
             match sm {
@@ -82,20 +130,51 @@ fn main() -> Result<()> {
                     println!("{:>6.2}% | {}", percent * 100.0, msg);
                     let _ = std::io::stdout().lock().flush();
                 }
+                corgi::types::StatusMessage::GlitchesCorrected(corrected, remaining) => {
+                    println!("Corrected {corrected} glitched pixels, {remaining} remaining");
+                }
                 corgi::types::StatusMessage::NewPreviewViewport(..) => todo!(),
                 corgi::types::StatusMessage::NewOutputViewport(..) => todo!(),
+                // the stats are returned by the render instead
+                corgi::types::StatusMessage::IterationStats(..) => {}
+                corgi::types::StatusMessage::Cancelled => println!("Render cancelled"),
+                // only sent for the commands of the UI
+                corgi::types::StatusMessage::NucleusFound(..)
+                | corgi::types::StatusMessage::SkewEstimated(..) => {}
             }
         }
-        render_image(
-            &mut gpu_data,
-            &mut vec![],
-            &image,
-            None,
-            std::sync::Arc::new(AtomicBool::new(false)),
-            status_callback,
-        );
+        let mut last_image = None;
+        // in automatic mode, the image is rendered again until max_iter settles
+        for pass in 1..=MAX_AUTO_MAX_ITER_PASSES {
+            let cancelled = Arc::new(AtomicBool::new(false));
+            let stats = match &mut backend {
+                Backend::Gpu(gpu_data, probed_data) => render_image(
+                    gpu_data,
+                    probed_data,
+                    &image,
+                    last_image.as_ref(),
+                    cancelled,
+                    status_callback,
+                ),
+                Backend::Cpu(cpu_data) => {
+                    render_image_cpu(cpu_data, &image, cancelled, status_callback)?
+                }
+            };
+            let Some(max_iter) = stats
+                .filter(|_| image.auto_max_iter && pass < MAX_AUTO_MAX_ITER_PASSES)
+                .and_then(|stats| stats.suggested_max_iter())
+            else {
+                break;
+            };
+            println!("Rendering again with a max_iter of {max_iter}");
+            last_image = Some(image.clone());
+            image.max_iter = max_iter;
+        }
         println!("Rendering took {:?}", Instant::now().duration_since(now));
-        save_to_file(&gpu_data, &image, &path, status_callback);
+        match &backend {
+            Backend::Gpu(gpu_data, _) => save_to_file(gpu_data, &image, &path, status_callback),
+            Backend::Cpu(cpu_data) => save_to_file_cpu(cpu_data, &image, &path, status_callback),
+        }
         return Ok(());
     }
 
@@ -117,6 +196,10 @@ fn main() -> Result<()> {
         wgpu_options: egui_wgpu::WgpuConfiguration {
             present_mode: wgpu::PresentMode::AutoVsync,
             desired_maximum_frame_latency: None,
+            wgpu_setup: egui_wgpu::WgpuSetup::CreateNew(egui_wgpu::WgpuSetupCreateNew {
+                device_descriptor: Arc::new(device_descriptor),
+                ..Default::default()
+            }),
             ..Default::default()
         },
         ..Default::default()
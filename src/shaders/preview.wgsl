@@ -1,8 +1,9 @@
 struct Uniforms {
-    angle: f32,
-    _padding: f32,
+    matrix: mat2x2<f32>,
     scale: vec2<f32>,
     offset: vec2<f32>,
+    aspect: vec2<f32>,
+    _padding: vec2<f32>,
 }
 @group(0) @binding(0) var<uniform> uniforms: Uniforms;
 
@@ -34,7 +35,9 @@ fn vs_main(
         case 3u: {vertex = vec2<f32>(1., 1.);}
         default: {vertex = vec2<f32>(0., 0.);}
     }
-    out.clip_position = vec4<f32>((vertex + uniforms.offset) * uniforms.scale, 0.5, 1.0);
+    // rotate and skew the rendered image around the center of the view
+    let position = uniforms.matrix * ((vertex + uniforms.offset) * uniforms.aspect);
+    out.clip_position = vec4<f32>(position * uniforms.scale, 0.5, 1.0);
     switch (in_vertex_index) {
         case 0u: {vertex = vec2<f32>(0., 0.);}
         case 1u: {vertex = vec2<f32>(0., 1.);}
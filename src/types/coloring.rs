@@ -85,6 +85,16 @@ pub enum LayerKind {
     Distance,
     OrbitTrap,
     Stripe,
+    /// The period of the attracting cycle a point inside the set settles into
+    Period,
+    /// The magnitude and argument of the multiplier of the attracting cycle of a point
+    Multiplier,
+    /// The Lyapunov exponent of [`RenderMode::Lyapunov`](super::RenderMode::Lyapunov) images
+    Lyapunov,
+    /// The root that a point of a [`RenderMode::Newton`](super::RenderMode::Newton) image converges to
+    RootIndex,
+    /// The smooth number of iterations a point of a Newton image takes to converge
+    Convergence,
 }
 
 impl LayerKind {
@@ -96,6 +106,11 @@ impl LayerKind {
             LayerKind::Distance => "Distance Estimate",
             LayerKind::OrbitTrap => "Orbit Trap",
             LayerKind::Stripe => "Stripe Average",
+            LayerKind::Period => "Attractor Period",
+            LayerKind::Multiplier => "Attractor Multiplier",
+            LayerKind::Lyapunov => "Lyapunov Exponent",
+            LayerKind::RootIndex => "Root Index",
+            LayerKind::Convergence => "Convergence Speed",
         }
     }
     pub fn icon_text(self) -> String {
@@ -106,6 +121,11 @@ impl LayerKind {
             LayerKind::Distance => format!("{} Distance Estimate", icons::ICON_TARGET),
             LayerKind::OrbitTrap => format!("{} Orbit Trap", icons::ICON_ORBIT),
             LayerKind::Stripe => format!("{} Stripe Average", icons::ICON_AIRWAVE),
+            LayerKind::Period => format!("{} Attractor Period", icons::ICON_CYCLE),
+            LayerKind::Multiplier => format!("{} Attractor Multiplier", icons::ICON_EXPLORE),
+            LayerKind::Lyapunov => format!("{} Lyapunov Exponent", icons::ICON_MULTILINE_CHART),
+            LayerKind::RootIndex => format!("{} Root Index", icons::ICON_HUB),
+            LayerKind::Convergence => format!("{} Convergence Speed", icons::ICON_SPEED),
         }
     }
 }
@@ -0,0 +1,1019 @@
+/*!
+# Formulas
+
+The iterated formulas `z = f(z) + c` that can be rendered, and the registry
+used to add new ones. Each formula provides its reference orbit step and
+WGSL snippets for the compute shaders, which are assembled into the pipelines
+when the GPU state is created.
+ */
+
+use std::fmt::Debug;
+use std::mem;
+use std::sync::RwLock;
+
+use color_eyre::eyre::{Result, eyre};
+use rug::ops::NegAssign;
+use rug::{Assign, Float};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// The maximum number of formulas, including the built-in ones.
+/// Formula identifiers are packed into 4 bits in the shaders.
+pub const MAX_FORMULAS: usize = 16;
+
+/// # FractalFormula
+/// An iterated formula `z = f(z) + c`. New formulas can be added with
+/// [`register_formula`], and used through [`Formula`].
+///
+/// The WGSL snippets are function bodies, and may use the `power` argument,
+/// WGSL builtins, and the helper functions `complex_mul(a: vec2f, b: vec2f) -> vec2f`,
+/// `complex_pow(z: vec2f, power: u32) -> vec2f` and `diffabs(c: f32, d: f32, exp: f32) -> f32`,
+/// which computes `(|c + d 2^exp| - |c|) / 2^exp` without losing precision.
+/// The f64 snippets may use the same helpers with f64 types, named with an `_f64` suffix,
+/// where `diffabs_f64(c: f64, d: f64) -> f64` computes `|c + d| - |c|`.
+///
+/// The CPU renderer uses the `_f64` methods instead of the snippets. Their default
+/// implementations go through [`FractalFormula::step`], which is much slower than
+/// implementing them directly.
+pub trait FractalFormula: Send + Sync {
+    /// The name the formula is stored as in settings files. Must be unique.
+    fn name(&self) -> &'static str;
+
+    /// The name of the formula shown in the UI
+    fn label(&self) -> &'static str;
+
+    /// A short description of the formula shown in the UI
+    fn description(&self) -> &'static str {
+        ""
+    }
+
+    /// Computes `f(z)` for the reference orbit, at the precision of z.
+    /// `power` is the power of the fractal kind, which may be ignored.
+    fn step(&self, z_real: &Float, z_imag: &Float, power: u32) -> (Float, Float);
+
+    /// Replaces z with `f(z)`, rounding the same as [`FractalFormula::step`]. `scratch`
+    /// holds temporaries at the precision of z, so that the reference orbit can be
+    /// computed without allocating. The default implementation goes through `step`.
+    ///
+    /// ```
+    /// # use corgi::types::Formula;
+    /// # use rug::Float;
+    /// for formula in Formula::all() {
+    ///     for power in 2..5 {
+    ///         let (re, im) = (Float::with_val(128, -0.7), Float::with_val(128, 0.1));
+    ///         let expected = formula.formula().step(&re, &im, power);
+    ///         let (mut z_real, mut z_imag) = (re, im);
+    ///         let mut scratch = [(); 4].map(|_| Float::new(128));
+    ///         formula.formula().step_in_place(&mut z_real, &mut z_imag, &mut scratch, power);
+    ///         assert_eq!((z_real, z_imag), expected);
+    ///     }
+    /// }
+    /// ```
+    fn step_in_place(
+        &self,
+        z_real: &mut Float,
+        z_imag: &mut Float,
+        _scratch: &mut [Float; 4],
+        power: u32,
+    ) {
+        let (real, imag) = self.step(z_real, z_imag, power);
+        *z_real = real;
+        *z_imag = imag;
+    }
+
+    /// The body of `fn(z: vec2f, power: u32) -> vec2f`, returning `f(z)`
+    fn direct_wgsl(&self) -> &'static str;
+
+    /// The body of `fn(x: vec2f, delta: vec2f, exp: f32, power: u32) -> vec2f`,
+    /// returning `(f(x + delta 2^exp) - f(x)) / 2^exp`. `x` is a point of the reference
+    /// orbit and `delta` is the scaled offset of the pixel from it, which may be too
+    /// small to represent without the scale. Reference points too small for an f32
+    /// are scaled to the size of the delta first, which assumes that
+    /// `f(z 2^s) = f(z) 2^(s power)`, as for all of the built-in formulas.
+    fn perturbed_wgsl(&self) -> &'static str;
+
+    /// The body of `fn(z: vec2f, power: u32) -> vec2f`, returning the derivative `f'(z)`
+    /// used for the distance estimate. Formulas that are not complex differentiable
+    /// should return a value of the same magnitude.
+    fn derivative_wgsl(&self) -> &'static str;
+
+    /// The body of `fn(z: vec2<f64>, power: u32) -> vec2<f64>`, returning `f(z)` in double
+    /// precision, or `None` if the formula is only rendered in single precision
+    fn direct_f64_wgsl(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// The body of `fn(x: vec2<f64>, delta: vec2<f64>, power: u32) -> vec2<f64>`,
+    /// returning `f(x + delta) - f(x)` in double precision, or `None` if the formula is
+    /// only rendered in single precision. The delta is not scaled, since the f64
+    /// pipelines are only used at zooms where it can be represented.
+    fn perturbed_f64_wgsl(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Computes `f(z)` in double precision for the CPU renderer
+    fn step_f64(&self, z: (f64, f64), power: u32) -> (f64, f64) {
+        let (real, imag) = self.step(&Float::with_val(53, z.0), &Float::with_val(53, z.1), power);
+        (real.to_f64(), imag.to_f64())
+    }
+
+    /// Computes `f(x + delta) - f(x)` in double precision for the CPU renderer
+    fn delta_f64(&self, x: (f64, f64), delta: (f64, f64), power: u32) -> (f64, f64) {
+        // enough precision to keep all of the bits of the delta in x + delta
+        let magnitude = |(re, im): (f64, f64)| re.abs().max(im.abs());
+        let bits = (magnitude(x) / magnitude(delta)).log2().clamp(0.0, 4096.0) as u32;
+        let precision = 64 + bits;
+        let x = (
+            Float::with_val(precision, x.0),
+            Float::with_val(precision, x.1),
+        );
+        let y = (
+            Float::with_val(precision, &x.0 + delta.0),
+            Float::with_val(precision, &x.1 + delta.1),
+        );
+        let (x_real, x_imag) = self.step(&x.0, &x.1, power);
+        let (y_real, y_imag) = self.step(&y.0, &y.1, power);
+        ((y_real - x_real).to_f64(), (y_imag - x_imag).to_f64())
+    }
+
+    /// Computes the derivative `f'(z)` used for the distance estimate in double precision
+    /// for the CPU renderer, matching [`FractalFormula::derivative_wgsl`]. The default
+    /// implementation takes the finite difference along the real axis.
+    fn derivative_f64(&self, z: (f64, f64), power: u32) -> (f64, f64) {
+        let epsilon = z.0.abs().max(z.1.abs()).max(1.0) * (-26f64).exp2();
+        let (real, imag) = self.delta_f64(z, (epsilon, 0.0), power);
+        (real / epsilon, imag / epsilon)
+    }
+}
+
+/// A handle to a built-in or registered [`FractalFormula`]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Formula(u8);
+
+impl Formula {
+    /// z = z^power + c
+    pub const MANDELBROT: Formula = Formula(0);
+    /// z = (|re z| + i |im z|)^2 + c
+    pub const BURNING_SHIP: Formula = Formula(1);
+    /// z = conj(z)^2 + c
+    pub const MANDELBAR: Formula = Formula(2);
+    /// z = |re z^2| + i im z^2 + c
+    pub const CELTIC: Formula = Formula(3);
+    /// z = (|re z| - i im z)^2 + c
+    pub const PERPENDICULAR: Formula = Formula(4);
+
+    /// The identifier of this formula in the shaders
+    pub fn id(self) -> u32 {
+        self.0 as u32
+    }
+
+    /// The implementation of this formula
+    pub fn formula(self) -> &'static dyn FractalFormula {
+        match BUILTIN_FORMULAS.get(self.0 as usize) {
+            Some(formula) => *formula,
+            None => REGISTERED_FORMULAS.read().unwrap()[self.0 as usize - BUILTIN_FORMULAS.len()],
+        }
+    }
+
+    /// Whether the formula provides the snippets for the f64 pipelines
+    pub fn supports_f64(self) -> bool {
+        let formula = self.formula();
+        formula.direct_f64_wgsl().is_some() && formula.perturbed_f64_wgsl().is_some()
+    }
+
+    /// All of the built-in and registered formulas
+    pub fn all() -> Vec<Formula> {
+        let count = BUILTIN_FORMULAS.len() + REGISTERED_FORMULAS.read().unwrap().len();
+        (0..count as u8).map(Formula).collect()
+    }
+
+    /// Finds a formula by its name
+    pub fn from_name(name: &str) -> Option<Formula> {
+        Formula::all()
+            .into_iter()
+            .find(|formula| formula.formula().name() == name)
+    }
+}
+
+impl Debug for Formula {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.formula().name())
+    }
+}
+
+impl Serialize for Formula {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.formula().name())
+    }
+}
+
+impl<'de> Deserialize<'de> for Formula {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Formula::from_name(&name).ok_or_else(|| D::Error::custom(format!("Unknown formula {name}")))
+    }
+}
+
+static BUILTIN_FORMULAS: [&dyn FractalFormula; 5] = [
+    &Mandelbrot,
+    &BurningShip,
+    &Mandelbar,
+    &Celtic,
+    &Perpendicular,
+];
+
+static REGISTERED_FORMULAS: RwLock<Vec<&'static dyn FractalFormula>> = RwLock::new(Vec::new());
+
+/// Adds a formula, so that it can be used in hybrid sequences and loaded from settings files.
+/// Formulas must be registered before the GPU state is created to be included in the shaders.
+pub fn register_formula(formula: &'static dyn FractalFormula) -> Result<Formula> {
+    if Formula::from_name(formula.name()).is_some() {
+        return Err(eyre!("A formula named {} already exists", formula.name()));
+    }
+    let mut registered = REGISTERED_FORMULAS.write().unwrap();
+    let id = BUILTIN_FORMULAS.len() + registered.len();
+    if id >= MAX_FORMULAS {
+        return Err(eyre!("No more than {MAX_FORMULAS} formulas can be used"));
+    }
+    registered.push(formula);
+    Ok(Formula(id as u8))
+}
+
+/// Computes z^power by repeated multiplication, at the precision of z
+pub(crate) fn complex_pow(real: &Float, imag: &Float, power: u32) -> (Float, Float) {
+    let mut result = (real.clone(), imag.clone());
+    for _ in 1..power {
+        result = (
+            result.0.clone() * real - result.1.clone() * imag,
+            result.0 * imag + result.1 * real,
+        );
+    }
+    result
+}
+
+/// Computes (re z^2, 2 re z im z), which is shared by the quadratic formulas
+fn square_parts(z_real: &Float, z_imag: &Float) -> (Float, Float) {
+    let precision = z_real.prec();
+    let real = Float::with_val(precision, z_real.square_ref())
+        - Float::with_val(precision, z_imag.square_ref());
+    let imag = Float::with_val(precision, z_real * z_imag) * 2;
+    (real, imag)
+}
+
+/// Computes z^power like [`complex_pow`], in place
+fn complex_pow_in_place(
+    z_real: &mut Float,
+    z_imag: &mut Float,
+    [real, imag, next_real, product]: &mut [Float; 4],
+    power: u32,
+) {
+    real.assign(&*z_real);
+    imag.assign(&*z_imag);
+    for _ in 1..power {
+        next_real.assign(&*real * &*z_real);
+        product.assign(&*imag * &*z_imag);
+        *next_real -= &*product;
+        product.assign(&*real * &*z_imag);
+        *imag *= &*z_real;
+        *imag += &*product;
+        mem::swap(real, next_real);
+    }
+    mem::swap(z_real, real);
+    mem::swap(z_imag, imag);
+}
+
+/// Computes [`square_parts`] in place
+fn square_parts_in_place(
+    z_real: &mut Float,
+    z_imag: &mut Float,
+    [real_square, imag_square, ..]: &mut [Float; 4],
+) {
+    real_square.assign(z_real.square_ref());
+    imag_square.assign(z_imag.square_ref());
+    *z_imag *= &*z_real;
+    *z_imag *= 2;
+    z_real.assign(&*real_square - &*imag_square);
+}
+
+struct Mandelbrot;
+
+impl FractalFormula for Mandelbrot {
+    fn name(&self) -> &'static str {
+        "Mandelbrot"
+    }
+
+    fn label(&self) -> &'static str {
+        "Mandelbrot"
+    }
+
+    fn description(&self) -> &'static str {
+        "Squares z, or raises it to the Multibrot power."
+    }
+
+    fn step(&self, z_real: &Float, z_imag: &Float, power: u32) -> (Float, Float) {
+        if power == 2 {
+            square_parts(z_real, z_imag)
+        } else {
+            complex_pow(z_real, z_imag, power)
+        }
+    }
+
+    fn step_in_place(
+        &self,
+        z_real: &mut Float,
+        z_imag: &mut Float,
+        scratch: &mut [Float; 4],
+        power: u32,
+    ) {
+        if power == 2 {
+            square_parts_in_place(z_real, z_imag, scratch);
+        } else {
+            complex_pow_in_place(z_real, z_imag, scratch, power);
+        }
+    }
+
+    fn direct_wgsl(&self) -> &'static str {
+        "
+    if power == 2u {
+        return vec2(z.x * z.x - z.y * z.y, (z.x + z.x) * z.y);
+    }
+    return complex_pow(z, power);"
+    }
+
+    fn perturbed_wgsl(&self) -> &'static str {
+        "
+    let scale = exp2(exp);
+    if power == 2u {
+        return vec2(
+            2.0 * (x.x * delta.x - x.y * delta.y) + (delta.x * delta.x - delta.y * delta.y) * scale,
+            2.0 * (x.x * delta.y + x.y * delta.x) + (delta.x * delta.y + delta.x * delta.y) * scale,
+        );
+    }
+    // (x + delta)^d - x^d = delta * sum_(k=1)^d C(d, k) x^(d-k) delta^(k-1),
+    // evaluated with Horner's method. The unscaled delta underflows to zero
+    // at deep zooms, where only the linear term is left.
+    let unscaled = delta * scale;
+    var coefficient = 1.0;
+    var x_power = vec2(1.0, 0.0);
+    var sum = vec2(1.0, 0.0);
+    for (var k = power - 1u; k > 0u; k -= 1u) {
+        coefficient *= f32(k + 1u) / f32(power - k);
+        x_power = complex_mul(x_power, x);
+        sum = complex_mul(sum, unscaled) + coefficient * x_power;
+    }
+    return complex_mul(sum, delta);"
+    }
+
+    fn derivative_wgsl(&self) -> &'static str {
+        "
+    if power == 2u {
+        return 2.0 * z;
+    }
+    return f32(power) * complex_pow(z, power - 1u);"
+    }
+
+    fn direct_f64_wgsl(&self) -> Option<&'static str> {
+        Some(
+            "
+    if power == 2u {
+        return vec2(z.x * z.x - z.y * z.y, (z.x + z.x) * z.y);
+    }
+    return complex_pow_f64(z, power);",
+        )
+    }
+
+    fn perturbed_f64_wgsl(&self) -> Option<&'static str> {
+        Some(
+            "
+    if power == 2u {
+        return vec2(
+            2.0 * (x.x * delta.x - x.y * delta.y) + delta.x * delta.x - delta.y * delta.y,
+            2.0 * (x.x * delta.y + x.y * delta.x + delta.x * delta.y),
+        );
+    }
+    // the same expansion as the f32 snippet, without the scale
+    var coefficient: f64 = 1.0;
+    var x_power = vec2<f64>(1.0, 0.0);
+    var sum = vec2<f64>(1.0, 0.0);
+    for (var k = power - 1u; k > 0u; k -= 1u) {
+        coefficient *= f64(k + 1u) / f64(power - k);
+        x_power = complex_mul_f64(x_power, x);
+        sum = complex_mul_f64(sum, delta) + coefficient * x_power;
+    }
+    return complex_mul_f64(sum, delta);",
+        )
+    }
+
+    fn step_f64(&self, (x, y): (f64, f64), power: u32) -> (f64, f64) {
+        if power == 2 {
+            return (x * x - y * y, (x + x) * y);
+        }
+        complex_pow_f64((x, y), power)
+    }
+
+    fn delta_f64(&self, x: (f64, f64), delta: (f64, f64), power: u32) -> (f64, f64) {
+        if power == 2 {
+            return (
+                2.0 * (x.0 * delta.0 - x.1 * delta.1) + delta.0 * delta.0 - delta.1 * delta.1,
+                2.0 * (x.0 * delta.1 + x.1 * delta.0 + delta.0 * delta.1),
+            );
+        }
+        // the same expansion as the f64 snippet
+        let mut coefficient = 1.0;
+        let mut x_power = (1.0, 0.0);
+        let mut sum = (1.0, 0.0);
+        for k in (1..power).rev() {
+            coefficient *= (k + 1) as f64 / (power - k) as f64;
+            x_power = complex_mul_f64(x_power, x);
+            let product = complex_mul_f64(sum, delta);
+            sum = (
+                product.0 + coefficient * x_power.0,
+                product.1 + coefficient * x_power.1,
+            );
+        }
+        complex_mul_f64(sum, delta)
+    }
+
+    fn derivative_f64(&self, z: (f64, f64), power: u32) -> (f64, f64) {
+        if power == 2 {
+            return (2.0 * z.0, 2.0 * z.1);
+        }
+        let (real, imag) = complex_pow_f64(z, power - 1);
+        (power as f64 * real, power as f64 * imag)
+    }
+}
+
+/// The derivative of z^2, used by the folds of the other quadratic formulas
+fn square_derivative_f64((x, y): (f64, f64)) -> (f64, f64) {
+    (2.0 * x, 2.0 * y)
+}
+
+// The folds of the other quadratic formulas only reflect z, so they use the
+// derivative of z^2, which has the same magnitude.
+const SQUARE_DERIVATIVE_WGSL: &str = "
+    return 2.0 * z;";
+
+struct BurningShip;
+
+impl FractalFormula for BurningShip {
+    fn name(&self) -> &'static str {
+        "BurningShip"
+    }
+
+    fn label(&self) -> &'static str {
+        "Burning Ship"
+    }
+
+    fn description(&self) -> &'static str {
+        "Takes the absolute value of both parts of z before squaring it."
+    }
+
+    fn step(&self, z_real: &Float, z_imag: &Float, _power: u32) -> (Float, Float) {
+        let (real, imag) = square_parts(z_real, z_imag);
+        (real, imag.abs())
+    }
+
+    fn step_in_place(
+        &self,
+        z_real: &mut Float,
+        z_imag: &mut Float,
+        scratch: &mut [Float; 4],
+        _power: u32,
+    ) {
+        square_parts_in_place(z_real, z_imag, scratch);
+        z_imag.abs_mut();
+    }
+
+    fn direct_wgsl(&self) -> &'static str {
+        "
+    return vec2(z.x * z.x - z.y * z.y, 2.0 * abs(z.x * z.y));"
+    }
+
+    fn perturbed_wgsl(&self) -> &'static str {
+        "
+    let scale = exp2(exp);
+    return vec2(
+        2.0 * (x.x * delta.x - x.y * delta.y) + (delta.x * delta.x - delta.y * delta.y) * scale,
+        2.0 * diffabs(x.x * x.y, x.x * delta.y + x.y * delta.x + delta.x * delta.y * scale, exp),
+    );"
+    }
+
+    fn derivative_wgsl(&self) -> &'static str {
+        SQUARE_DERIVATIVE_WGSL
+    }
+
+    fn direct_f64_wgsl(&self) -> Option<&'static str> {
+        // the snippet does not depend on the float type
+        Some(self.direct_wgsl())
+    }
+
+    fn perturbed_f64_wgsl(&self) -> Option<&'static str> {
+        Some(
+            "
+    return vec2(
+        2.0 * (x.x * delta.x - x.y * delta.y) + delta.x * delta.x - delta.y * delta.y,
+        2.0 * diffabs_f64(x.x * x.y, x.x * delta.y + x.y * delta.x + delta.x * delta.y),
+    );",
+        )
+    }
+
+    fn step_f64(&self, (x, y): (f64, f64), _power: u32) -> (f64, f64) {
+        (x * x - y * y, 2.0 * (x * y).abs())
+    }
+
+    fn delta_f64(&self, x: (f64, f64), delta: (f64, f64), _power: u32) -> (f64, f64) {
+        (
+            2.0 * (x.0 * delta.0 - x.1 * delta.1) + delta.0 * delta.0 - delta.1 * delta.1,
+            2.0 * diffabs_f64(x.0 * x.1, x.0 * delta.1 + x.1 * delta.0 + delta.0 * delta.1),
+        )
+    }
+
+    fn derivative_f64(&self, z: (f64, f64), _power: u32) -> (f64, f64) {
+        square_derivative_f64(z)
+    }
+}
+
+struct Mandelbar;
+
+impl FractalFormula for Mandelbar {
+    fn name(&self) -> &'static str {
+        "Mandelbar"
+    }
+
+    fn label(&self) -> &'static str {
+        "Mandelbar"
+    }
+
+    fn description(&self) -> &'static str {
+        "Squares the complex conjugate of z."
+    }
+
+    fn step(&self, z_real: &Float, z_imag: &Float, _power: u32) -> (Float, Float) {
+        let (real, imag) = square_parts(z_real, z_imag);
+        (real, -imag)
+    }
+
+    fn step_in_place(
+        &self,
+        z_real: &mut Float,
+        z_imag: &mut Float,
+        scratch: &mut [Float; 4],
+        _power: u32,
+    ) {
+        square_parts_in_place(z_real, z_imag, scratch);
+        z_imag.neg_assign();
+    }
+
+    fn direct_wgsl(&self) -> &'static str {
+        "
+    return vec2(z.x * z.x - z.y * z.y, -(z.x + z.x) * z.y);"
+    }
+
+    fn perturbed_wgsl(&self) -> &'static str {
+        "
+    let scale = exp2(exp);
+    return vec2(
+        2.0 * (x.x * delta.x - x.y * delta.y) + (delta.x * delta.x - delta.y * delta.y) * scale,
+        -2.0 * (x.x * delta.y + x.y * delta.x) - 2.0 * delta.x * delta.y * scale,
+    );"
+    }
+
+    fn derivative_wgsl(&self) -> &'static str {
+        SQUARE_DERIVATIVE_WGSL
+    }
+
+    fn direct_f64_wgsl(&self) -> Option<&'static str> {
+        Some(self.direct_wgsl())
+    }
+
+    fn perturbed_f64_wgsl(&self) -> Option<&'static str> {
+        Some(
+            "
+    return vec2(
+        2.0 * (x.x * delta.x - x.y * delta.y) + delta.x * delta.x - delta.y * delta.y,
+        -2.0 * (x.x * delta.y + x.y * delta.x + delta.x * delta.y),
+    );",
+        )
+    }
+
+    fn step_f64(&self, (x, y): (f64, f64), _power: u32) -> (f64, f64) {
+        (x * x - y * y, -(x + x) * y)
+    }
+
+    fn delta_f64(&self, x: (f64, f64), delta: (f64, f64), _power: u32) -> (f64, f64) {
+        (
+            2.0 * (x.0 * delta.0 - x.1 * delta.1) + delta.0 * delta.0 - delta.1 * delta.1,
+            -2.0 * (x.0 * delta.1 + x.1 * delta.0 + delta.0 * delta.1),
+        )
+    }
+
+    fn derivative_f64(&self, z: (f64, f64), _power: u32) -> (f64, f64) {
+        square_derivative_f64(z)
+    }
+}
+
+struct Celtic;
+
+impl FractalFormula for Celtic {
+    fn name(&self) -> &'static str {
+        "Celtic"
+    }
+
+    fn label(&self) -> &'static str {
+        "Celtic"
+    }
+
+    fn description(&self) -> &'static str {
+        "Takes the absolute value of the real part of z squared."
+    }
+
+    fn step(&self, z_real: &Float, z_imag: &Float, _power: u32) -> (Float, Float) {
+        let (real, imag) = square_parts(z_real, z_imag);
+        (real.abs(), imag)
+    }
+
+    fn step_in_place(
+        &self,
+        z_real: &mut Float,
+        z_imag: &mut Float,
+        scratch: &mut [Float; 4],
+        _power: u32,
+    ) {
+        square_parts_in_place(z_real, z_imag, scratch);
+        z_real.abs_mut();
+    }
+
+    fn direct_wgsl(&self) -> &'static str {
+        "
+    return vec2(abs(z.x * z.x - z.y * z.y), (z.x + z.x) * z.y);"
+    }
+
+    fn perturbed_wgsl(&self) -> &'static str {
+        "
+    let scale = exp2(exp);
+    let re = 2.0 * (x.x * delta.x - x.y * delta.y) + (delta.x * delta.x - delta.y * delta.y) * scale;
+    return vec2(
+        diffabs(x.x * x.x - x.y * x.y, re, exp),
+        2.0 * (x.x * delta.y + x.y * delta.x) + 2.0 * delta.x * delta.y * scale,
+    );"
+    }
+
+    fn derivative_wgsl(&self) -> &'static str {
+        SQUARE_DERIVATIVE_WGSL
+    }
+
+    fn direct_f64_wgsl(&self) -> Option<&'static str> {
+        Some(self.direct_wgsl())
+    }
+
+    fn perturbed_f64_wgsl(&self) -> Option<&'static str> {
+        Some(
+            "
+    let re = 2.0 * (x.x * delta.x - x.y * delta.y) + delta.x * delta.x - delta.y * delta.y;
+    return vec2(
+        diffabs_f64(x.x * x.x - x.y * x.y, re),
+        2.0 * (x.x * delta.y + x.y * delta.x + delta.x * delta.y),
+    );",
+        )
+    }
+
+    fn step_f64(&self, (x, y): (f64, f64), _power: u32) -> (f64, f64) {
+        ((x * x - y * y).abs(), (x + x) * y)
+    }
+
+    fn delta_f64(&self, x: (f64, f64), delta: (f64, f64), _power: u32) -> (f64, f64) {
+        let real = 2.0 * (x.0 * delta.0 - x.1 * delta.1) + delta.0 * delta.0 - delta.1 * delta.1;
+        (
+            diffabs_f64(x.0 * x.0 - x.1 * x.1, real),
+            2.0 * (x.0 * delta.1 + x.1 * delta.0 + delta.0 * delta.1),
+        )
+    }
+
+    fn derivative_f64(&self, z: (f64, f64), _power: u32) -> (f64, f64) {
+        square_derivative_f64(z)
+    }
+}
+
+struct Perpendicular;
+
+impl FractalFormula for Perpendicular {
+    fn name(&self) -> &'static str {
+        "Perpendicular"
+    }
+
+    fn label(&self) -> &'static str {
+        "Perpendicular"
+    }
+
+    fn description(&self) -> &'static str {
+        "Takes the absolute value of the real part of z before squaring its conjugate."
+    }
+
+    fn step(&self, z_real: &Float, z_imag: &Float, _power: u32) -> (Float, Float) {
+        let precision = z_real.prec();
+        let real = Float::with_val(precision, z_real.square_ref())
+            - Float::with_val(precision, z_imag.square_ref());
+        let imag = -(Float::with_val(precision, z_real.abs_ref()) * z_imag) * 2;
+        (real, imag)
+    }
+
+    fn step_in_place(
+        &self,
+        z_real: &mut Float,
+        z_imag: &mut Float,
+        [real_square, imag_square, real_abs, _]: &mut [Float; 4],
+        _power: u32,
+    ) {
+        real_square.assign(z_real.square_ref());
+        imag_square.assign(z_imag.square_ref());
+        real_abs.assign(z_real.abs_ref());
+        *z_imag *= &*real_abs;
+        z_imag.neg_assign();
+        *z_imag *= 2;
+        z_real.assign(&*real_square - &*imag_square);
+    }
+
+    fn direct_wgsl(&self) -> &'static str {
+        "
+    return vec2(z.x * z.x - z.y * z.y, -2.0 * abs(z.x) * z.y);"
+    }
+
+    fn perturbed_wgsl(&self) -> &'static str {
+        "
+    let scale = exp2(exp);
+    return vec2(
+        2.0 * (x.x * delta.x - x.y * delta.y) + (delta.x * delta.x - delta.y * delta.y) * scale,
+        -2.0 * (diffabs(x.x, delta.x, exp) * (x.y + delta.y * scale) + abs(x.x) * delta.y),
+    );"
+    }
+
+    fn derivative_wgsl(&self) -> &'static str {
+        SQUARE_DERIVATIVE_WGSL
+    }
+
+    fn direct_f64_wgsl(&self) -> Option<&'static str> {
+        Some(self.direct_wgsl())
+    }
+
+    fn perturbed_f64_wgsl(&self) -> Option<&'static str> {
+        Some(
+            "
+    return vec2(
+        2.0 * (x.x * delta.x - x.y * delta.y) + delta.x * delta.x - delta.y * delta.y,
+        -2.0 * (diffabs_f64(x.x, delta.x) * (x.y + delta.y) + abs(x.x) * delta.y),
+    );",
+        )
+    }
+
+    fn step_f64(&self, (x, y): (f64, f64), _power: u32) -> (f64, f64) {
+        (x * x - y * y, -2.0 * x.abs() * y)
+    }
+
+    fn delta_f64(&self, x: (f64, f64), delta: (f64, f64), _power: u32) -> (f64, f64) {
+        (
+            2.0 * (x.0 * delta.0 - x.1 * delta.1) + delta.0 * delta.0 - delta.1 * delta.1,
+            -2.0 * (diffabs_f64(x.0, delta.0) * (x.1 + delta.1) + x.0.abs() * delta.1),
+        )
+    }
+
+    fn derivative_f64(&self, z: (f64, f64), _power: u32) -> (f64, f64) {
+        square_derivative_f64(z)
+    }
+}
+
+/// Multiplies two complex numbers in double precision
+pub fn complex_mul_f64(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    (a.0 * b.0 - a.1 * b.1, a.0 * b.1 + a.1 * b.0)
+}
+
+/// Computes z^power by squaring in double precision, like `complex_pow_f64` in the shaders
+pub fn complex_pow_f64(z: (f64, f64), power: u32) -> (f64, f64) {
+    let mut result = (1.0, 0.0);
+    let mut base = z;
+    let mut n = power;
+    while n > 0 {
+        if n & 1 != 0 {
+            result = complex_mul_f64(result, base);
+        }
+        base = complex_mul_f64(base, base);
+        n >>= 1;
+    }
+    result
+}
+
+/// Computes `|c + d| - |c|` without losing the precision of d, like `diffabs_f64` in the shaders
+pub fn diffabs_f64(c: f64, d: f64) -> f64 {
+    if c >= 0.0 {
+        if c + d >= 0.0 {
+            return d;
+        }
+        return -(2.0 * c + d);
+    }
+    if c + d > 0.0 {
+        return 2.0 * c + d;
+    }
+    -d
+}
+
+/// The helper functions available to the formula snippets
+const HELPERS_WGSL: &str = "
+fn complex_mul(a: vec2f, b: vec2f) -> vec2f {
+    return vec2(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x);
+}
+
+fn complex_pow(z: vec2f, power: u32) -> vec2f {
+    var result = vec2(1.0, 0.0);
+    var base = z;
+    for (var n = power; n > 0u; n >>= 1u) {
+        if (n & 1u) != 0u {
+            result = complex_mul(result, base);
+        }
+        base = complex_mul(base, base);
+    }
+    return result;
+}
+
+fn diffabs(c: f32, d: f32, exp: f32) -> f32 {
+    // the sign of c can not change if it is much larger than the offset
+    if log2(abs(c)) > log2(abs(d)) + exp + 2.0 {
+        return sign(c) * d;
+    }
+    // otherwise, c is small enough to be scaled to the size of the delta
+    let c_scaled = ldexp(c * exp2(floor(exp) - exp), -i32(floor(exp)));
+    if c >= 0.0 {
+        if c_scaled + d >= 0.0 {
+            return d;
+        }
+        return -(2.0 * c_scaled + d);
+    }
+    if c_scaled + d > 0.0 {
+        return 2.0 * c_scaled + d;
+    }
+    return -d;
+}
+";
+
+/// The helper functions available to the f64 formula snippets
+const HELPERS_F64_WGSL: &str = "
+fn complex_mul_f64(a: vec2<f64>, b: vec2<f64>) -> vec2<f64> {
+    return vec2(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x);
+}
+
+fn complex_pow_f64(z: vec2<f64>, power: u32) -> vec2<f64> {
+    var result = vec2<f64>(1.0, 0.0);
+    var base = z;
+    for (var n = power; n > 0u; n >>= 1u) {
+        if (n & 1u) != 0u {
+            result = complex_mul_f64(result, base);
+        }
+        base = complex_mul_f64(base, base);
+    }
+    return result;
+}
+
+fn diffabs_f64(c: f64, d: f64) -> f64 {
+    if c >= 0.0 {
+        if c + d >= 0.0 {
+            return d;
+        }
+        return -(2.0 * c + d);
+    }
+    if c + d > 0.0 {
+        return 2.0 * c + d;
+    }
+    return -d;
+}
+";
+
+/// A function in a compute shader which is generated from the snippets of every formula.
+/// The shader declares it with a placeholder body, and an extra first argument
+/// selecting the formula.
+pub(crate) struct FormulaFunction {
+    /// The name of the function in the shader
+    pub name: &'static str,
+    /// The arguments of the snippets, other than the power
+    pub args: &'static [(&'static str, &'static str)],
+    /// The type returned by the function
+    pub ty: &'static str,
+    /// The snippet of a formula, if it provides one
+    pub body: fn(&dyn FractalFormula) -> Option<&'static str>,
+}
+
+pub(crate) const DIRECT_STEP: FormulaFunction = FormulaFunction {
+    name: "formula_step",
+    args: &[("z", "vec2f")],
+    ty: "vec2f",
+    body: |formula| Some(formula.direct_wgsl()),
+};
+
+pub(crate) const PERTURBED_STEP: FormulaFunction = FormulaFunction {
+    name: "formula_delta",
+    args: &[("x", "vec2f"), ("delta", "vec2f"), ("exp", "f32")],
+    ty: "vec2f",
+    body: |formula| Some(formula.perturbed_wgsl()),
+};
+
+pub(crate) const DERIVATIVE: FormulaFunction = FormulaFunction {
+    name: "formula_derivative",
+    args: &[("z", "vec2f")],
+    ty: "vec2f",
+    body: |formula| Some(formula.derivative_wgsl()),
+};
+
+pub(crate) const DIRECT_STEP_F64: FormulaFunction = FormulaFunction {
+    name: "formula_step_f64",
+    args: &[("z", "vec2<f64>")],
+    ty: "vec2<f64>",
+    body: |formula| formula.direct_f64_wgsl(),
+};
+
+pub(crate) const PERTURBED_STEP_F64: FormulaFunction = FormulaFunction {
+    name: "formula_delta_f64",
+    args: &[("x", "vec2<f64>"), ("delta", "vec2<f64>")],
+    ty: "vec2<f64>",
+    body: |formula| formula.perturbed_f64_wgsl(),
+};
+
+/// Replaces the placeholders of `functions` in a compiled shader with dispatches
+/// to the snippets of all formulas. Formulas without a snippet return zero,
+/// and must not be rendered with the shader.
+pub(crate) fn assemble_formulas(source: &str, functions: &[FormulaFunction]) -> String {
+    let formulas = Formula::all();
+    let mut source = source.to_string();
+    for function in functions {
+        let params = function
+            .args
+            .iter()
+            .map(|(name, kind)| format!("{name}: {kind}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let args = function
+            .args
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let name = function.name;
+        let ty = function.ty;
+
+        let mut generated = format!("fn {name}(formula: u32, {params}) -> {ty} {{\n");
+        generated += "    switch formula {\n";
+        for formula in formulas.iter().skip(1) {
+            let id = formula.id();
+            generated +=
+                &format!("        case {id}u: {{ return {name}_{id}({args}, params.power); }}\n");
+        }
+        generated += &format!("        default: {{ return {name}_0({args}, params.power); }}\n");
+        generated += "    }\n}\n";
+        for formula in &formulas {
+            let body = (function.body)(formula.formula())
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("\n    return {ty}();"));
+            generated += &format!(
+                "\nfn {name}_{}({params}, power: u32) -> {ty} {{{body}\n}}\n",
+                formula.id(),
+            );
+        }
+        source = replace_function(&source, name, &generated);
+    }
+    source + HELPERS_WGSL
+}
+
+/// Switches a compiled shader using the `double` alias from the f64 shader utilities
+/// to double precision, and adds the helpers of the f64 formula snippets
+pub(crate) fn enable_f64(source: &str) -> String {
+    let source = source
+        .lines()
+        .map(|line| {
+            if line.starts_with("alias ") && line.ends_with("double = f32;") {
+                line.replace("= f32;", "= f64;")
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    source + HELPERS_F64_WGSL
+}
+
+/// Replaces the declaration of the function `name` in `source`
+fn replace_function(source: &str, name: &str, replacement: &str) -> String {
+    let start = source
+        .find(&format!("fn {name}("))
+        .unwrap_or_else(|| panic!("The shader does not declare {name}"));
+    let body = start + source[start..].find('{').unwrap();
+    let mut depth = 0;
+    let mut end = body;
+    for (i, char) in source[body..].char_indices() {
+        match char {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => continue,
+        }
+        if depth == 0 {
+            end = body + i + 1;
+            break;
+        }
+    }
+    format!("{}{replacement}{}", &source[..start], &source[end..])
+}
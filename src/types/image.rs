@@ -11,7 +11,10 @@ use rug::Float;
 use rug::ops::{CompleteRound, PowAssign};
 use serde::{Deserialize, Serialize};
 
-use super::{Coloring, Transform, get_precision};
+use super::{
+    Coloring, Formula, MAX_HYBRID_STEPS, MAX_LYAPUNOV_SEQUENCE, MAX_NEWTON_DEGREE, Transform,
+    get_precision,
+};
 use crate::image_gen::is_metadata_supported;
 use crate::types::{Layer, LayerKind, next_layer_id};
 
@@ -54,6 +57,13 @@ pub struct Viewport {
     pub scaling: f64,
     pub zoom: f64,
     pub center: ComplexPoint,
+    /// The counterclockwise rotation of the viewport around its center, in radians
+    #[serde(default)]
+    pub angle: f64,
+    /// A linear transform of the offsets from the center, applied before the rotation,
+    /// as the rows of a 2x2 matrix. This frames the stretched regions of deep zooms.
+    #[serde(default)]
+    pub skew: Option<[[f64; 2]; 2]>,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -64,27 +74,130 @@ pub struct ComplexPoint {
     pub y: Float,
 }
 
+/// The center of a minibrot, which makes an ideal reference point
+/// since its orbit is periodic and never escapes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Nucleus {
+    pub location: ComplexPoint,
+    pub period: u64,
+}
+
 /// A representation of the current image being rendered, including
 /// the viewport, coloring, and other parameters
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(default)]
 pub struct Image {
     pub fractal_kind: FractalKind,
+    /// A repeating sequence of formulas to use instead of the formula of the fractal
+    /// kind, one per iteration. The fractal kind still selects the Julia parameter
+    /// and the power. At most [`MAX_HYBRID_STEPS`] are used.
+    pub hybrid: Vec<Formula>,
     pub viewport: Viewport,
     pub max_iter: u64,
+    /// Whether to replace the max iteration with
+    /// [`IterationStats::suggested_max_iter`](crate::types::IterationStats::suggested_max_iter)
+    /// after each render
+    pub auto_max_iter: bool,
     pub probe_location: ComplexPoint,
+    /// Whether to replace the probe location with the nearest minibrot nucleus in view
+    pub auto_reference: bool,
     pub external_coloring: Coloring,
     pub internal_coloring: Coloring,
+    /// Whether to color the escape time of each pixel, or the density of escaping orbits
+    pub render_mode: RenderMode,
     #[serde(skip)]
     pub optimization_level: OptLevel,
     pub misc: f32,
     pub debug_shutter: f32,
 }
 
+// the flags of the compute shaders, from [`Image::get_flags`]
+pub(crate) const STRIPES_ENABLED: u32 = 0x1;
+pub(crate) const TOTAL_ANGLE_ENABLED: u32 = 0x2;
+pub(crate) const ORBIT_ENABLED: u32 = 0x4;
+pub(crate) const DERIVATIVE_ENABLED: u32 = 0x8;
+pub(crate) const INTERIOR_DISTANCE_ENABLED: u32 = 0x10;
+pub(crate) const ATTRACTOR_ENABLED: u32 = 0x20;
+const FORMULA_SHIFT: u32 = 24;
+pub(crate) const JULIA: u32 = 0x1000_0000;
+
+/// The smallest determinant of a 2x2 matrix that is inverted, relative to the sum of
+/// the squares of its entries. Smaller ones are too close to singular to be undone.
+const MIN_RELATIVE_DETERMINANT: f64 = 1e-12;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Algorithm {
     Directf32,
     Perturbedf32,
+    Directf64,
+    Perturbedf64,
+    /// The perturbed algorithm with emulated double precision from pairs of f32s
+    Perturbedf32x2,
+}
+
+impl Algorithm {
+    /// Whether the algorithm iterates offsets from a reference orbit
+    pub fn is_perturbed(self) -> bool {
+        matches!(
+            self,
+            Algorithm::Perturbedf32 | Algorithm::Perturbedf64 | Algorithm::Perturbedf32x2
+        )
+    }
+
+    /// Whether the algorithm uses the f64 shaders
+    pub fn is_f64(self) -> bool {
+        matches!(self, Algorithm::Directf64 | Algorithm::Perturbedf64)
+    }
+}
+
+/// The zoom levels at which each [`Algorithm`] is used
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AlgorithmPolicy {
+    /// Whether to use the f64 shaders if the GPU supports them
+    pub use_f64: bool,
+    /// The zoom at which direct calculation in f32 stops being accurate enough
+    pub perturbation_zoom: f64,
+    /// The zoom at which direct calculation in f64 stops being accurate enough
+    pub perturbation_zoom_f64: f64,
+    /// The zoom at which the pixel offsets get too small for an f64,
+    /// above which the extended range of the perturbed f32 shader is needed
+    pub max_zoom_f64: f64,
+    /// Whether to replace the perturbed f32 shader with the slower, more accurate
+    /// emulated double precision shader
+    pub double_single: bool,
+}
+
+impl Default for AlgorithmPolicy {
+    fn default() -> Self {
+        Self {
+            use_f64: true,
+            perturbation_zoom: 13.0,
+            perturbation_zoom_f64: 40.0,
+            max_zoom_f64: 960.0,
+            double_single: false,
+        }
+    }
+}
+
+impl AlgorithmPolicy {
+    /// Selects the algorithm for an image at the given zoom. f32 is preferred where it
+    /// is accurate enough, since f64 is much slower on most GPUs.
+    pub fn select(
+        &self,
+        zoom: f64,
+        f64_supported: bool,
+        double_single_supported: bool,
+    ) -> Algorithm {
+        let f64 = self.use_f64 && f64_supported;
+        match zoom {
+            x if x < self.perturbation_zoom => Algorithm::Directf32,
+            x if f64 && x < self.perturbation_zoom_f64 => Algorithm::Directf64,
+            x if f64 && x < self.max_zoom_f64 => Algorithm::Perturbedf64,
+            _ if self.double_single && double_single_supported => Algorithm::Perturbedf32x2,
+            _ => Algorithm::Perturbedf32,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
@@ -95,11 +208,198 @@ pub enum OptLevel {
     PerformanceOptimized,
 }
 
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub enum RenderMode {
+    /// Colors each pixel by the escape time of its orbit
+    #[default]
+    Escape,
+    /// Accumulates the points of randomly sampled escaping orbits into each pixel,
+    /// as in the Buddhabrot
+    Density(DensityOptions),
+    /// Colors the Lyapunov exponent of a sequence of logistic maps, with the growth
+    /// rates of A and B on the real and imaginary axes
+    Lyapunov(LyapunovOptions),
+    /// Colors the root each point converges to with Newton's method on a polynomial,
+    /// or the fixed point of the Nova fractal
+    Newton(NewtonOptions),
+}
+
+/// The parameters of the [`RenderMode::Density`] mode
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DensityOptions {
+    /// The number of orbits to sample
+    pub samples: u32,
+    /// The range of escape iterations (start inclusive, end exclusive) whose orbits
+    /// are counted in the red, green and blue channels
+    pub bands: [[u32; 2]; 3],
+    /// The brightness of the counts, relative to the largest count of each channel
+    pub exposure: f32,
+    pub gamma: f32,
+    /// The seed of the sampled points
+    pub seed: u32,
+}
+
+impl Default for DensityOptions {
+    fn default() -> Self {
+        // the bands of the Nebulabrot
+        Self {
+            samples: 10_000_000,
+            bands: [[0, 5000], [0, 500], [0, 50]],
+            exposure: 1.0,
+            gamma: 2.0,
+            seed: 0,
+        }
+    }
+}
+
+impl DensityOptions {
+    /// The number of iterations needed to find which bands each orbit is counted in
+    pub fn max_iter(&self) -> u32 {
+        self.bands.iter().map(|[_, end]| *end).max().unwrap_or(0)
+    }
+
+    /// Whether the counts of both options are the same, so only the tone mapping differs
+    pub fn same_samples(&self, other: &Self) -> bool {
+        self.samples == other.samples && self.bands == other.bands && self.seed == other.seed
+    }
+}
+
+/// The parameters of the [`RenderMode::Lyapunov`] mode
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LyapunovOptions {
+    /// The repeating sequence of growth rates, as a string of `A`s and `B`s.
+    /// At most [`MAX_LYAPUNOV_SEQUENCE`] letters are used.
+    pub sequence: String,
+    /// The number of iterations to skip before the exponent is measured,
+    /// so the orbit can settle first
+    pub warmup: u32,
+}
+
+impl Default for LyapunovOptions {
+    fn default() -> Self {
+        Self {
+            sequence: "AB".into(),
+            warmup: 200,
+        }
+    }
+}
+
+impl LyapunovOptions {
+    /// Packs the sequence into 1 bit per letter (1 for B), returning the packed
+    /// sequence and its length. Other characters are ignored, and an empty
+    /// sequence is treated as `AB`.
+    pub fn pattern(&self) -> (u32, u32) {
+        let letters: Vec<bool> = self
+            .sequence
+            .chars()
+            .filter_map(|c| match c.to_ascii_uppercase() {
+                'A' => Some(false),
+                'B' => Some(true),
+                _ => None,
+            })
+            .take(MAX_LYAPUNOV_SEQUENCE)
+            .collect();
+        if letters.is_empty() {
+            return (0b10, 2);
+        }
+        let packed = letters
+            .iter()
+            .enumerate()
+            .fold(0, |packed, (i, &b)| packed | (b as u32) << i);
+        (packed, letters.len() as u32)
+    }
+}
+
+/// The parameters of the [`RenderMode::Newton`] mode
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct NewtonOptions {
+    /// The complex coefficient of each power of z, starting from z^0.
+    /// At most [`MAX_NEWTON_DEGREE`] + 1 coefficients are used.
+    pub coefficients: Vec<[f32; 2]>,
+    /// The complex factor R in `z - R p(z) / p'(z)`
+    pub relaxation: [f32; 2],
+    /// Whether to render the Nova fractal, `z - R p(z) / p'(z) + c`, which
+    /// starts at `start` for each point c
+    pub nova: bool,
+    pub start: [f32; 2],
+    /// The distance between iterations below which a point has converged
+    pub tolerance: f32,
+}
+
+impl Default for NewtonOptions {
+    fn default() -> Self {
+        // z^3 - 1
+        Self {
+            coefficients: vec![[-1.0, 0.0], [0.0, 0.0], [0.0, 0.0], [1.0, 0.0]],
+            relaxation: [1.0, 0.0],
+            nova: false,
+            start: [1.0, 0.0],
+            tolerance: 1e-4,
+        }
+    }
+}
+
+impl NewtonOptions {
+    /// The coefficients up to the highest power of z with a non-zero coefficient
+    pub fn polynomial(&self) -> &[[f32; 2]] {
+        let coefficients = &self.coefficients[..self.coefficients.len().min(MAX_NEWTON_DEGREE + 1)];
+        let len = coefficients
+            .iter()
+            .rposition(|&c| c != [0.0, 0.0])
+            .map_or(0, |degree| degree + 1);
+        &coefficients[..len]
+    }
+}
+
+impl RenderMode {
+    pub fn density(&self) -> Option<&DensityOptions> {
+        match self {
+            RenderMode::Density(options) => Some(options),
+            _ => None,
+        }
+    }
+
+    pub fn lyapunov(&self) -> Option<&LyapunovOptions> {
+        match self {
+            RenderMode::Lyapunov(options) => Some(options),
+            _ => None,
+        }
+    }
+
+    pub fn newton(&self) -> Option<&NewtonOptions> {
+        match self {
+            RenderMode::Newton(options) => Some(options),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
 pub enum FractalKind {
     #[default]
     Mandelbrot,
     Julia(ComplexPoint),
+    /// The Mandelbrot set generalized to z^power + c
+    Multibrot {
+        power: u32,
+    },
+    /// The Julia set generalized to z^power + c
+    MultibrotJulia {
+        power: u32,
+        point: ComplexPoint,
+    },
+    BurningShip,
+    BurningShipJulia(ComplexPoint),
+    /// Also known as the Tricorn
+    Mandelbar,
+    MandelbarJulia(ComplexPoint),
+    Celtic,
+    CelticJulia(ComplexPoint),
+    Perpendicular,
+    PerpendicularJulia(ComplexPoint),
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -108,20 +408,27 @@ pub struct ImageDiff {
     pub recompute: bool,
     pub recolor: bool,
     pub resize: bool,
+    /// The max iteration of the previous image, if only the max iteration was raised
+    /// since then. The compute step can then continue the pixels that had not escaped.
+    pub resume_from: Option<u64>,
 }
 
 impl Default for Image {
     fn default() -> Self {
         Self {
             fractal_kind: FractalKind::Mandelbrot,
+            hybrid: Vec::new(),
             viewport: Viewport::default(),
             probe_location: ComplexPoint {
                 x: Float::with_val(53, -0.5),
                 y: Float::with_val(53, 0.0),
             },
+            auto_reference: false,
             max_iter: 10000,
+            auto_max_iter: false,
             external_coloring: Coloring::default(),
             internal_coloring: Coloring::internal_default(),
+            render_mode: RenderMode::Escape,
             misc: 1.0,
             debug_shutter: 0.0,
             optimization_level: OptLevel::AccuracyOptimized,
@@ -140,6 +447,8 @@ impl Default for Viewport {
                 x: Float::with_val(53, -0.5),
                 y: Float::with_val(53, 0.0),
             },
+            angle: 0.0,
+            skew: None,
         }
     }
 }
@@ -153,9 +462,65 @@ impl Default for ComplexPoint {
     }
 }
 
+impl FractalKind {
+    /// The iterated formula
+    pub fn formula(&self) -> Formula {
+        match self {
+            FractalKind::Mandelbrot
+            | FractalKind::Julia(_)
+            | FractalKind::Multibrot { .. }
+            | FractalKind::MultibrotJulia { .. } => Formula::MANDELBROT,
+            FractalKind::BurningShip | FractalKind::BurningShipJulia(_) => Formula::BURNING_SHIP,
+            FractalKind::Mandelbar | FractalKind::MandelbarJulia(_) => Formula::MANDELBAR,
+            FractalKind::Celtic | FractalKind::CelticJulia(_) => Formula::CELTIC,
+            FractalKind::Perpendicular | FractalKind::PerpendicularJulia(_) => {
+                Formula::PERPENDICULAR
+            }
+        }
+    }
+
+    /// The power of z in the iterated formula. Powers below 2 are not supported.
+    pub fn power(&self) -> u32 {
+        match self {
+            FractalKind::Multibrot { power } | FractalKind::MultibrotJulia { power, .. } => {
+                (*power).max(2)
+            }
+            _ => 2,
+        }
+    }
+
+    /// The constant c used in the Julia formula, if this is a Julia set
+    pub fn julia_point(&self) -> Option<&ComplexPoint> {
+        match self {
+            FractalKind::Mandelbrot
+            | FractalKind::Multibrot { .. }
+            | FractalKind::BurningShip
+            | FractalKind::Mandelbar
+            | FractalKind::Celtic
+            | FractalKind::Perpendicular => None,
+            FractalKind::Julia(point)
+            | FractalKind::MultibrotJulia { point, .. }
+            | FractalKind::BurningShipJulia(point)
+            | FractalKind::MandelbarJulia(point)
+            | FractalKind::CelticJulia(point)
+            | FractalKind::PerpendicularJulia(point) => Some(point),
+        }
+    }
+}
+
 impl Image {
-    pub fn algorithm(&self) -> Algorithm {
-        self.viewport.algorithm()
+    /// The repeating sequence of formulas used for each iteration
+    pub fn formulas(&self) -> Vec<Formula> {
+        if self.hybrid.is_empty() {
+            vec![self.fractal_kind.formula()]
+        } else {
+            self.hybrid.iter().copied().take(MAX_HYBRID_STEPS).collect()
+        }
+    }
+
+    /// Whether every iteration uses the (complex analytic) Mandelbrot formula
+    pub fn is_analytic(&self) -> bool {
+        self.formulas().iter().all(|&f| f == Formula::MANDELBROT)
     }
 
     pub fn comp(&self, other: &Self) -> ImageDiff {
@@ -165,24 +530,49 @@ impl Image {
             || self.viewport.scaling != other.viewport.scaling
             // if there are more bits set, then there are more enabled features
             || (self.get_flags() & 0xFF).count_ones() > (other.get_flags() & 0xFF).count_ones()
-            || self.get_flags() & 0xFF00_0000 != other.get_flags() & 0xFF00_0000
-            || self.max_iter != other.max_iter;
-        // if the max iteration or probe location has changed, re-run the probe
-        let reprobe = self.max_iter != other.max_iter
-            || self.probe_location.x != other.probe_location.x
+            || self.get_flags() & 0xFF00_0000 != other.get_flags() & 0xFF00_0000;
+        // if the probe location has changed, re-run the probe
+        let reprobe = self.probe_location.x != other.probe_location.x
             || self.probe_location.y != other.probe_location.y
-            || self.viewport.algorithm() == Algorithm::Perturbedf32
-                && other.viewport.algorithm() == Algorithm::Directf32
             || self.fractal_kind != other.fractal_kind
+            || self.hybrid != other.hybrid
+            || self.auto_reference != other.auto_reference
+            // the escape time buffers are not valid after rendering in another mode
+            || std::mem::discriminant(&self.render_mode)
+                != std::mem::discriminant(&other.render_mode)
             || resize;
+        let render_mode_changed = match (&self.render_mode, &other.render_mode) {
+            (RenderMode::Density(options), RenderMode::Density(other)) => {
+                !options.same_samples(other)
+            }
+            (mode, other) => mode != other,
+        };
+        // a higher max iteration of an otherwise identical escape time image
+        // continues the previous render, extending its reference orbit. The total angle is
+        // not kept for the resumed pixels, so images that color by it are rendered again.
+        let resume_from = (self.max_iter > other.max_iter
+            && self.render_mode == RenderMode::Escape
+            && self.get_flags() & TOTAL_ANGLE_ENABLED == 0
+            && self.viewport == other.viewport
+            && !render_mode_changed
+            && !reprobe)
+            .then_some(other.max_iter);
+        // any other change of the max iteration restarts from the probe,
+        // with buffers sized for the new reference orbit
+        let restart = self.max_iter != other.max_iter && resume_from.is_none();
+        let resize = resize || restart;
+        let reprobe = reprobe || restart;
         // if the probe location has changed or the image viewport has changed, re-generate the delta grid
         // if the image generation parameters have changed, re-run the compute shader
-        let recompute =
-            self.max_iter != other.max_iter || self.viewport != other.viewport || reprobe;
+        let recompute = self.max_iter != other.max_iter
+            || self.viewport != other.viewport
+            || render_mode_changed
+            || reprobe;
         // if the image coloring parameters have changed, re-run the image render
         let recolor = self.external_coloring != other.external_coloring
             || self.internal_coloring != other.internal_coloring
             || recompute
+            || self.render_mode != other.render_mode
             || self.misc != other.misc
             || self.debug_shutter != other.debug_shutter;
         ImageDiff {
@@ -190,6 +580,7 @@ impl Image {
             recompute,
             recolor,
             resize,
+            resume_from,
         }
     }
 
@@ -253,21 +644,26 @@ impl Image {
     }
 
     pub fn get_flags(&self) -> u32 {
-        const STRIPES_ENABLED: u32 = 0x1;
-        const TOTAL_ANGLE_ENABLED: u32 = 0x2;
-        const ORBIT_ENABLED: u32 = 0x4;
-        const DERIVATIVE_ENABLED: u32 = 0x8;
-        const JULIA: u32 = 0x1000_0000;
-        let kind_flags = match &self.fractal_kind {
-            FractalKind::Mandelbrot => 0,
-            FractalKind::Julia(_) => JULIA,
+        let formula_flags = self.fractal_kind.formula().id() << FORMULA_SHIFT;
+        let kind_flags = match self.fractal_kind.julia_point() {
+            None => formula_flags,
+            Some(_) => formula_flags | JULIA,
         };
+        // the interior distance estimate relies on the derivatives of z^power + c with respect to c,
+        // so it is only available for the Mandelbrot set and Multibrots
+        let interior_distance = self.is_analytic() && self.fractal_kind.julia_point().is_none();
         match self.optimization_level {
             OptLevel::CacheOptimized => {
                 STRIPES_ENABLED
                     | TOTAL_ANGLE_ENABLED
                     | ORBIT_ENABLED
                     | DERIVATIVE_ENABLED
+                    | ATTRACTOR_ENABLED
+                    | if interior_distance {
+                        INTERIOR_DISTANCE_ENABLED
+                    } else {
+                        0
+                    }
                     | kind_flags
             }
             OptLevel::AccuracyOptimized | OptLevel::PerformanceOptimized => {
@@ -288,6 +684,17 @@ impl Image {
                 {
                     flags |= DERIVATIVE_ENABLED;
                 }
+                if interior_distance
+                    && (self.internal_contains_kind(LayerKind::Distance)
+                        || self.internal_coloring.overlays.set_outline_color[3].fract() != 0.0)
+                {
+                    flags |= INTERIOR_DISTANCE_ENABLED;
+                }
+                if self.internal_contains_kind(LayerKind::Period)
+                    || self.internal_contains_kind(LayerKind::Multiplier)
+                {
+                    flags |= ATTRACTOR_ENABLED;
+                }
                 flags | kind_flags
             }
         }
@@ -334,19 +741,78 @@ impl Viewport {
         let mut this_scale = Float::with_val(get_precision(self.zoom), 2.0);
         this_scale.pow_assign(-self.zoom);
         let self_aspect = self.aspect_scale();
-        let aspect_scale = self_aspect / other.aspect_scale();
-        let offset: [Float; 2] = [
-            (self.center.x.clone() - other.center.x.clone()) / this_scale.clone() / self_aspect.x,
-            (self.center.y.clone() - other.center.y.clone()) / this_scale / self_aspect.y,
-        ];
+        let other_aspect = other.aspect_scale();
+        // the offset between the centers, in the transformed coordinates of this viewport
+        let (x, y) = self.inverse_transform_offset(
+            ((self.center.x.clone() - other.center.x.clone()) / this_scale.clone()).to_f64(),
+            ((self.center.y.clone() - other.center.y.clone()) / this_scale).to_f64(),
+        );
+        let matrix = matrix_mul(other.inverse_linear_transform(), self.linear_transform());
         Transform {
-            angle: 0.0,
-            _padding: 0.0,
-            scale: [scale * aspect_scale.x, scale * aspect_scale.y],
-            offset: [offset[0].to_f32(), offset[1].to_f32()],
+            matrix: columns_f32(matrix),
+            scale: [scale / other_aspect.x, scale / other_aspect.y],
+            offset: [x as f32 / self_aspect.x, y as f32 / self_aspect.y],
+            aspect: [self_aspect.x, self_aspect.y],
+            _padding: [0.0; 2],
         }
     }
 
+    /// The linear transform of the offsets from the center, after the aspect scale
+    /// and before the zoom scale, which combines the skew and the rotation
+    pub fn linear_transform(&self) -> [[f64; 2]; 2] {
+        let (sin, cos) = self.angle.sin_cos();
+        let rotation = [[cos, -sin], [sin, cos]];
+        match self.valid_skew() {
+            Some(skew) => matrix_mul(rotation, skew),
+            None => rotation,
+        }
+    }
+
+    /// The inverse of [`Viewport::linear_transform`]
+    fn inverse_linear_transform(&self) -> [[f64; 2]; 2] {
+        let (sin, cos) = self.angle.sin_cos();
+        let rotation = [[cos, sin], [-sin, cos]];
+        match self.valid_skew().and_then(matrix_inverse) {
+            Some(inverse) => matrix_mul(inverse, rotation),
+            None => rotation,
+        }
+    }
+
+    /// The skew of the viewport, which is ignored if it can not be inverted,
+    /// such as a singular skew loaded from a file
+    fn valid_skew(&self) -> Option<[[f64; 2]; 2]> {
+        self.skew.filter(|skew| matrix_inverse(*skew).is_some())
+    }
+
+    /// The linear transform in the column-major layout of a WGSL `mat2x2f`
+    pub fn linear_transform_f32(&self) -> [f32; 4] {
+        columns_f32(self.linear_transform())
+    }
+
+    /// The inverse of [`Viewport::linear_transform_f32`]
+    pub fn inverse_transform_f32(&self) -> [f32; 4] {
+        columns_f32(self.inverse_linear_transform())
+    }
+
+    /// Applies the skew and rotation of the viewport to an offset from its center
+    pub fn transform_offset(&self, x: f64, y: f64) -> (f64, f64) {
+        matrix_apply(self.linear_transform(), x, y)
+    }
+
+    /// Reverts the skew and rotation of the viewport from an offset from its center
+    pub fn inverse_transform_offset(&self, x: f64, y: f64) -> (f64, f64) {
+        matrix_apply(self.inverse_linear_transform(), x, y)
+    }
+
+    /// The largest factor by which the skew stretches the offsets from the center
+    pub fn max_stretch(&self) -> f64 {
+        let [[a, b], [c, d]] = self.valid_skew().unwrap_or([[1.0, 0.0], [0.0, 1.0]]);
+        // the largest singular value of the skew
+        let sum = a * a + b * b + c * c + d * d;
+        let det = a * d - b * c;
+        ((sum + (sum * sum - 4.0 * det * det).max(0.0).sqrt()) / 2.0).sqrt()
+    }
+
     /// The aspect ratio of the viewport
     pub fn aspect_ratio(&self) -> f64 {
         self.width as f64 / self.height as f64
@@ -368,34 +834,44 @@ impl Viewport {
         scale.pow_assign(-self.zoom);
         let aspect_scale = self.aspect_scale();
 
-        let r = ((x / self.width as f64) * 2.0 - 1.0) * scale.clone() * aspect_scale.x
-            + Float::with_val(precision, &self.center.x);
-        let i = ((y / self.height as f64) * 2.0 - 1.0) * scale.clone() * aspect_scale.y
-            + Float::with_val(precision, &self.center.y);
+        let (x, y) = self.transform_offset(
+            ((x / self.width as f64) * 2.0 - 1.0) * aspect_scale.x as f64,
+            ((y / self.height as f64) * 2.0 - 1.0) * aspect_scale.y as f64,
+        );
+        let r = x * scale.clone() + Float::with_val(precision, &self.center.x);
+        let i = y * scale + Float::with_val(precision, &self.center.y);
         (r, i)
     }
 
     /// Returns the offset in pixels from the center of this viewport to
-    /// the given location in fractal coordinates
+    /// the given location in fractal coordinates. This is the inverse of
+    /// [`Viewport::get_real_coords`], relative to the center.
+    ///
+    /// ```
+    /// use corgi::types::Viewport;
+    ///
+    /// let viewport = Viewport {
+    ///     angle: 0.5,
+    ///     ..Default::default()
+    /// };
+    /// let (r, i) = viewport.get_real_coords(400.0, 100.0);
+    /// let (x, y) = viewport.coords_to_px_offset(&r, &i);
+    /// assert!((x - 144.0).abs() < 1e-9 && (y + 156.0).abs() < 1e-9);
+    /// ```
     pub fn coords_to_px_offset(&self, r: &Float, i: &Float) -> (f64, f64) {
         let precision = get_precision(self.zoom);
         let mut scale = Float::with_val(precision, 2.0);
         scale.pow_assign(-self.zoom);
         let aspect_scale = self.aspect_scale();
 
-        let x =
-            ((r.clone() - self.center.x.clone()) / scale.clone()).to_f64() / aspect_scale.x as f64;
-        let y = ((i.clone() - self.center.y.clone()) / scale).to_f64() / aspect_scale.y as f64;
+        let (x, y) = self.inverse_transform_offset(
+            ((r.clone() - self.center.x.clone()) / scale.clone()).to_f64(),
+            ((i.clone() - self.center.y.clone()) / scale).to_f64(),
+        );
+        let (x, y) = (x / aspect_scale.x as f64, y / aspect_scale.y as f64);
         (x * 0.5 * self.width as f64, y * 0.5 * self.height as f64)
     }
 
-    pub fn algorithm(&self) -> Algorithm {
-        match self.zoom {
-            x if x < 13.0 => Algorithm::Directf32,
-            _ => Algorithm::Perturbedf32,
-        }
-    }
-
     pub fn buffer_size(&self) -> usize {
         (self.width as f64 * self.scaling) as usize * (self.height as f64 * self.scaling) as usize
     }
@@ -407,6 +883,47 @@ impl Viewport {
     }
 }
 
+/// The product of two 2x2 matrices, stored as rows
+pub fn matrix_mul(a: [[f64; 2]; 2], b: [[f64; 2]; 2]) -> [[f64; 2]; 2] {
+    [
+        [
+            a[0][0] * b[0][0] + a[0][1] * b[1][0],
+            a[0][0] * b[0][1] + a[0][1] * b[1][1],
+        ],
+        [
+            a[1][0] * b[0][0] + a[1][1] * b[1][0],
+            a[1][0] * b[0][1] + a[1][1] * b[1][1],
+        ],
+    ]
+}
+
+/// The inverse of a 2x2 matrix, stored as rows. Returns `None` if the matrix is singular,
+/// not finite, or so close to singular that its inverse would be mostly rounding error.
+///
+/// ```
+/// use corgi::types::matrix_inverse;
+///
+/// assert_eq!(matrix_inverse([[2.0, 0.0], [0.0, 4.0]]), Some([[0.5, 0.0], [0.0, 0.25]]));
+/// assert_eq!(matrix_inverse([[1.0, 2.0], [2.0, 4.0]]), None);
+/// assert_eq!(matrix_inverse([[1.0, 0.0], [0.0, 1e-20]]), None);
+/// ```
+pub fn matrix_inverse([[a, b], [c, d]]: [[f64; 2]; 2]) -> Option<[[f64; 2]; 2]> {
+    let det = a * d - b * c;
+    let size = a * a + b * b + c * c + d * d;
+    if !det.is_finite() || !size.is_finite() || det.abs() <= MIN_RELATIVE_DETERMINANT * size {
+        return None;
+    }
+    Some([[d / det, -b / det], [-c / det, a / det]])
+}
+
+fn matrix_apply([[a, b], [c, d]]: [[f64; 2]; 2], x: f64, y: f64) -> (f64, f64) {
+    (a * x + b * y, c * x + d * y)
+}
+
+fn columns_f32([[a, b], [c, d]]: [[f64; 2]; 2]) -> [f32; 4] {
+    [a as f32, c as f32, b as f32, d as f32]
+}
+
 impl From<&Viewport> for Extent3d {
     fn from(viewport: &Viewport) -> Self {
         Self {
@@ -424,6 +941,7 @@ impl ImageDiff {
             reprobe: true,
             recompute: true,
             recolor: true,
+            resume_from: None,
         }
     }
 }
@@ -5,16 +5,38 @@ A Collection of types used throughout the application, and their associated func
  */
 
 mod coloring;
+mod formula;
 mod image;
 
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::time::Duration;
 
 pub use self::coloring::*;
+pub use self::formula::*;
 pub use self::image::*;
 
 pub const ESCAPE_RADIUS: f64 = 1e10;
 
+/// The maximum length of a hybrid formula sequence. Each step is packed
+/// into 4 bits of [`ComputeParams::hybrid`].
+pub const MAX_HYBRID_STEPS: usize = 8;
+
+/// The maximum length of a Lyapunov sequence. Each letter is packed
+/// into 1 bit of [`ComputeParams::hybrid`].
+pub const MAX_LYAPUNOV_SEQUENCE: usize = 32;
+
+/// The maximum degree of the polynomial of a Newton fractal
+pub const MAX_NEWTON_DEGREE: usize = 8;
+
+/// The smallest max iteration suggested by [`IterationStats::suggested_max_iter`]
+pub const MIN_SUGGESTED_MAX_ITER: u64 = 100;
+
+/// The largest max iteration suggested by [`IterationStats::suggested_max_iter`]. Views
+/// where some pixels escape ever later, such as near the boundary of the set, stop doubling here.
+pub const MAX_SUGGESTED_MAX_ITER: u64 = 1 << 24;
+
 /// Get the precision for a given zoom level
 pub fn get_precision(zoom: f64) -> u32 {
     ((zoom * 1.25) as u32).max(53)
@@ -25,6 +47,10 @@ pub enum ImageGenCommand {
     NewPreviewSettings(Image),
     NewOutputSettings(Image),
     SaveToFile(PathBuf),
+    /// Searches for the nearest minibrot nucleus of the image, to use as its probe point
+    FindNucleus(Image),
+    /// Estimates the skew at the center of the image, to undo it with the camera
+    EstimateSkew(Image),
 }
 
 #[derive(Debug)]
@@ -32,6 +58,177 @@ pub enum StatusMessage {
     Progress(String, f64),
     NewPreviewViewport(Duration, Viewport),
     NewOutputViewport(Duration, Viewport),
+    /// The number of glitched pixels that were corrected with extra references,
+    /// and the number of glitched pixels that remain
+    GlitchesCorrected(usize, usize),
+    /// The escape iterations of the last preview render
+    IterationStats(IterationStats),
+    /// The last render was stopped before it completed
+    Cancelled,
+    /// The result of [`ImageGenCommand::FindNucleus`]
+    NucleusFound(Option<Nucleus>),
+    /// The result of [`ImageGenCommand::EstimateSkew`]
+    SkewEstimated(Option<[[f64; 2]; 2]>),
+}
+
+/// Flags shared with the render thread to stop its renders early. The render thread
+/// clears them before starting the renders of newly received commands.
+#[derive(Debug, Clone, Default)]
+pub struct CancelFlags {
+    /// Set when newer preview settings replace the preview being rendered
+    pub preview: Arc<AtomicBool>,
+    /// Set when newer output settings replace the output being rendered
+    pub output: Arc<AtomicBool>,
+    /// Set to stop the nucleus search or skew estimate being run
+    pub search: Arc<AtomicBool>,
+    /// The number of preview settings sent to the render thread
+    previews_sent: Arc<AtomicU64>,
+    /// The number of output settings sent to the render thread
+    outputs_sent: Arc<AtomicU64>,
+}
+
+impl CancelFlags {
+    /// Stops the preview and output renders, and the search being run
+    pub fn cancel_all(&self) {
+        self.preview.store(true, Ordering::Relaxed);
+        self.output.store(true, Ordering::Relaxed);
+        self.search.store(true, Ordering::Relaxed);
+    }
+
+    /// Stops the preview being rendered, which is replaced by the preview settings
+    /// sent after this
+    pub fn replace_preview(&self) {
+        self.previews_sent.fetch_add(1, Ordering::SeqCst);
+        self.preview.store(true, Ordering::SeqCst);
+    }
+
+    /// Stops the output being rendered, which is replaced by the output settings
+    /// sent after this
+    pub fn replace_output(&self) {
+        self.outputs_sent.fetch_add(1, Ordering::SeqCst);
+        self.output.store(true, Ordering::SeqCst);
+    }
+
+    /// Clears the flags before starting new renders, given the number of preview and
+    /// output settings received so far. A render flag stays set if newer settings were
+    /// sent than the received ones, since they replace the render that is about to start.
+    pub fn reset(&self, previews_received: u64, outputs_received: u64) {
+        self.search.store(false, Ordering::Relaxed);
+        for (flag, sent, received) in [
+            (&self.preview, &self.previews_sent, previews_received),
+            (&self.output, &self.outputs_sent, outputs_received),
+        ] {
+            // the flag is cleared first, so that settings sent after the count is read
+            // set it again
+            flag.store(false, Ordering::SeqCst);
+            if sent.load(Ordering::SeqCst) != received {
+                flag.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+/// Statistics of the escape iterations of the pixels of a rendered image,
+/// which are used to suggest a better max iteration
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IterationStats {
+    /// The max iteration of the rendered image
+    pub max_iter: u64,
+    pub pixels: usize,
+    /// The number of pixels which did not escape before the max iteration,
+    /// including the ones whose step is 0
+    pub unescaped: usize,
+    /// The highest iteration at which a pixel escaped
+    pub max_escaped: u64,
+    /// The number of pixels which escaped in the last half of the iterations
+    pub near_max: usize,
+}
+
+impl IterationStats {
+    /// Collects the statistics from the step buffer of the compute shaders, in which
+    /// escaped pixels have positive steps. The steps of the other pixels are the negated
+    /// iteration at which their orbit came closest to 0, which is 0 if that was its start.
+    /// Pixels escape after at least one iteration, so a step of 0 is never an escape.
+    pub fn from_steps(steps: &[i32], max_iter: u64) -> Self {
+        let mut stats = IterationStats {
+            max_iter,
+            pixels: steps.len(),
+            ..Default::default()
+        };
+        for &step in steps {
+            if step <= 0 {
+                stats.unescaped += 1;
+                continue;
+            }
+            stats.max_escaped = stats.max_escaped.max(step as u64);
+            if step as u64 * 2 > max_iter {
+                stats.near_max += 1;
+            }
+        }
+        stats
+    }
+
+    /// Suggests a new max iteration, or `None` if the current one suits the image.
+    ///
+    /// The max iteration is doubled if more than one in a thousand pixels escaped in the
+    /// last half of the iterations, since more of them would likely escape with more
+    /// iterations. It is also doubled if more than a tenth of the pixels hit the max
+    /// iteration while some still escaped in its last half, since the boundary between
+    /// them is then likely cut off. It is lowered to twice the highest escape iteration if
+    /// every escaped pixel did so in the first quarter, which does not change the image.
+    /// Views where no pixel escaped, such as inside the set, are kept, since more
+    /// iterations would not change them.
+    ///
+    /// ```
+    /// use corgi::types::IterationStats;
+    ///
+    /// // many pixels escape close to the max iteration
+    /// let stats = IterationStats::from_steps(&[10, 20, 900, -5], 1000);
+    /// assert_eq!(stats.suggested_max_iter(), Some(2000));
+    ///
+    /// // few pixels escape close to it, but many hit it
+    /// let mut steps = vec![-5; 500];
+    /// steps.extend([600; 10]);
+    /// steps.extend([10; 20_000]);
+    /// let stats = IterationStats::from_steps(&steps, 1000);
+    /// assert_eq!(stats.unescaped, 500);
+    /// assert_eq!(stats.suggested_max_iter(), None);
+    /// steps.extend([0; 2000]);
+    /// let stats = IterationStats::from_steps(&steps, 1000);
+    /// assert_eq!(stats.unescaped, 2500);
+    /// assert_eq!(stats.suggested_max_iter(), Some(2000));
+    ///
+    /// // no pixel gets close to the max iteration
+    /// let stats = IterationStats::from_steps(&[10, 200, -5], 1000);
+    /// assert_eq!(stats.suggested_max_iter(), Some(400));
+    ///
+    /// let stats = IterationStats::from_steps(&[10, 400, -5], 1000);
+    /// assert_eq!(stats.suggested_max_iter(), None);
+    ///
+    /// // every pixel escapes early, down to the smallest suggestion
+    /// let stats = IterationStats::from_steps(&[3, 5, 8, 12], 1000);
+    /// assert_eq!(stats.unescaped, 0);
+    /// assert_eq!(stats.suggested_max_iter(), Some(100));
+    ///
+    /// // the view is inside the set
+    /// let stats = IterationStats::from_steps(&[-5, -3, 0], 1000);
+    /// assert_eq!(stats.suggested_max_iter(), None);
+    /// ```
+    pub fn suggested_max_iter(&self) -> Option<u64> {
+        let suggestion = if self.pixels == 0 || self.max_escaped == 0 {
+            return None;
+        } else if self.near_max * 1000 > self.pixels
+            || (self.near_max > 0 && self.unescaped * 10 > self.pixels)
+        {
+            self.max_iter * 2
+        } else if self.max_escaped * 4 < self.max_iter {
+            self.max_escaped * 2
+        } else {
+            return None;
+        };
+        let suggestion = suggestion.clamp(MIN_SUGGESTED_MAX_ITER, MAX_SUGGESTED_MAX_ITER);
+        (suggestion != self.max_iter).then_some(suggestion)
+    }
 }
 
 /// Shared status between the main thread and the render thread
@@ -96,8 +293,18 @@ pub struct ComputeParams {
     pub height: u32,
     pub max_iter: u32,
     pub chunk_max_iter: u32,
+    /// The length of the reference orbit
     pub probe_len: u32,
+    /// The length of the critical orbit of Julia sets, which follows the reference orbit
+    pub critical_len: u32,
     pub iter_offset: u32,
+    pub start_iter: u32,
+    pub glitch_pass: u32,
+    pub power: u32,
+    /// The formula ids of the hybrid sequence, 4 bits per step
+    pub hybrid: u32,
+    /// The length of the hybrid sequence, or 0 to use the formula in the flags
+    pub hybrid_len: u32,
     pub x: f32,
     pub y: f32,
     pub cx: f32,
@@ -105,6 +312,140 @@ pub struct ComputeParams {
     pub zoom: f32,
     pub julia_x: f32,
     pub julia_y: f32,
+    /// The max iteration of the previous render when it is resumed at a higher one, or 0
+    pub resume_iter: u32,
+    /// The first reference iteration of the window of a streamed orbit in the probe buffer
+    pub window_start: u32,
+    /// The length of each window of a streamed orbit, or `u32::MAX` if the orbits fit
+    pub window_len: u32,
+    /// The length of the heads of both orbits in front of the window, or 0 if the orbits fit
+    pub head_len: u32,
+    pub _padding: u32,
+    /// The skew and rotation of the pixel offsets, from [`Viewport::linear_transform_f32`]
+    pub transform: [f32; 4],
+    // double precision values, which are only read by the f64 shaders
+    pub x_f64: f64,
+    pub y_f64: f64,
+    pub cx_f64: f64,
+    pub cy_f64: f64,
+    pub julia_x_f64: f64,
+    pub julia_y_f64: f64,
+    /// 2^-zoom
+    pub scale_f64: f64,
+}
+
+/// The parameters for the density shader of [`RenderMode::Density`]. This is sent as a uniform
+/// to the density shader.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DensityParams {
+    pub width: u32,
+    pub height: u32,
+    /// The index of the first sample of this batch
+    pub sample_offset: u32,
+    /// The number of samples in this batch
+    pub sample_count: u32,
+    pub seed: u32,
+    pub max_iter: u32,
+    pub power: u32,
+    /// The formula ids of the hybrid sequence, 4 bits per step
+    pub hybrid: u32,
+    /// The first escape iteration counted by each channel (the last is unused)
+    pub band_min: [u32; 4],
+    /// The escape iteration after the last one counted by each channel
+    pub band_max: [u32; 4],
+    /// The length of the hybrid sequence, or 0 to use the formula in the flags
+    pub hybrid_len: u32,
+    pub x: f32,
+    pub y: f32,
+    pub zoom: f32,
+    pub julia_x: f32,
+    pub julia_y: f32,
+    pub exposure: f32,
+    pub gamma: f32,
+    /// The inverse of the skew and rotation of the pixel offsets, as the columns of a 2x2 matrix
+    pub inverse_transform: [f32; 4],
+}
+
+/// The polynomial and its roots for the Newton shader of [`RenderMode::Newton`]. This is sent
+/// as a uniform to the Newton shader.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct NewtonParams {
+    /// The degree of the polynomial
+    pub degree: u32,
+    /// The number of roots in `roots`, which is the degree unless the polynomial is constant
+    pub root_count: u32,
+    /// Whether to add the point to each iteration, for the Nova fractal
+    pub nova: u32,
+    /// The distance between iterations below which a point has converged
+    pub tolerance: f32,
+    /// The factor R of the Newton step
+    pub relaxation: [f32; 2],
+    /// The starting point of the Nova fractal
+    pub start: [f32; 2],
+    /// The coefficient of each power of z, as `[re, im, 0, 0]`
+    pub coefficients: [[f32; 4]; MAX_NEWTON_DEGREE + 1],
+    /// The roots of the polynomial, as `[re, im, 0, 0]`
+    pub roots: [[f32; 4]; MAX_NEWTON_DEGREE],
+}
+
+/// The initial state of the perturbed compute shader after skipping the first
+/// iterations with a series approximation. This is sent as a storage buffer
+/// to the compute shader.
+///
+/// The coefficients are stored as (real, imaginary, exponent, unused).
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SeriesParams {
+    pub a: [f32; 4],
+    pub b: [f32; 4],
+    pub c: [f32; 4],
+    /// The orbit traps of the skipped reference iterations
+    pub orbits: [f32; 4],
+    /// The stripe sums of the skipped reference iterations
+    pub stripes: [f32; 4],
+    /// The total angle state: (angles, total angle, closest distance, minimum iteration)
+    pub angles: [f32; 4],
+    /// The total angle state: (line, previous point)
+    pub line: [f32; 4],
+}
+
+/// The number of level offsets stored in a [`BlaHeader`]
+pub const MAX_BLA_LEVELS: usize = 32;
+
+/// The header of the bilinear approximation table. This is sent to the
+/// perturbed compute shader at the start of a storage buffer, followed
+/// by the [`BlaEntry`] list.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BlaHeader {
+    /// The first reference iteration covered by the table. Blocks are
+    /// aligned relative to this iteration.
+    pub start: u32,
+    pub min_level: u32,
+    pub max_level: u32,
+    pub _padding: u32,
+    /// The index of the first entry of each level
+    pub offsets: [u32; MAX_BLA_LEVELS],
+}
+
+/// A bilinear approximation of `2^level` iterations of the perturbed delta:
+///
+///   delta_(n + 2^level) = A delta_n + B delta_0
+///
+/// Coefficients are stored as (real, imaginary, exponent, _).
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BlaEntry {
+    /// The A coefficient, with the base 2 logarithm of the validity radius in `w`
+    pub a: [f32; 4],
+    pub b: [f32; 4],
+    /// The orbit traps of the reference iterations skipped by this entry
+    pub orbits: [f32; 4],
+    /// The stripe sums of the reference iterations skipped by this entry,
+    /// with the total angle sum in `w`
+    pub stripes: [f32; 4],
 }
 
 /// The parameters for the render shader. This is sent as a uniform
@@ -118,6 +459,7 @@ pub struct RenderParams {
     pub zoom: f32,
     pub misc: f32,
     pub debug_shutter: f32,
+    pub power: f32,
 }
 
 impl From<&Image> for RenderParams {
@@ -129,6 +471,7 @@ impl From<&Image> for RenderParams {
             zoom: image.viewport.zoom as f32,
             misc: image.misc,
             debug_shutter: image.debug_shutter,
+            power: image.fractal_kind.power() as f32,
         }
     }
 }
@@ -138,19 +481,24 @@ impl From<&Image> for RenderParams {
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Transform {
-    pub angle: f32,
-    pub _padding: f32,
+    /// The linear transform from the rendered image to the view, as the
+    /// columns of a 2x2 matrix
+    pub matrix: [f32; 4],
     pub scale: [f32; 2],
     pub offset: [f32; 2],
+    /// The aspect scale of the rendered image, which is applied before the matrix
+    pub aspect: [f32; 2],
+    pub _padding: [f32; 2],
 }
 
 impl Default for Transform {
     fn default() -> Self {
         Self {
-            angle: 0.0,
-            _padding: 0.0,
+            matrix: [1.0, 0.0, 0.0, 1.0],
             scale: [1.0, 1.0],
             offset: [0.0, 0.0],
+            aspect: [1.0, 1.0],
+            _padding: [0.0, 0.0],
         }
     }
 }
@@ -253,6 +253,8 @@ impl EditUI for Gradient {
 pub struct OrbitType(pub u8);
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct StripeType(pub u8);
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct MultiplierPart(pub u8);
 
 impl EditUI for Layer {
     fn render_edit_ui(&mut self, _ctx: &egui::Context, tui: &mut egui_taffy::Tui) {
@@ -355,6 +357,62 @@ impl EditUI for Layer {
 
                 self.param = index.0 as f32 - 1.0 + offset;
             }
+            LayerKind::Period => {
+                input_with_label(
+                    tui,
+                    "Strength",
+                    Some(strength_help_text),
+                    egui::DragValue::new(&mut self.strength).speed(0.01),
+                );
+            }
+            LayerKind::Multiplier => {
+                let mut index = MultiplierPart(self.param as u8 + 1);
+                if index.0 > 2 {
+                    index.0 = 2;
+                }
+                let mut offset = self.param.fract();
+                selection_with_label(
+                    tui,
+                    "Multiplier Part",
+                    Some("The part of the multiplier that is used"),
+                    &mut index,
+                    vec![1, 2].into_iter().map(MultiplierPart).collect(),
+                );
+                input_with_label(
+                    tui,
+                    "Strength",
+                    Some(strength_help_text),
+                    egui::DragValue::new(&mut self.strength).speed(0.01),
+                );
+                input_with_label(
+                    tui,
+                    "Offset",
+                    Some(
+                        "Subtracts this value from this layer. Mostly useful in Lighting to adjust the black level of the layer.",
+                    ),
+                    egui::DragValue::new(&mut offset)
+                        .speed(0.003)
+                        .range(0.0..=0.99),
+                );
+
+                self.param = index.0 as f32 - 1.0 + offset;
+            }
+            LayerKind::Lyapunov | LayerKind::RootIndex | LayerKind::Convergence => {
+                input_with_label(
+                    tui,
+                    "Strength",
+                    Some(strength_help_text),
+                    egui::DragValue::new(&mut self.strength).speed(0.01),
+                );
+                input_with_label(
+                    tui,
+                    "Offset",
+                    Some(
+                        "Adds this value to the layer. In Lighting, it is subtracted instead to adjust the black level of the layer.",
+                    ),
+                    egui::DragValue::new(&mut self.param).speed(0.01),
+                );
+            }
         }
     }
 }
@@ -592,6 +650,11 @@ impl EditUI for [Layer; 8] {
                                         LayerKind::Distance,
                                         LayerKind::OrbitTrap,
                                         LayerKind::Stripe,
+                                        LayerKind::Period,
+                                        LayerKind::Multiplier,
+                                        LayerKind::Lyapunov,
+                                        LayerKind::RootIndex,
+                                        LayerKind::Convergence,
                                     ],
                                 );
                                 tui.grow().add_empty();
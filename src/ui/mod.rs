@@ -11,7 +11,9 @@ use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 
 use corgi::types::{
-    Coloring, ComplexPoint, Image, ImageGenCommand, OptLevel, Status, Viewport, get_precision,
+    CancelFlags, Coloring, ComplexPoint, DensityOptions, Formula, Image, ImageGenCommand,
+    IterationStats, LyapunovOptions, MAX_HYBRID_STEPS, MAX_NEWTON_DEGREE, NewtonOptions, Nucleus,
+    OptLevel, RenderMode, Status, Viewport, get_precision, matrix_inverse,
 };
 use directories::BaseDirs;
 use eframe::egui::containers::menu::MenuButton;
@@ -27,7 +29,10 @@ use rug::Float;
 use rug::ops::PowAssign;
 use taffy::Overflow;
 use taffy::prelude::*;
-use utils::{TuiExt, collapsible, input_with_label, point_edit, section, selection_with_label};
+use utils::{
+    TuiExt, collapsible, input_with_label, point_edit, section, selection_with_label,
+    text_edit_with_label, ui_with_label,
+};
 
 mod coloring;
 mod preview_resources;
@@ -71,8 +76,10 @@ pub struct CorgiUI {
     pub swap: bool,
     pub status: Status,
     command_channel: mpsc::Sender<ImageGenCommand>,
+    cancel_flags: CancelFlags,
     output_path: PathBuf,
     show_settings: bool,
+    suggested_max_iter: Option<u64>,
 }
 
 impl CorgiUI {
@@ -81,6 +88,7 @@ impl CorgiUI {
         context: &crate::Context,
         image: Image,
         command_channel: mpsc::Sender<ImageGenCommand>,
+        cancel_flags: CancelFlags,
     ) -> Self {
         let default_output_viewport = Viewport {
             width: 3840,
@@ -88,6 +96,8 @@ impl CorgiUI {
             scaling: image.viewport.scaling,
             zoom: image.viewport.zoom,
             center: image.viewport.center.clone(),
+            angle: image.viewport.angle,
+            skew: image.viewport.skew,
         };
         Self {
             status: Status::default(),
@@ -114,9 +124,57 @@ impl CorgiUI {
             setting_probe: false,
             swap: false,
             command_channel,
+            cancel_flags,
             tab: UITab::Explore,
             output_path: context.cache().previous_paths.image.clone(),
             show_settings: false,
+            suggested_max_iter: None,
+        }
+    }
+
+    /// Suggests a new max iteration from the statistics of the last preview render,
+    /// or applies it directly in the automatic mode
+    pub fn update_max_iter(&mut self, stats: IterationStats) {
+        // the statistics of an older max iteration are out of date
+        if stats.max_iter != self.output_settings.max_iter {
+            return;
+        }
+        self.suggested_max_iter = stats.suggested_max_iter();
+        if self.output_settings.auto_max_iter
+            && let Some(max_iter) = self.suggested_max_iter.take()
+        {
+            self.output_settings.max_iter = max_iter;
+        }
+    }
+
+    /// Sets the probe point of the current view to a nucleus found by the render thread
+    pub fn apply_nucleus(&mut self, nucleus: Option<Nucleus>) {
+        match nucleus {
+            Some(Nucleus { location, period }) => {
+                match self.view_state {
+                    ViewState::Viewport => self.explore_settings.probe_location = location,
+                    ViewState::OutputView | ViewState::OutputLock | ViewState::Output => {
+                        self.output_settings.probe_location = location
+                    }
+                }
+                self.status.message = format!("Found a nucleus of period {period}");
+            }
+            None => self.status.message = "No nucleus found in view".to_string(),
+        }
+    }
+
+    /// Sets the skew of the current view to one estimated by the render thread
+    pub fn apply_skew(&mut self, skew: Option<[[f64; 2]; 2]>) {
+        let viewport = match self.view_state {
+            ViewState::Viewport => &mut self.explore_settings.viewport,
+            ViewState::OutputView | ViewState::OutputLock | ViewState::Output => {
+                &mut self.output_settings.viewport
+            }
+        };
+        // a singular skew can not be undone, so it is not applied
+        match skew.filter(|skew| matrix_inverse(*skew).is_some()) {
+            Some(skew) => viewport.skew = Some(skew),
+            None => self.status.message = "Could not estimate the skew of the view".to_string(),
         }
     }
 
@@ -281,6 +339,8 @@ impl CorgiUI {
                                 })
                                 .add(|tui| {
                                     if tui.ui_add(Button::new("Render")).clicked() {
+                                        // the new settings replace the output being rendered
+                                        self.cancel_flags.replace_output();
                                         let image = self.output_settings.clone();
                                         let _ = self
                                             .command_channel
@@ -336,6 +396,16 @@ impl CorgiUI {
                             );
                         },
                     );
+                    if ui
+                        .add_visible(
+                            self.status.progress.is_some(),
+                            Button::new(icons::ICON_CANCEL).frame(false),
+                        )
+                        .on_hover_text("Cancel the render")
+                        .clicked()
+                    {
+                        self.cancel_flags.cancel_all();
+                    }
                     ui.separator();
                     ui.label(&self.status.message)
                 })
@@ -486,6 +556,11 @@ impl CorgiUI {
             ..Default::default()
         })
         .add(|tui| {
+            // keep the current power when switching between the Multibrot modes
+            let multibrot_power = match img.fractal_kind.power() {
+                2 => 3,
+                power => power,
+            };
             selection_with_label(
                 tui,
                 "Fractal Mode",
@@ -494,15 +569,286 @@ impl CorgiUI {
                 vec![
                     corgi::types::FractalKind::Mandelbrot,
                     corgi::types::FractalKind::Julia(img.viewport.center.clone()),
+                    corgi::types::FractalKind::Multibrot { power: multibrot_power },
+                    corgi::types::FractalKind::MultibrotJulia {
+                        power: multibrot_power,
+                        point: img.viewport.center.clone(),
+                    },
+                    corgi::types::FractalKind::BurningShip,
+                    corgi::types::FractalKind::BurningShipJulia(img.viewport.center.clone()),
+                    corgi::types::FractalKind::Mandelbar,
+                    corgi::types::FractalKind::MandelbarJulia(img.viewport.center.clone()),
+                    corgi::types::FractalKind::Celtic,
+                    corgi::types::FractalKind::CelticJulia(img.viewport.center.clone()),
+                    corgi::types::FractalKind::Perpendicular,
+                    corgi::types::FractalKind::PerpendicularJulia(img.viewport.center.clone()),
                 ],
             );
             match &mut self.explore_settings.fractal_kind {
-                corgi::types::FractalKind::Mandelbrot => {}
-                corgi::types::FractalKind::Julia(pt) => {
+                corgi::types::FractalKind::Mandelbrot
+                | corgi::types::FractalKind::BurningShip
+                | corgi::types::FractalKind::Mandelbar
+                | corgi::types::FractalKind::Celtic
+                | corgi::types::FractalKind::Perpendicular => {}
+                corgi::types::FractalKind::Julia(pt)
+                | corgi::types::FractalKind::BurningShipJulia(pt)
+                | corgi::types::FractalKind::MandelbarJulia(pt)
+                | corgi::types::FractalKind::CelticJulia(pt)
+                | corgi::types::FractalKind::PerpendicularJulia(pt) => {
                     point_edit(tui, "Julia parameter", Some("The C value used in the Julia equation. picking values from interesting locations in the Mandelbrot set tend to be interesting in the Julia Set."), get_precision(img.viewport.zoom), pt);
                 }
+                corgi::types::FractalKind::Multibrot { power } => {
+                    input_with_label(
+                        tui,
+                        "Power",
+                        Some("The power z is raised to in the iterated formula. Higher powers have more symmetry, and lose precision sooner near the escape radius."),
+                        egui::DragValue::new(power)
+                            .speed(0.05)
+                            .range(2..=16)
+                            .update_while_editing(false),
+                    );
+                }
+                corgi::types::FractalKind::MultibrotJulia { power, point } => {
+                    input_with_label(
+                        tui,
+                        "Power",
+                        Some("The power z is raised to in the iterated formula. Higher powers have more symmetry, and lose precision sooner near the escape radius."),
+                        egui::DragValue::new(power)
+                            .speed(0.05)
+                            .range(2..=16)
+                            .update_while_editing(false),
+                    );
+                    point_edit(tui, "Julia parameter", Some("The C value used in the Julia equation. picking values from interesting locations in the Multibrot set tend to be interesting in the Julia Set."), get_precision(img.viewport.zoom), point);
+                }
             }
             self.output_settings.fractal_kind = self.explore_settings.fractal_kind.clone();
+            collapsible(tui, "Hybrid Formula", |tui| {
+                tui.small("Alternates between these formulas each iteration, instead of only using the fractal mode's formula.");
+                let hybrid = &mut self.explore_settings.hybrid;
+                for i in 0..hybrid.len() {
+                    let mut remove = false;
+                    tui.horizontal().add(|tui| {
+                        tui.grow().add(|tui| {
+                            selection_with_label(
+                                tui,
+                                &format!("Step {}", i + 1),
+                                None,
+                                &mut hybrid[i],
+                                Formula::all(),
+                            );
+                        });
+                        remove = tui
+                            .ui_add(Button::new(icons::ICON_DELETE))
+                            .on_hover_text("Delete")
+                            .clicked();
+                    });
+                    if remove {
+                        hybrid.remove(i);
+                        // the indexes after this one are no longer valid
+                        break;
+                    }
+                }
+                if tui
+                    .enabled_ui(hybrid.len() < MAX_HYBRID_STEPS)
+                    .ui_add(Button::new(format!("{} Add Formula Step", icons::ICON_ADD)))
+                    .clicked()
+                {
+                    hybrid.push(self.explore_settings.fractal_kind.formula());
+                }
+            });
+            self.output_settings.hybrid = self.explore_settings.hybrid.clone();
+            collapsible(tui, "Density Rendering", |tui| {
+                tui.small("Counts how often the orbits of randomly sampled escaping points pass through each pixel, instead of coloring the escape time. The coloring settings are not used.");
+                let was_enabled = self.output_settings.render_mode.density().is_some();
+                let mut enabled = was_enabled;
+                tui.ui_add(egui::Checkbox::new(&mut enabled, "Render Densities"));
+                let render_mode = &mut self.output_settings.render_mode;
+                if enabled != was_enabled {
+                    *render_mode = if enabled {
+                        RenderMode::Density(DensityOptions::default())
+                    } else {
+                        RenderMode::Escape
+                    };
+                }
+                let RenderMode::Density(options) = render_mode else {
+                    return;
+                };
+                input_with_label(
+                    tui,
+                    "Samples",
+                    Some("The number of points to sample. More samples reduce the noise of the image."),
+                    egui::DragValue::new(&mut options.samples)
+                        .speed(10000.0)
+                        .range(1..=u32::MAX)
+                        .update_while_editing(false),
+                );
+                for (channel, band) in ["Red", "Green", "Blue"].iter().zip(&mut options.bands) {
+                    ui_with_label(
+                        tui,
+                        &format!("{channel} Iterations"),
+                        Some("Only orbits which escape after at least the first and fewer than the second number of iterations are counted in this channel."),
+                        |tui| {
+                            tui.ui_add(
+                                egui::DragValue::new(&mut band[0])
+                                    .speed(1.0)
+                                    .update_while_editing(false),
+                            );
+                            tui.ui_add(
+                                egui::DragValue::new(&mut band[1])
+                                    .speed(1.0)
+                                    .update_while_editing(false),
+                            );
+                        },
+                    );
+                }
+                input_with_label(
+                    tui,
+                    "Exposure",
+                    Some("Scales the brightness of the counts, relative to the largest count of each channel."),
+                    egui::DragValue::new(&mut options.exposure)
+                        .speed(0.01)
+                        .range(0.0..=f32::MAX),
+                );
+                input_with_label(
+                    tui,
+                    "Gamma",
+                    Some("Brightens the lower counts, which are often much smaller than the largest count."),
+                    egui::DragValue::new(&mut options.gamma)
+                        .speed(0.01)
+                        .range(0.1..=10.0),
+                );
+                input_with_label(
+                    tui,
+                    "Seed",
+                    Some("Selects a different set of sampled points."),
+                    egui::DragValue::new(&mut options.seed).update_while_editing(false),
+                );
+            });
+            collapsible(tui, "Lyapunov Fractal", |tui| {
+                tui.small("Colors the Lyapunov exponent of the logistic map, using the real part of each point as the growth rate A and the imaginary part as B. Stable points use the internal coloring, and the Lyapunov Exponent layer colors the exponent.");
+                let was_enabled = self.output_settings.render_mode.lyapunov().is_some();
+                let mut enabled = was_enabled;
+                tui.ui_add(egui::Checkbox::new(&mut enabled, "Render Lyapunov Exponents"));
+                if enabled != was_enabled {
+                    if enabled {
+                        self.output_settings.render_mode =
+                            RenderMode::Lyapunov(LyapunovOptions::default());
+                        // move to the region where both growth rates are from 2 to 4
+                        for viewport in [
+                            &mut self.explore_settings.viewport,
+                            &mut self.output_settings.viewport,
+                        ] {
+                            viewport.center = ComplexPoint {
+                                x: Float::with_val(53, 3.0),
+                                y: Float::with_val(53, 3.0),
+                            };
+                            viewport.zoom = 0.0;
+                        }
+                    } else {
+                        self.output_settings.render_mode = RenderMode::Escape;
+                    }
+                }
+                let RenderMode::Lyapunov(options) = &mut self.output_settings.render_mode else {
+                    return;
+                };
+                text_edit_with_label(
+                    tui,
+                    "Sequence",
+                    Some("The repeating sequence of growth rates, such as AB or AABAB. Up to 32 letters are used."),
+                    &mut options.sequence,
+                );
+                input_with_label(
+                    tui,
+                    "Warmup Iterations",
+                    Some("The number of iterations skipped before measuring the exponent, so the orbit can settle first. The exponent is measured over the maximum iterations after these."),
+                    egui::DragValue::new(&mut options.warmup)
+                        .speed(1.0)
+                        .update_while_editing(false),
+                );
+            });
+            collapsible(tui, "Newton Fractal", |tui| {
+                tui.small("Colors the root that Newton's method on a polynomial converges to from each point. Converged points use the internal coloring, and the Root Index and Convergence Speed layers color them.");
+                let was_enabled = self.output_settings.render_mode.newton().is_some();
+                let mut enabled = was_enabled;
+                tui.ui_add(egui::Checkbox::new(&mut enabled, "Render Newton Fractal"));
+                if enabled != was_enabled {
+                    if enabled {
+                        self.output_settings.render_mode =
+                            RenderMode::Newton(NewtonOptions::default());
+                        for viewport in [
+                            &mut self.explore_settings.viewport,
+                            &mut self.output_settings.viewport,
+                        ] {
+                            viewport.center = ComplexPoint::default();
+                            viewport.zoom = -1.0;
+                        }
+                    } else {
+                        self.output_settings.render_mode = RenderMode::Escape;
+                    }
+                }
+                let RenderMode::Newton(options) = &mut self.output_settings.render_mode else {
+                    return;
+                };
+                tui.ui_add(egui::Checkbox::new(&mut options.nova, "Nova"))
+                    .on_hover_text("Adds each point to every iteration, starting from the same point for all of them, instead of starting from each point.");
+                let coefficients = &mut options.coefficients;
+                for i in 0..coefficients.len() {
+                    let mut remove = false;
+                    ui_with_label(
+                        tui,
+                        &format!("z^{i}"),
+                        Some("The complex coefficient of this power of z in the polynomial."),
+                        |tui| {
+                            tui.ui_add(egui::DragValue::new(&mut coefficients[i][0]).speed(0.01));
+                            tui.ui_add(egui::DragValue::new(&mut coefficients[i][1]).speed(0.01));
+                            remove = i > 0
+                                && i == coefficients.len() - 1
+                                && tui
+                                    .ui_add(Button::new(icons::ICON_DELETE))
+                                    .on_hover_text("Delete")
+                                    .clicked();
+                        },
+                    );
+                    if remove {
+                        coefficients.pop();
+                    }
+                }
+                if tui
+                    .enabled_ui(coefficients.len() <= MAX_NEWTON_DEGREE)
+                    .ui_add(Button::new(format!("{} Add Term", icons::ICON_ADD)))
+                    .clicked()
+                {
+                    coefficients.push([0.0, 0.0]);
+                }
+                ui_with_label(
+                    tui,
+                    "Relaxation",
+                    Some("The complex factor R that scales each step of Newton's method."),
+                    |tui| {
+                        tui.ui_add(egui::DragValue::new(&mut options.relaxation[0]).speed(0.01));
+                        tui.ui_add(egui::DragValue::new(&mut options.relaxation[1]).speed(0.01));
+                    },
+                );
+                if options.nova {
+                    ui_with_label(
+                        tui,
+                        "Starting Point",
+                        Some("The point every iteration of the Nova fractal starts from. A critical point of the polynomial produces the classic Nova shapes."),
+                        |tui| {
+                            tui.ui_add(egui::DragValue::new(&mut options.start[0]).speed(0.01));
+                            tui.ui_add(egui::DragValue::new(&mut options.start[1]).speed(0.01));
+                        },
+                    );
+                }
+                input_with_label(
+                    tui,
+                    "Tolerance",
+                    Some("The distance between iterations below which a point has converged."),
+                    egui::DragValue::new(&mut options.tolerance)
+                        .speed(0.00001)
+                        .range(1e-6..=1.0),
+                );
+            });
             let mut scaling = (1.0 / self.explore_settings.viewport.scaling) as u32;
             input_with_label(
                 tui,
@@ -533,6 +879,56 @@ impl CorgiUI {
                     .speed(0.03)
                     .update_while_editing(false),
             );
+            input_with_label(
+                tui,
+                "Rotation",
+                Some(
+                    "Counterclockwise rotation of the camera around the image center. The view can also be rotated by dragging with the right mouse button.",
+                ),
+                egui::DragValue::new(&mut self.output_settings.viewport.angle)
+                    .speed(0.01)
+                    .custom_formatter(|angle, _| format!("{:.1}°", angle.to_degrees()))
+                    .custom_parser(|text| {
+                        text.trim_end_matches('°')
+                            .trim()
+                            .parse::<f64>()
+                            .ok()
+                            .map(f64::to_radians)
+                    })
+                    .update_while_editing(false),
+            );
+            ui_with_label(
+                tui,
+                "Skew",
+                Some(
+                    "Stretches the camera to undo the stretching of the fractal at the image center, which is common near embedded Julia sets of deep zooms and in the Burning Ship.",
+                ),
+                |tui| {
+                    let viewport = match self.view_state {
+                        ViewState::Viewport => &mut self.explore_settings.viewport,
+                        ViewState::OutputView | ViewState::OutputLock | ViewState::Output => {
+                            &mut self.output_settings.viewport
+                        }
+                    };
+                    if tui
+                        .ui_add(Button::new(format!("{} Unskew", icons::ICON_TRANSFORM)))
+                        .on_hover_text("Estimates the skew from the derivative of the orbit at the image center.")
+                        .clicked()
+                    {
+                        // the orbit is iterated at full precision, so it runs on the render thread
+                        self.status.message = "Estimating the skew...".to_string();
+                        let _ = self
+                            .command_channel
+                            .send(ImageGenCommand::EstimateSkew(img.clone()));
+                    }
+                    if tui
+                        .ui_add(Button::new(format!("{} Reset", icons::ICON_RESTART_ALT)))
+                        .clicked()
+                    {
+                        viewport.skew = None;
+                    }
+                },
+            );
             input_with_label(
                 tui,
                 "Max iteration",
@@ -544,6 +940,23 @@ impl CorgiUI {
                     .range(100..=u32::MAX)
                     .update_while_editing(false),
             );
+            tui.horizontal().add(|tui| {
+                tui.ui_add(egui::Checkbox::new(
+                    &mut self.output_settings.auto_max_iter,
+                    "Automatic max iteration",
+                ))
+                .on_hover_text("Adjusts the max iteration after each render, based on how many pixels reached it and how many iterations the escaped pixels needed.");
+                if let Some(max_iter) = self.suggested_max_iter
+                    && !self.output_settings.auto_max_iter
+                    && tui
+                        .ui_add(Button::new(format!("{} Use {max_iter}", icons::ICON_AUTO_FIX_HIGH)))
+                        .on_hover_text("The max iteration suggested by the last render.")
+                        .clicked()
+                {
+                    self.output_settings.max_iter = max_iter;
+                    self.suggested_max_iter = None;
+                }
+            });
             collapsible(tui, "Advanced", |tui| {
                 point_edit(
                     tui,
@@ -560,6 +973,31 @@ impl CorgiUI {
                 )))
                 .clicked()
                 .then(|| self.setting_probe = !self.setting_probe);
+                if tui
+                    .ui_add(Button::new(format!(
+                        "{} Find nearest nucleus",
+                        icons::ICON_MY_LOCATION
+                    )))
+                    .on_hover_text("Sets the probe point to the center of the lowest-period minibrot in view, which is usually the best reference.")
+                    .clicked()
+                {
+                    if img.fractal_kind.julia_point().is_none() && img.is_analytic() {
+                        // the search can take minutes at deep zooms, so it runs on the render thread
+                        self.status.message = "Searching for a nucleus...".to_string();
+                        let _ = self
+                            .command_channel
+                            .send(ImageGenCommand::FindNucleus(img.clone()));
+                    } else {
+                        self.apply_nucleus(None);
+                    }
+                }
+                // like the max iteration, this is kept in the output settings, which every
+                // image is built from, so it applies to the explore view as well
+                tui.ui_add(egui::Checkbox::new(
+                    &mut self.output_settings.auto_reference,
+                    "Automatic probe point",
+                ))
+                .on_hover_text("Automatically uses the nearest minibrot nucleus as the probe point whenever the view moves away from the previous one. This applies to both the explore view and the output.");
             });
         });
         section(tui, "Camera", false, |tui| {
@@ -579,6 +1017,10 @@ impl CorgiUI {
                                     self.explore_settings.viewport.center.clone();
                                 self.output_settings.viewport.zoom =
                                     self.explore_settings.viewport.zoom + 0.5;
+                                self.output_settings.viewport.angle =
+                                    self.explore_settings.viewport.angle;
+                                self.output_settings.viewport.skew =
+                                    self.explore_settings.viewport.skew;
                                 self.output_settings.update_probe();
                                 self.render_zoom_offset = -0.5;
                                 self.view_state = ViewState::OutputLock;
@@ -603,6 +1045,10 @@ impl CorgiUI {
                                 self.output_settings.viewport.center.clone();
                             self.explore_settings.viewport.zoom =
                                 self.output_settings.viewport.zoom + self.render_zoom_offset;
+                            self.explore_settings.viewport.angle =
+                                self.output_settings.viewport.angle;
+                            self.explore_settings.viewport.skew =
+                                self.output_settings.viewport.skew;
                             self.explore_settings.update_probe();
                             self.view_state = ViewState::OutputView;
                         }
@@ -678,20 +1124,16 @@ impl CorgiUI {
 
                 // render texture and camera overlay
                 let view_image = self.image();
-                let mut render_rect = rect.scale_from_center2(
-                    egui::Vec2::splat(1.0) / view_image.viewport.aspect_scale(),
-                );
-                let (x, y) = view_image.viewport.coords_to_px_offset(
-                    &self.output_settings.viewport.center.x,
-                    &self.output_settings.viewport.center.y,
-                );
-                render_rect = render_rect.translate(Vec2::new(x as f32, -y as f32));
-                render_rect = render_rect.scale_from_center(f32::powf(
-                    2.0,
-                    -(self.output_settings.viewport.zoom - view_image.viewport.zoom) as f32,
-                ));
-                render_rect =
-                    render_rect.scale_from_center2(self.output_settings.viewport.aspect_scale());
+                // the corners of the output image, which may be rotated relative to the view
+                let camera_corners = (self.tab != UITab::Render && self.show_camera).then(|| {
+                    let output = &self.output_settings.viewport;
+                    let (width, height) = (output.width as f64, output.height as f64);
+                    [(0.0, 0.0), (width, 0.0), (width, height), (0.0, height)].map(|(x, y)| {
+                        let (r, i) = output.get_real_coords(x, y);
+                        let (x, y) = view_image.viewport.coords_to_px_offset(&r, &i);
+                        rect.center() + Vec2::new(x as f32, -y as f32)
+                    })
+                });
                 let cb = PaintCallback {
                     rendered_viewport: if self.tab == UITab::Render {
                         self.rendered_output_viewport.clone()
@@ -707,13 +1149,11 @@ impl CorgiUI {
                 let callback = egui_wgpu::Callback::new_paint_callback(rect, cb);
 
                 ui.painter().add(callback);
-                if self.tab != UITab::Render && self.show_camera {
-                    ui.painter().rect_stroke(
-                        render_rect.intersect(rect),
-                        0.0,
+                if let Some(corners) = camera_corners {
+                    ui.painter_at(rect).add(egui::Shape::closed_line(
+                        corners.to_vec(),
                         Stroke::new(2.0, Color32::from_gray(255)),
-                        egui::StrokeKind::Outside,
-                    );
+                    ));
                 }
             },
         );
@@ -731,23 +1171,37 @@ impl CorgiUI {
         if !pointer_in_rect {
             scroll = Vec2::ZERO;
         }
-        let drag = response.drag_delta();
+        let mut drag = response.drag_delta();
+
+        // dragging with the secondary button rotates the view around its center
+        // by the angle swept by the pointer, instead of moving it
+        let mut rotation = 0.0;
+        if response.dragged_by(egui::PointerButton::Secondary) {
+            if let Some(pos) = response.interact_pointer_pos() {
+                let to = pos - response.rect.center();
+                let from = to - drag;
+                rotation = (from.x * to.y - from.y * to.x).atan2(from.dot(to)) as f64;
+            }
+            drag = Vec2::ZERO;
+        }
 
         // scroll
         let precision = get_precision(view_image.viewport.zoom);
         let mut scale = Float::with_val(precision, 2.0);
         scale.pow_assign(-view_image.viewport.zoom);
         let aspect_scale = view_image.viewport.aspect_scale();
-        let x_offset = -(drag.x as f64 / view_image.viewport.width as f64
-                            * aspect_scale.x as f64
-                            * pixel_scale as f64
-                            * 1.715) // TODO: why this value? and does this work on other screens?
-                            * scale.clone();
-        let y_offset = (drag.y as f64 / view_image.viewport.height as f64
-            * aspect_scale.y as f64
-            * pixel_scale as f64
-            * 1.715)
-            * scale;
+        let (x_offset, y_offset) = view_image.viewport.transform_offset(
+            -(drag.x as f64 / view_image.viewport.width as f64
+                * aspect_scale.x as f64
+                * pixel_scale as f64
+                * 1.715), // TODO: why this value? and does this work on other screens?
+            drag.y as f64 / view_image.viewport.height as f64
+                * aspect_scale.y as f64
+                * pixel_scale as f64
+                * 1.715,
+        );
+        let x_offset = x_offset * scale.clone();
+        let y_offset = y_offset * scale;
         match if self.tab == UITab::Render {
             ViewState::Output
         } else {
@@ -757,16 +1211,19 @@ impl CorgiUI {
                 self.explore_settings.viewport.center.x += x_offset;
                 self.explore_settings.viewport.center.y += y_offset;
                 self.explore_settings.viewport.zoom += scroll.y as f64 * pixel_scale as f64 * 0.005;
+                self.explore_settings.viewport.angle += rotation;
                 self.explore_settings.viewport.update_prec();
                 self.explore_settings.update_probe();
             }
             ViewState::OutputView => {
-                if drag.x != 0.0 || drag.y != 0.0 {
+                if drag.x != 0.0 || drag.y != 0.0 || rotation != 0.0 {
                     self.explore_settings.viewport.center.x =
                         self.output_settings.viewport.center.x.clone() + x_offset;
                     self.explore_settings.viewport.center.y =
                         self.output_settings.viewport.center.y.clone() + y_offset;
                     self.explore_settings.viewport.zoom = view_image.viewport.zoom;
+                    self.explore_settings.viewport.angle = view_image.viewport.angle + rotation;
+                    self.explore_settings.viewport.skew = view_image.viewport.skew;
                     self.explore_settings.viewport.update_prec();
                     self.explore_settings.update_probe();
                     self.view_state = ViewState::Viewport;
@@ -777,6 +1234,7 @@ impl CorgiUI {
                 self.output_settings.viewport.center.x += x_offset;
                 self.output_settings.viewport.center.y += y_offset;
                 self.output_settings.viewport.zoom += scroll.y as f64 * pixel_scale as f64 * 0.005;
+                self.output_settings.viewport.angle += rotation;
                 self.output_settings.viewport.update_prec();
                 self.output_settings.update_probe();
             }
@@ -784,6 +1242,7 @@ impl CorgiUI {
                 self.output_preview_viewport.center.x += x_offset;
                 self.output_preview_viewport.center.y += y_offset;
                 self.output_preview_viewport.zoom += scroll.y as f64 * pixel_scale as f64 * 0.005;
+                self.output_preview_viewport.angle += rotation;
                 self.output_preview_viewport.update_prec();
             }
         }
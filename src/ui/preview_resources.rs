@@ -169,12 +169,7 @@ impl SubResources {
 
         let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Preview Uniform Buffer"),
-            contents: bytemuck::cast_slice(&[Transform {
-                angle: 0.0,
-                _padding: 0.0,
-                scale: [1.0; 2],
-                offset: [0.0; 2],
-            }]),
+            contents: bytemuck::cast_slice(&[Transform::default()]),
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
         });
 
@@ -1,4 +1,4 @@
-use eframe::egui::DragValue;
+use eframe::egui::{Checkbox, DragValue};
 
 use crate::ui::EditUI;
 use crate::ui::utils::{color32_edit, input_with_label, ui_with_label};
@@ -13,6 +13,65 @@ impl EditUI for crate::Config {
             ),
             DragValue::new(&mut self.max_shader_batch_iters).speed(10),
         );
+        input_with_label(
+            tui,
+            "Max Glitch Correction References",
+            Some(
+                "The number of extra reference points used to correct glitched pixels in deep zooms. Set to 0 to disable glitch correction.\nRequires a restart after changing.",
+            ),
+            DragValue::new(&mut self.max_glitch_references).speed(0.1),
+        );
+        input_with_label(
+            tui,
+            "Reference Orbit Cache Size",
+            Some(
+                "The space in MiB used to store computed reference orbits, so that deep locations are not probed again. Set to 0 to disable the cache.\nRequires a restart after changing.",
+            ),
+            DragValue::new(&mut self.orbit_cache_size)
+                .speed(10)
+                .suffix(" MiB"),
+        );
+        let policy = &mut self.algorithm_policy;
+        input_with_label(
+            tui,
+            "Use f64 Shaders",
+            Some(
+                "Use double precision shaders between the f32 zoom limits and the f64 limit, if the GPU supports them. They are more accurate, but much slower on most GPUs.\nRequires a restart after changing.",
+            ),
+            Checkbox::without_text(&mut policy.use_f64),
+        );
+        input_with_label(
+            tui,
+            "Emulated Double Precision",
+            Some(
+                "Replace the perturbed f32 shader with a slower, more accurate shader that emulates double precision with pairs of f32s.\nRequires a restart after changing.",
+            ),
+            Checkbox::without_text(&mut policy.double_single),
+        );
+        input_with_label(
+            tui,
+            "Perturbation Zoom",
+            Some(
+                "The zoom at which direct calculation in f32 stops being accurate enough, and perturbation is used.\nRequires a restart after changing.",
+            ),
+            DragValue::new(&mut policy.perturbation_zoom).speed(0.1),
+        );
+        input_with_label(
+            tui,
+            "Perturbation Zoom (f64)",
+            Some(
+                "The zoom at which direct calculation in f64 stops being accurate enough, and perturbation is used.\nRequires a restart after changing.",
+            ),
+            DragValue::new(&mut policy.perturbation_zoom_f64).speed(0.1),
+        );
+        input_with_label(
+            tui,
+            "Max f64 Zoom",
+            Some(
+                "The zoom above which pixel offsets are too small for an f64, and the extended range f32 shader is used.\nRequires a restart after changing.",
+            ),
+            DragValue::new(&mut policy.max_zoom_f64).speed(1.0),
+        );
     }
 }
 
@@ -1,6 +1,6 @@
 use std::mem::{Discriminant, discriminant};
 
-use corgi::types::{ComplexPoint, FractalKind, Gradient, LayerKind, LightingKind};
+use corgi::types::{ComplexPoint, Formula, FractalKind, Gradient, LayerKind, LightingKind};
 use eframe::egui::{self, Color32, RichText, Sense, TextStyle, WidgetText};
 use egui_material_icons::icons;
 use egui_taffy::{Tui, TuiBuilderLogic, TuiWidget};
@@ -8,7 +8,7 @@ use rug::Float;
 use taffy::Overflow;
 use taffy::prelude::*;
 
-use super::coloring::{OrbitType, StripeType};
+use super::coloring::{MultiplierPart, OrbitType, StripeType};
 
 pub trait TuiExt {
     fn horizontal(&mut self) -> egui_taffy::TuiBuilder<'_>;
@@ -61,11 +61,27 @@ pub trait ToLabel {
     fn label(&self) -> &'static str;
 }
 
+impl ToLabel for Formula {
+    fn label(&self) -> &'static str {
+        self.formula().label()
+    }
+}
+
 impl ToLabel for FractalKind {
     fn label(&self) -> &'static str {
         match &self {
             FractalKind::Mandelbrot => "Mandelbrot",
             FractalKind::Julia(_) => "Julia",
+            FractalKind::Multibrot { .. } => "Multibrot",
+            FractalKind::MultibrotJulia { .. } => "Multibrot Julia",
+            FractalKind::BurningShip => "Burning Ship",
+            FractalKind::BurningShipJulia(_) => "Burning Ship Julia",
+            FractalKind::Mandelbar => "Mandelbar",
+            FractalKind::MandelbarJulia(_) => "Mandelbar Julia",
+            FractalKind::Celtic => "Celtic",
+            FractalKind::CelticJulia(_) => "Celtic Julia",
+            FractalKind::Perpendicular => "Perpendicular",
+            FractalKind::PerpendicularJulia(_) => "Perpendicular Julia",
         }
     }
 }
@@ -120,6 +136,16 @@ impl ToLabel for StripeType {
     }
 }
 
+impl ToLabel for MultiplierPart {
+    fn label(&self) -> &'static str {
+        match self.0 {
+            1 => "Magnitude",
+            2 => "Argument",
+            _ => unreachable!(),
+        }
+    }
+}
+
 impl ToLabel for LayerKind {
     fn label(&self) -> &'static str {
         match self {
@@ -129,6 +155,11 @@ impl ToLabel for LayerKind {
             LayerKind::Distance => "Distance",
             LayerKind::OrbitTrap => "Orbit Trap",
             LayerKind::Stripe => "Stripe Average",
+            LayerKind::Period => "Period",
+            LayerKind::Multiplier => "Multiplier",
+            LayerKind::Lyapunov => "Lyapunov Exponent",
+            LayerKind::RootIndex => "Root Index",
+            LayerKind::Convergence => "Convergence Speed",
         }
     }
 }
@@ -178,6 +209,16 @@ impl ToHelpText for StripeType {
     }
 }
 
+impl ToHelpText for MultiplierPart {
+    fn help_text(&self) -> &'static str {
+        match self.0 {
+            1 => "how strongly the cycle attracts, from 0 at its center to 1 at its edge",
+            2 => "the phase of the multiplier, which turns once around each component",
+            _ => unreachable!(),
+        }
+    }
+}
+
 impl ToHelpText for LightingKind {
     fn help_text(&self) -> &'static str {
         match self {
@@ -198,10 +239,32 @@ impl ToHelpText for FractalKind {
         match self {
             FractalKind::Mandelbrot => "",
             FractalKind::Julia(_) => "",
+            FractalKind::Multibrot { .. } => "The Mandelbrot set with z raised to a higher power.",
+            FractalKind::MultibrotJulia { .. } => "The Julia set with z raised to a higher power.",
+            FractalKind::BurningShip => {
+                "Takes the absolute value of both parts of z before squaring it."
+            }
+            FractalKind::Mandelbar => {
+                "Squares the complex conjugate of z. Also known as the Tricorn."
+            }
+            FractalKind::Celtic => "Takes the absolute value of the real part of z squared.",
+            FractalKind::Perpendicular => {
+                "Takes the absolute value of the real part of z before squaring its conjugate."
+            }
+            FractalKind::BurningShipJulia(_)
+            | FractalKind::MandelbarJulia(_)
+            | FractalKind::CelticJulia(_)
+            | FractalKind::PerpendicularJulia(_) => "",
         }
     }
 }
 
+impl ToHelpText for Formula {
+    fn help_text(&self) -> &'static str {
+        self.formula().description()
+    }
+}
+
 impl ToHelpText for LayerKind {
     fn help_text(&self) -> &'static str {
         match self {
@@ -213,12 +276,27 @@ impl ToHelpText for LayerKind {
                 "A version of step without hard lines. Logarithmic instead of linear."
             }
             LayerKind::Distance => {
-                "Distance estimation to the edge of the set. Scales depending on the zoom level."
+                "Distance estimation to the edge of the set. Scales depending on the zoom level. Inside the Mandelbrot set and Multibrots, this is estimated from the attracting cycle of each point."
             }
             LayerKind::OrbitTrap => {
                 "Draws copies of a given shape in repeated patterns around critical points in the set."
             }
             LayerKind::Stripe => "Draws effects radiating from the edges of the fractal.",
+            LayerKind::Period => {
+                "The period of the attracting cycle inside the set, which is the same across each component. Only affects internal coloring."
+            }
+            LayerKind::Multiplier => {
+                "The multiplier of the attracting cycle inside the set. Only affects internal coloring, and is only meaningful for the Mandelbrot set and Multibrots."
+            }
+            LayerKind::Lyapunov => {
+                "The Lyapunov exponent of the logistic map sequence. Only meaningful in Lyapunov mode, where stable points (with negative exponents) use the internal coloring."
+            }
+            LayerKind::RootIndex => {
+                "The root each point converges to, spread over one cycle of the gradient. Only meaningful in Newton mode, where converged points use the internal coloring."
+            }
+            LayerKind::Convergence => {
+                "The smooth number of iterations each point takes to converge. Only meaningful in Newton mode, where converged points use the internal coloring."
+            }
         }
     }
 }
@@ -642,7 +720,6 @@ pub fn pseudo_color_edit(tui: &mut egui_taffy::Tui, color: &mut [f32; 3]) {
     });
 }
 
-#[expect(dead_code)]
 pub fn text_edit_with_label(
     tui: &mut Tui,
     label: &str,
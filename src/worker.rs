@@ -1,21 +1,25 @@
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, mpsc};
 use std::time::Instant;
 
-use corgi::image_gen::{GPUData, SharedState, render_image, save_to_file};
-use corgi::types::{Image, ImageGenCommand, StatusMessage};
+use corgi::image_gen::{
+    GPUData, ReferenceOrbit, SharedState, estimate_skew, find_nucleus, render_image, save_to_file,
+};
+use corgi::types::{CancelFlags, Image, ImageGenCommand, StatusMessage};
 use eframe::egui::mutex::RwLock;
 use eframe::{egui, egui_wgpu, wgpu};
 
 pub struct WorkerState {
     preview_state: GPUData,
     output_state: GPUData,
-    probe_buffer: Vec<[f32; 2]>,
+    // each render keeps its own reference orbit, which a higher max iteration extends
+    preview_probe: ReferenceOrbit,
+    output_probe: ReferenceOrbit,
     preview_settings: Option<Image>,
     output_settings: Option<Image>,
     command_channel: mpsc::Receiver<ImageGenCommand>,
     status_channel: mpsc::Sender<StatusMessage>,
-    cancelled: Arc<AtomicBool>,
+    cancel_flags: CancelFlags,
     ctx: egui::Context,
 }
 
@@ -28,7 +32,7 @@ impl WorkerState {
         output_settings: Image,
         recv: mpsc::Receiver<ImageGenCommand>,
         send: mpsc::Sender<StatusMessage>,
-        cancelled: Arc<AtomicBool>,
+        cancel_flags: CancelFlags,
         ctx: egui::Context,
         context: &crate::Context,
     ) -> Self {
@@ -42,6 +46,9 @@ impl WorkerState {
                 "Preview",
                 corgi::image_gen::Constants {
                     iter_batch_size: context.config().max_shader_batch_iters,
+                    max_glitch_references: context.config().max_glitch_references,
+                    algorithm_policy: context.config().algorithm_policy.clone(),
+                    orbit_cache: context.config().orbit_cache(),
                 },
             ),
             output_state: GPUData::init(
@@ -51,14 +58,18 @@ impl WorkerState {
                 "Output",
                 corgi::image_gen::Constants {
                     iter_batch_size: context.config().max_shader_batch_iters,
+                    max_glitch_references: context.config().max_glitch_references,
+                    algorithm_policy: context.config().algorithm_policy.clone(),
+                    orbit_cache: context.config().orbit_cache(),
                 },
             ),
-            probe_buffer: vec![],
+            preview_probe: ReferenceOrbit::default(),
+            output_probe: ReferenceOrbit::default(),
             preview_settings: None,
             output_settings: None,
             command_channel: recv,
             status_channel: send,
-            cancelled,
+            cancel_flags,
             ctx,
         }
     }
@@ -66,56 +77,125 @@ impl WorkerState {
     /// Main entry point for the image generation process. This should be called in a separate thread,
     /// and will run until the given message channel is closed. `status` is used to communicate the
     /// current status of the render process to the main thread.
+    ///
+    /// A cancelled render is not displayed, and the next render of its kind starts from scratch.
     pub fn run(&mut self) {
+        let mut previews_received = 0;
+        let mut outputs_received = 0;
         while let Ok(msg) = self.command_channel.recv() {
             let mut new_preview = None;
             let mut new_output = None;
             let mut file_save = None;
+            let mut nucleus_search = None;
+            let mut skew_estimate = None;
             match msg {
                 ImageGenCommand::NewPreviewSettings(image) => {
+                    previews_received += 1;
                     new_preview = Some(image);
                 }
                 ImageGenCommand::NewOutputSettings(image) => {
+                    outputs_received += 1;
                     new_output = Some(image);
                 }
                 ImageGenCommand::SaveToFile(path) => {
                     file_save = Some(path);
                 }
+                ImageGenCommand::FindNucleus(image) => {
+                    nucleus_search = Some(image);
+                }
+                ImageGenCommand::EstimateSkew(image) => {
+                    skew_estimate = Some(image);
+                }
             }
             loop {
                 let next = self.command_channel.try_recv();
                 match next {
                     Ok(ImageGenCommand::NewPreviewSettings(image)) => {
+                        previews_received += 1;
                         new_preview = Some(image);
                     }
                     Ok(ImageGenCommand::NewOutputSettings(image)) => {
+                        outputs_received += 1;
                         new_output = Some(image);
                     }
                     Ok(ImageGenCommand::SaveToFile(path)) => {
                         file_save = Some(path);
                     }
+                    Ok(ImageGenCommand::FindNucleus(image)) => {
+                        nucleus_search = Some(image);
+                    }
+                    Ok(ImageGenCommand::EstimateSkew(image)) => {
+                        skew_estimate = Some(image);
+                    }
                     Err(mpsc::TryRecvError::Empty) => break,
                     Err(mpsc::TryRecvError::Disconnected) => return,
                 }
             }
+            // the flags were set for the renders before these commands, unless newer settings
+            // were sent after the queue was emptied, which replace the renders of these ones
+            self.cancel_flags.reset(previews_received, outputs_received);
+            // searches requested from the UI are run first, since the user is waiting for them
+            if let Some(image) = nucleus_search {
+                let _ = self.status_channel.send(StatusMessage::Progress(
+                    "Searching for a nucleus".into(),
+                    0.0,
+                ));
+                self.ctx.request_repaint();
+                let nucleus = find_nucleus(
+                    &image.viewport,
+                    image.max_iter,
+                    image.fractal_kind.power(),
+                    &self.cancel_flags.search,
+                );
+                let message = if self.cancel_flags.search.load(Ordering::Relaxed) {
+                    StatusMessage::Cancelled
+                } else {
+                    StatusMessage::NucleusFound(nucleus)
+                };
+                let _ = self.status_channel.send(message);
+                self.ctx.request_repaint();
+            }
+            if let Some(image) = skew_estimate {
+                let _ = self
+                    .status_channel
+                    .send(StatusMessage::Progress("Estimating the skew".into(), 0.0));
+                self.ctx.request_repaint();
+                let skew = estimate_skew(&image, &self.cancel_flags.search);
+                let message = if self.cancel_flags.search.load(Ordering::Relaxed) {
+                    StatusMessage::Cancelled
+                } else {
+                    StatusMessage::SkewEstimated(skew)
+                };
+                let _ = self.status_channel.send(message);
+                self.ctx.request_repaint();
+            }
             if let Some(image) = new_preview {
                 let start = Instant::now();
-                render_image(
+                let stats = render_image(
                     &mut self.preview_state,
-                    &mut self.probe_buffer,
+                    &mut self.preview_probe,
                     &image,
                     self.preview_settings.as_ref(),
-                    self.cancelled.clone(), // TODO: should this be the same cancel??
+                    self.cancel_flags.preview.clone(),
                     |sm| {
                         let _ = self.status_channel.send(sm);
                         self.ctx.request_repaint();
                     },
                 );
-                let _ = self.status_channel.send(StatusMessage::NewPreviewViewport(
-                    Instant::now() - start,
-                    image.viewport.clone(),
-                ));
-                self.preview_settings = Some(image);
+                if self.cancel_flags.preview.load(Ordering::Relaxed) {
+                    self.preview_settings = None;
+                } else {
+                    let _ = self.status_channel.send(StatusMessage::NewPreviewViewport(
+                        Instant::now() - start,
+                        image.viewport.clone(),
+                    ));
+                    if let Some(stats) = stats {
+                        let _ = self
+                            .status_channel
+                            .send(StatusMessage::IterationStats(stats));
+                    }
+                    self.preview_settings = Some(image);
+                }
                 self.ctx.request_repaint();
             }
             if let Some(image) = new_output {
@@ -123,20 +203,24 @@ impl WorkerState {
                 // TODO: move to separate thread
                 render_image(
                     &mut self.output_state,
-                    &mut self.probe_buffer,
+                    &mut self.output_probe,
                     &image,
                     self.output_settings.as_ref(),
-                    self.cancelled.clone(),
+                    self.cancel_flags.output.clone(),
                     |sm| {
                         let _ = self.status_channel.send(sm);
                         self.ctx.request_repaint();
                     },
                 );
-                let _ = self.status_channel.send(StatusMessage::NewOutputViewport(
-                    Instant::now() - start,
-                    image.viewport.clone(),
-                ));
-                self.output_settings = Some(image);
+                if self.cancel_flags.output.load(Ordering::Relaxed) {
+                    self.output_settings = None;
+                } else {
+                    let _ = self.status_channel.send(StatusMessage::NewOutputViewport(
+                        Instant::now() - start,
+                        image.viewport.clone(),
+                    ));
+                    self.output_settings = Some(image);
+                }
                 self.ctx.request_repaint();
             }
             if let Some(path) = file_save